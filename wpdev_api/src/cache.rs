@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use wpdev_core::docker::instance::Instance;
+
+/// Short-lived cache of `inspect_all` results keyed by namespace, so bursts of dashboard
+/// requests share one round of Docker queries. Routes that change instances invalidate it.
+pub(crate) struct InstanceCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Vec<Instance>)>>,
+}
+
+impl InstanceCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        InstanceCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) async fn get(&self, namespace: &str) -> Option<Vec<Instance>> {
+        let entries = self.entries.lock().await;
+        entries
+            .get(namespace)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, instances)| instances.clone())
+    }
+
+    pub(crate) async fn insert(&self, namespace: &str, instances: &[Instance]) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries
+            .lock()
+            .await
+            .insert(namespace.to_string(), (Instant::now(), instances.to_vec()));
+    }
+
+    pub(crate) async fn invalidate(&self) {
+        self.entries.lock().await.clear();
+    }
+}
@@ -1,12 +1,29 @@
 #[macro_use]
 extern crate rocket;
+use bollard::Docker;
+use log::{error, info};
 use rocket::http::Method;
 use rocket_cors::{AllowedOrigins, Cors, CorsOptions};
+use std::net::SocketAddr;
+use std::time::Duration;
+use wpdev_core::docker::instance::Instance;
+use wpdev_core::{config, metrics, utils, AppConfig};
 
+mod cache;
 mod routes;
 
-fn cors() -> Cors {
-    let allowed_origins = AllowedOrigins::all();
+/// Only the web app may make credentialed requests, since responses can carry passwords
+/// (`/credentials`, `?show_secrets=true`). A loopback web app is also reachable as
+/// `localhost`.
+fn cors(config: &AppConfig) -> Cors {
+    let mut origins = vec![format!(
+        "http://{}",
+        SocketAddr::new(config.web_app_ip, config.web_app_port)
+    )];
+    if config.web_app_ip.is_loopback() {
+        origins.push(format!("http://localhost:{}", config.web_app_port));
+    }
+    let allowed_origins = AllowedOrigins::some_exact(&origins);
 
     CorsOptions {
         allowed_origins,
@@ -22,9 +39,73 @@ fn cors() -> Cors {
     .expect("Failed to create CORS middleware")
 }
 
+/// Reconciles the configured namespace every `interval`, logging what's found. Instances whose
+/// containers are gone only have their metadata moved aside, and only with `fix`.
+fn spawn_reconciler(docker: Docker, interval: Duration, fix: bool) {
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let result = async {
+                let namespace = config::read_or_create_config().await?.namespace;
+                Instance::reconcile(&docker, &namespace, fix).await
+            }
+            .await;
+            match result {
+                Ok(discrepancies) if discrepancies.is_empty() => info!("Reconciled, no drift"),
+                Ok(discrepancies) => info!("Reconciled, {} discrepancies", discrepancies.len()),
+                Err(e) => error!("Failed to reconcile instances: {:#}", e),
+            }
+        }
+    });
+}
+
 #[launch]
-fn rocket() -> _ {
-    rocket::build()
-        .attach(cors())
-        .mount("/api", routes::routes())
+async fn rocket() -> _ {
+    let config = match config::read_or_create_config().await {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to read config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = utils::ensure_port_available(config.api_ip, config.api_port, "api_port") {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    // One client is shared by every request, bollard pools the connections behind it.
+    let docker = match wpdev_core::docker::connect_docker_host(config.docker_host.as_deref()) {
+        Ok(docker) => docker,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let figment = rocket::Config::figment()
+        .merge(("address", config.api_ip))
+        .merge(("port", config.api_port));
+    if config.reconcile_interval_secs > 0 {
+        spawn_reconciler(
+            docker.clone(),
+            Duration::from_secs(config.reconcile_interval_secs),
+            config.reconcile_fix,
+        );
+    }
+
+    let rocket = rocket::custom(figment)
+        .manage(docker)
+        .manage(cache::InstanceCache::new(Duration::from_millis(
+            config.api_cache_ttl_ms,
+        )))
+        .attach(cors(&config))
+        .mount("/api", routes::routes());
+    if config.enable_metrics {
+        rocket
+            .manage(metrics::registry().clone())
+            .mount("/", routes![routes::prometheus_metrics])
+    } else {
+        rocket
+    }
 }
@@ -1,250 +1,688 @@
 use bollard::Docker;
 use log::error;
 /// External dependencies
+use prometheus::{Encoder, Registry, TextEncoder};
+use rocket::futures::stream::{self, Stream, StreamExt};
 use rocket::get;
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
 use rocket::response::status::Custom;
+use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
+use rocket::State;
 use serde_json;
 use uuid::Uuid;
 
 /// Internal dependencies
+use crate::cache::InstanceCache;
+use std::collections::HashMap;
+use wpdev_core::config;
 use wpdev_core::docker::container::{ContainerEnvVars, InstanceContainer};
-use wpdev_core::docker::instance::Instance;
+use wpdev_core::docker::events::{watch_events, InstanceEvent, InstanceEventKind};
+use wpdev_core::docker::instance::{Credentials, Instance, InstanceStats};
+use wpdev_core::error::WpdevError;
+use wpdev_core::{metrics, utils};
 
-#[post("/instances/create", data = "<env_vars>")]
+/// Error response of the routes that look up a single instance or container. A missing
+/// one is a 404 with a JSON body, anything else is a 500 with the error message.
+#[derive(Responder)]
+pub(crate) enum LookupError {
+    #[response(status = 404)]
+    NotFound(Json<serde_json::Value>),
+    #[response(status = 500)]
+    Internal(String),
+}
+
+impl From<anyhow::Error> for LookupError {
+    fn from(e: anyhow::Error) -> Self {
+        if WpdevError::is_not_found(&e) {
+            LookupError::NotFound(Json(serde_json::json!({
+                "error": "not_found",
+                "message": e.to_string(),
+            })))
+        } else {
+            LookupError::Internal(e.to_string())
+        }
+    }
+}
+
+/// Redacts the passwords of `instances` unless the client asked for them with
+/// `?show_secrets=true`.
+fn redact_unless_requested(instances: &mut [Instance], show_secrets: Option<bool>) {
+    if !show_secrets.unwrap_or(false) {
+        instances.iter_mut().for_each(Instance::redact_secrets);
+    }
+}
+
+#[post("/instances/create?<show_secrets>", data = "<env_vars>")]
 pub(crate) async fn create_instance(
+    docker: &State<Docker>,
     env_vars: Option<Json<ContainerEnvVars>>,
+    show_secrets: Option<bool>,
+    cache: &State<InstanceCache>,
 ) -> Result<Json<Instance>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
     let uuid = Uuid::new_v4().to_string();
 
     let default_env_vars = ContainerEnvVars::default();
 
     let env_vars = env_vars.map_or(default_env_vars, |json| json.into_inner());
+    let import_dump = env_vars.import_dump.clone();
+    let import_site_url = env_vars.import_site_url.clone();
 
-    match Instance::new(&docker, &uuid, env_vars).await {
-        Ok(instance) => Ok(Json(instance)),
+    // Importing a dump needs the containers running.
+    let result = match Instance::new(&docker, &uuid, env_vars, import_dump.is_some()).await {
+        Ok(mut instance) => {
+            if let Some(dump) = &import_dump {
+                let imported = Instance::import_sql_dump(
+                    &docker,
+                    &instance.uuid,
+                    dump,
+                    import_site_url.as_deref(),
+                )
+                .await;
+                if let Err(e) = imported {
+                    cache.invalidate().await;
+                    return Err(Custom(
+                        Status::InternalServerError,
+                        format!(
+                            "Instance {} was created but importing {:?} failed: {:#}",
+                            instance.uuid, dump, e
+                        ),
+                    ));
+                }
+            }
+            redact_unless_requested(std::slice::from_mut(&mut instance), show_secrets);
+            Ok(Json(instance))
+        }
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+    };
+    cache.invalidate().await;
+    result
+}
+
+/// The ID of the instance a route was given, which may also be the instance's name.
+async fn resolve_instance(instance_uuid: &str) -> Result<String, LookupError> {
+    let namespace = config::read_or_create_config().await?.namespace;
+    Ok(Instance::resolve_id(&namespace, instance_uuid).await?)
+}
+
+/// Clones a stopped instance, files and database included, into a new started instance.
+#[post("/instances/<instance_uuid>/clone?<show_secrets>")]
+pub(crate) async fn clone_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    show_secrets: Option<bool>,
+    cache: &State<InstanceCache>,
+) -> Result<Json<Instance>, LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let uuid = Uuid::new_v4().to_string();
+    let result = match Instance::duplicate(&docker, instance_uuid, &uuid).await {
+        Ok(mut instance) => {
+            redact_unless_requested(std::slice::from_mut(&mut instance), show_secrets);
+            Ok(Json(instance))
+        }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
-#[get("/instances/<instance_uuid>/inspect")]
+#[get("/instances/<instance_uuid>/inspect?<show_secrets>")]
 pub(crate) async fn inspect_instance(
+    docker: &State<Docker>,
     instance_uuid: &str,
-) -> Result<Json<Instance>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    show_secrets: Option<bool>,
+) -> Result<Json<Instance>, LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
     match Instance::inspect(&docker, instance_uuid).await {
-        Ok(instance) => Ok(Json(instance)),
+        Ok(mut instance) => {
+            redact_unless_requested(std::slice::from_mut(&mut instance), show_secrets);
+            Ok(Json(instance))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// CPU and memory usage of the instance's running containers, summed and per container.
+#[get("/instances/<instance_uuid>/stats")]
+pub(crate) async fn instance_stats(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+) -> Result<Json<InstanceStats>, LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    match Instance::stats(&docker, instance_uuid).await {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[get("/instances/<instance_uuid>/credentials")]
+pub(crate) async fn instance_credentials(
+    instance_uuid: &str,
+) -> Result<Json<Credentials>, LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    match Instance::credentials(instance_uuid).await {
+        Ok(credentials) => Ok(Json(credentials)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Instances whose containers carry every `label` (`key=value`, repeatable), e.g.
+/// `/instances?label=client=acme`.
+#[get("/instances?<label>&<namespace>&<show_secrets>")]
+pub(crate) async fn list_instances_by_label(
+    docker: &State<Docker>,
+    label: Vec<&str>,
+    namespace: Option<&str>,
+    show_secrets: Option<bool>,
+) -> Result<Json<Vec<Instance>>, Custom<String>> {
+    let labels = label
+        .into_iter()
+        .map(utils::parse_label)
+        .collect::<anyhow::Result<HashMap<_, _>>>()
+        .map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    let namespace = match namespace {
+        Some(namespace) => namespace.to_string(),
+        None => {
+            config::read_or_create_config()
+                .await
+                .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+                .namespace
+        }
+    };
+    match Instance::list_all_with_labels(&docker, &namespace, &labels).await {
+        Ok(instances) => {
+            let mut instances: Vec<Instance> = instances.into_values().collect();
+            redact_unless_requested(&mut instances, show_secrets);
+            Ok(Json(instances))
+        }
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
     }
 }
 
-#[get("/instances/inspect_all")]
-pub(crate) async fn inspect_all_instances() -> Result<Json<Vec<Instance>>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instance) => Ok(Json(instance)),
+#[get("/instances/inspect_all?<namespace>&<fresh>&<show_secrets>")]
+pub(crate) async fn inspect_all_instances(
+    docker: &State<Docker>,
+    namespace: Option<&str>,
+    fresh: Option<bool>,
+    show_secrets: Option<bool>,
+    cache: &State<InstanceCache>,
+) -> Result<Json<Vec<Instance>>, Custom<String>> {
+    let namespace = match namespace {
+        Some(namespace) => namespace.to_string(),
+        None => {
+            config::read_or_create_config()
+                .await
+                .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+                .namespace
+        }
+    };
+    if !fresh.unwrap_or(false) {
+        if let Some(mut instances) = cache.get(&namespace).await {
+            redact_unless_requested(&mut instances, show_secrets);
+            return Ok(Json(instances));
+        }
+    }
+    match Instance::inspect_all(&docker, &namespace).await {
+        Ok(mut instances) => {
+            cache.insert(&namespace, &instances).await;
+            redact_unless_requested(&mut instances, show_secrets);
+            Ok(Json(instances))
+        }
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
     }
 }
 
 #[post("/instances/<instance_uuid>/start")]
-pub(crate) async fn start_instance(instance_uuid: &str) -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::start(&docker, instance_uuid).await {
+pub(crate) async fn start_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::start(&docker, instance_uuid).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/instances/<instance_uuid>/stop")]
-pub(crate) async fn stop_instance(instance_uuid: &str) -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::stop(&docker, instance_uuid).await {
+pub(crate) async fn stop_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::stop(&docker, instance_uuid).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/instances/<instance_uuid>/restart")]
-pub(crate) async fn restart_instance(instance_uuid: &str) -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::restart(&docker, instance_uuid).await {
+pub(crate) async fn restart_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::restart(&docker, instance_uuid).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
+}
+
+#[post("/instances/<instance_uuid>/pause")]
+pub(crate) async fn pause_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::pause(&docker, instance_uuid).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
+}
+
+#[post("/instances/<instance_uuid>/unpause")]
+pub(crate) async fn unpause_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::unpause(&docker, instance_uuid).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/instances/start_all")]
-pub(crate) async fn start_all_instances() -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::start_all(&docker, wpdev_core::NETWORK_NAME).await {
+pub(crate) async fn start_all_instances(
+    docker: &State<Docker>,
+    cache: &State<InstanceCache>,
+) -> Result<(), Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let result = match Instance::start_all(&docker, &namespace).await {
         Ok(_) => Ok(()),
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/instances/stop_all")]
-pub(crate) async fn stop_all_instances() -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::stop_all(&docker, wpdev_core::NETWORK_NAME).await {
+pub(crate) async fn stop_all_instances(
+    docker: &State<Docker>,
+    cache: &State<InstanceCache>,
+) -> Result<(), Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let result = match Instance::stop_all(&docker, &namespace).await {
         Ok(_) => Ok(()),
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/instances/restart_all")]
-pub(crate) async fn restart_all_instances() -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::restart_all(&docker, wpdev_core::NETWORK_NAME).await {
+pub(crate) async fn restart_all_instances(
+    docker: &State<Docker>,
+    cache: &State<InstanceCache>,
+) -> Result<(), Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let result = match Instance::restart_all(&docker, &namespace).await {
         Ok(_) => Ok(()),
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[delete("/instances/<instance_uuid>/delete")]
-pub(crate) async fn delete_instance(instance_uuid: &str) -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::delete(&docker, &instance_uuid, false).await {
+pub(crate) async fn delete_instance(
+    docker: &State<Docker>,
+    instance_uuid: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let instance_uuid = &resolve_instance(instance_uuid).await?;
+    let result = match Instance::delete(&docker, &instance_uuid, true).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[delete("/instances/purge")]
-pub(crate) async fn delete_all_instances() -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match Instance::delete_all(&docker, wpdev_core::NETWORK_NAME).await {
+pub(crate) async fn delete_all_instances(
+    docker: &State<Docker>,
+    cache: &State<InstanceCache>,
+) -> Result<(), Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let result = match Instance::delete_all(&docker, &namespace).await {
         Ok(_) => Ok(()),
         Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[get("/containers/<container_id>/inspect")]
 pub(crate) async fn inspect_container(
+    docker: &State<Docker>,
     container_id: &str,
-) -> Result<Json<InstanceContainer>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+) -> Result<Json<InstanceContainer>, LookupError> {
     match InstanceContainer::inspect(&docker, container_id).await {
         Ok(container) => Ok(Json(container)),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 #[post("/containers/<container_id>/start")]
 pub(crate) async fn start_container(
+    docker: &State<Docker>,
     container_id: &str,
-) -> Result<Json<InstanceContainer>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match InstanceContainer::start(&docker, container_id).await {
+    cache: &State<InstanceCache>,
+) -> Result<Json<InstanceContainer>, LookupError> {
+    let result = match InstanceContainer::start(&docker, container_id).await {
         Ok(container) => Ok(Json(container)),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/containers/<container_id>/stop")]
 pub(crate) async fn stop_container(
+    docker: &State<Docker>,
     container_id: &str,
-) -> Result<Json<InstanceContainer>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match InstanceContainer::stop(&docker, container_id).await {
+    cache: &State<InstanceCache>,
+) -> Result<Json<InstanceContainer>, LookupError> {
+    let result = match InstanceContainer::stop(&docker, container_id).await {
         Ok(container) => Ok(Json(container)),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[post("/containers/<container_id>/restart")]
 pub(crate) async fn restart_container(
+    docker: &State<Docker>,
     container_id: &str,
-) -> Result<Json<InstanceContainer>, Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match InstanceContainer::restart(&docker, container_id).await {
+    cache: &State<InstanceCache>,
+) -> Result<Json<InstanceContainer>, LookupError> {
+    let result = match InstanceContainer::restart(&docker, container_id).await {
         Ok(container) => Ok(Json(container)),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
 }
 
 #[delete("/containers/<container_id>/delete")]
-pub(crate) async fn delete_container(container_id: &str) -> Result<(), Custom<String>> {
-    let docker = Docker::connect_with_defaults()
-        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-    match InstanceContainer::delete(&docker, container_id).await {
+pub(crate) async fn delete_container(
+    docker: &State<Docker>,
+    container_id: &str,
+    cache: &State<InstanceCache>,
+) -> Result<(), LookupError> {
+    let result = match InstanceContainer::delete(&docker, container_id).await {
         Ok(_) => Ok(()),
-        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
-    }
+        Err(e) => Err(e.into()),
+    };
+    cache.invalidate().await;
+    result
+}
+
+/// What a WebSocket route waits on: a message from the client, a Docker event, or the client
+/// going away.
+enum WsInput {
+    Message(Result<ws::Message, ws::result::Error>),
+    Event(anyhow::Result<InstanceEvent>),
+    Closed,
+}
+
+/// Merges the client's messages with `events`, ending with `WsInput::Closed` once the client
+/// is gone, since the event stream itself never ends.
+fn ws_inputs(
+    messages: impl Stream<Item = Result<ws::Message, ws::result::Error>>,
+    events: impl Stream<Item = anyhow::Result<InstanceEvent>>,
+) -> impl Stream<Item = WsInput> {
+    let messages = messages
+        .map(WsInput::Message)
+        .chain(stream::once(async { WsInput::Closed }));
+    stream::select(messages, events.map(WsInput::Event))
+}
+
+fn ws_error(e: impl ToString) -> ws::result::Error {
+    ws::result::Error::Io(std::io::Error::other(e.to_string()))
+}
+
+/// `instance` with its secrets redacted, as a text message.
+fn instance_message(mut instance: Instance) -> Result<ws::Message, ws::result::Error> {
+    instance.redact_secrets();
+    serde_json::to_string(&instance)
+        .map(ws::Message::Text)
+        .map_err(|e| {
+            error!("Error serializing instance: {}", e);
+            ws_error(e)
+        })
 }
 
+/// WebSocket of the namespace's instances. `request_inspect` is answered with all of them, and
+/// whenever one of their containers changes state the updated instance is pushed.
 #[get("/instances/ws")]
-pub(crate) fn inspect_instance_ws(ws: ws::WebSocket) -> ws::Stream!['static] {
+pub(crate) fn inspect_instance_ws(
+    ws: ws::WebSocket,
+    docker: &State<Docker>,
+) -> ws::Stream!['static] {
+    let docker = docker.inner().clone();
     ws::Stream! { ws =>
 
-        let docker = Docker::connect_with_defaults().map_err(|e| {
-            error!("Error connecting to Docker: {}", e);
-            ws::result::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-        })?;
-        for await message in ws {
-            match message {
-                Ok(ws::Message::Text(text)) => {
-                    if text == "request_inspect" {
-                        match Instance::inspect_all(
-                            &docker,
-                            wpdev_core::NETWORK_NAME,
-                        ).await {
-                            Ok(instances) => {
-                                let response = serde_json::to_string(&instances).map_err(|e| {
-                                    error!("Error serializing instance inspection response: {}", e);
-                                    ws::result::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-                                })?;
-                                yield ws::Message::Text(response);
-                            },
-                            Err(e) => {
-                                error!("Error during instance inspection: {}", e);
-                                let error = serde_json::to_string(&e.to_string()).map_err(|e| {
-                                    error!("Error serializing instance inspection error: {}", e);
-                                    ws::result::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-                                })?;
-                                yield ws::Message::Text(error);
-                            }
+        let namespace = config::read_or_create_config().await.map_err(|e| {
+            error!("Error reading config: {}", e);
+            ws_error(e)
+        })?.namespace;
+        for await input in ws_inputs(ws, watch_events(&docker, &namespace)) {
+            match input {
+                WsInput::Message(Ok(ws::Message::Text(text))) if text == "request_inspect" => {
+                    match Instance::inspect_all(
+                        &docker,
+                        &namespace,
+                    ).await {
+                        Ok(mut instances) => {
+                            redact_unless_requested(&mut instances, None);
+                            let response = serde_json::to_string(&instances).map_err(|e| {
+                                error!("Error serializing instance inspection response: {}", e);
+                                ws_error(e)
+                            })?;
+                            yield ws::Message::Text(response);
+                        },
+                        Err(e) => {
+                            error!("Error during instance inspection: {}", e);
+                            let error = serde_json::to_string(&e.to_string()).map_err(|e| {
+                                error!("Error serializing instance inspection error: {}", e);
+                                ws_error(e)
+                            })?;
+                            yield ws::Message::Text(error);
                         }
                     }
                 },
-                Err(e) => {
+                WsInput::Message(Err(e)) => {
                     error!("WebSocket error: {}", e);
-                    ws::result::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e));
+                    break;
                 },
-                _ => {
+                WsInput::Message(Ok(ws::Message::Close(_))) | WsInput::Closed => break,
+                WsInput::Message(_) => {},
+                WsInput::Event(Ok(event)) => {
+                    match Instance::inspect(&docker, &event.instance_id).await {
+                        Ok(instance) => yield instance_message(instance)?,
+                        // The instance was deleted along with its containers.
+                        Err(_) if matches!(event.kind, InstanceEventKind::Removed) => {},
+                        Err(e) => error!("Error inspecting instance {}: {}", event.instance_id, e),
+                    }
+                },
+                WsInput::Event(Err(e)) => {
+                    error!("Error reading Docker events: {}", e);
+                    break;
+                },
+            }
+        }
+    }
+}
+
+/// WebSocket of one instance. It's sent when the socket opens, on `request_inspect` and whenever
+/// one of its containers changes state. The socket is closed once the instance is deleted.
+#[get("/instances/<instance_uuid>/ws")]
+pub(crate) fn instance_ws(
+    ws: ws::WebSocket,
+    docker: &State<Docker>,
+    instance_uuid: &str,
+) -> ws::Stream!['static] {
+    let docker = docker.inner().clone();
+    let instance_uuid = instance_uuid.to_string();
+    ws::Stream! { ws =>
+
+        let namespace = config::read_or_create_config().await.map_err(|e| {
+            error!("Error reading config: {}", e);
+            ws_error(e)
+        })?.namespace;
+        let instance_uuid = Instance::resolve_id(&namespace, &instance_uuid).await.map_err(ws_error)?;
+        let found = match Instance::inspect(&docker, &instance_uuid).await {
+            Ok(instance) => {
+                yield instance_message(instance)?;
+                true
+            },
+            Err(e) => {
+                error!("Error inspecting instance {}: {}", instance_uuid, e);
+                let error = serde_json::json!({
+                    "error": "not_found",
+                    "message": e.to_string(),
+                });
+                yield ws::Message::Text(error.to_string());
+                false
+            },
+        };
+        if found {
+            for await input in ws_inputs(ws, watch_events(&docker, &namespace)) {
+                let removed = match input {
+                    WsInput::Message(Ok(ws::Message::Text(text))) if text == "request_inspect" => false,
+                    WsInput::Message(Err(e)) => {
+                        error!("WebSocket error: {}", e);
+                        break;
+                    },
+                    WsInput::Message(Ok(ws::Message::Close(_))) | WsInput::Closed => break,
+                    WsInput::Message(_) => continue,
+                    WsInput::Event(Ok(event)) if event.instance_id == instance_uuid => {
+                        matches!(event.kind, InstanceEventKind::Removed)
+                    },
+                    WsInput::Event(Ok(_)) => continue,
+                    WsInput::Event(Err(e)) => {
+                        error!("Error reading Docker events: {}", e);
+                        break;
+                    },
+                };
+                match Instance::inspect(&docker, &instance_uuid).await {
+                    // Deleting removes the containers first and the instance directory last,
+                    // either means the instance is gone.
+                    Ok(instance) if instance.containers.is_empty() => break,
+                    Ok(instance) => yield instance_message(instance)?,
+                    Err(_) if removed => break,
+                    Err(e) => error!("Error inspecting instance {}: {}", instance_uuid, e),
                 }
             }
         }
     }
 }
 
+/// Server-sent stream of container state changes for the configured namespace, so clients can
+/// update instance statuses without polling.
+#[get("/instances/events")]
+pub(crate) async fn instance_events(
+    docker: &State<Docker>,
+) -> Result<EventStream![], Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let events = watch_events(&docker, &namespace);
+    Ok(EventStream! {
+        for await event in events {
+            match event {
+                Ok(event) => yield Event::json(&event).event("instance"),
+                Err(e) => {
+                    error!("Error reading Docker events: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Prometheus metrics for the instance operations run by this API, plus the current
+/// instances by status. Only mounted when `enable_metrics` is set.
+#[get("/metrics")]
+pub(crate) async fn prometheus_metrics(
+    docker: &State<Docker>,
+    registry: &State<Registry>,
+) -> Result<(ContentType, String), Custom<String>> {
+    let namespace = config::read_or_create_config()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .namespace;
+    let instances = Instance::list_all(&docker, &namespace)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    let instances: Vec<Instance> = instances.into_values().collect();
+    metrics::record_instances(&instances);
+    let mut output = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut output)
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    let output = String::from_utf8(output)
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok((ContentType::Plain, output))
+}
+
 pub(crate) fn routes() -> Vec<rocket::Route> {
     routes![
         create_instance,
+        clone_instance,
         delete_instance,
         delete_all_instances,
         inspect_instance,
+        instance_credentials,
+        instance_stats,
         inspect_all_instances,
+        list_instances_by_label,
         start_instance,
         stop_instance,
         restart_instance,
+        pause_instance,
+        unpause_instance,
         start_all_instances,
         stop_all_instances,
         restart_all_instances,
@@ -254,5 +692,7 @@ pub(crate) fn routes() -> Vec<rocket::Route> {
         restart_container,
         delete_container,
         inspect_instance_ws,
+        instance_ws,
+        instance_events,
     ]
 }
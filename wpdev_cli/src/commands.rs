@@ -1,38 +1,466 @@
 use anyhow::{Error as AnyhowError, Result};
 use bollard::Docker;
+use futures::stream::StreamExt;
 use serde_json::Value as Json;
+use spinners::{Spinner, Spinners};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 use uuid::Uuid;
 
-use wpdev_core::docker::container::ContainerEnvVars;
-use wpdev_core::docker::instance::Instance;
+use wpdev_core::docker::connect_docker;
+use wpdev_core::docker::container::{
+    ContainerEnvVars, ContainerImage, ContainerStatus, DatabaseEngine, DebugSettings,
+    ExternalDatabase, InstanceContainer,
+};
+use wpdev_core::docker::instance::{
+    AdminLogin, AdoptReport, BackupCompression, BulkFailure, BulkOptions, BulkProgress, BulkReport,
+    CancellationToken, CreateObserver, Credentials, Instance, InstanceDescription, InstanceInfo,
+    InstancePlan, InstanceStats, InstanceStatus,
+};
+use wpdev_core::utils;
 
-pub(crate) async fn create_instance(
-    env_vars_str: Option<&String>,
-) -> Result<serde_json::Value, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    let uuid = Uuid::new_v4().to_string();
+const TAIL_LINES: usize = 50;
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Awaits `operation` and, if it fails and `enabled` is set, prints the last log lines of
+/// every container in the instance that isn't running before returning the error.
+pub(crate) async fn tail_on_error<F, T>(operation: F, instance_id: &str, enabled: bool) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let result = operation.await;
+    if enabled && result.is_err() {
+        if let Err(e) = print_failing_logs(instance_id).await {
+            eprintln!("Failed to fetch logs for {}: {}", instance_id, e);
+        }
+    }
+    result
+}
+
+async fn print_failing_logs(instance_id: &str) -> Result<()> {
+    let docker = connect_docker().await?;
+    let instance = Instance::list(&docker, instance_id).await?;
+    for container in instance
+        .containers
+        .iter()
+        .filter(|container| !matches!(container.container_status, ContainerStatus::Running))
+    {
+        let logs = InstanceContainer::logs(&docker, &container.container_id, TAIL_LINES).await?;
+        eprintln!(
+            "\n==> {} ({}) <==",
+            container.container_image,
+            container.container_status.to_string()
+        );
+        eprintln!("{}", logs.trim_end());
+    }
+    Ok(())
+}
+
+/// Persists a line for each completed create stage and spins on the next one. Without a
+/// terminal only the plain lines are printed.
+struct SpinnerObserver {
+    spinner: Mutex<Option<Spinner>>,
+    start: bool,
+    interactive: bool,
+}
+
+impl SpinnerObserver {
+    fn new(message: &str, start: bool) -> Self {
+        let interactive = utils::is_interactive();
+        let spinner = if interactive {
+            Some(Spinner::new(Spinners::Dots9, message.into()))
+        } else {
+            println!("{}...", message);
+            None
+        };
+        SpinnerObserver {
+            spinner: Mutex::new(spinner),
+            start,
+            interactive,
+        }
+    }
+
+    fn advance(&self, done: String, next: Option<&str>) {
+        if !self.interactive {
+            println!("{}", done);
+            if let Some(message) = next {
+                println!("{}...", message);
+            }
+            return;
+        }
+        let mut spinner = self.spinner.lock().unwrap();
+        if let Some(sp) = spinner.as_mut() {
+            sp.stop_and_persist("✔", done);
+        }
+        *spinner = next.map(|message| Spinner::new(Spinners::Dots9, message.into()));
+    }
+
+    fn finish(&self) {
+        if let Some(mut sp) = self.spinner.lock().unwrap().take() {
+            sp.stop();
+        }
+    }
+}
+
+impl CreateObserver for SpinnerObserver {
+    fn network_created(&self, network_name: &str) {
+        self.advance(
+            format!("Created network {}", network_name),
+            Some("Creating containers"),
+        );
+    }
+
+    fn container_created(&self, container_image: &ContainerImage, _container_id: &str) {
+        self.advance(
+            format!("Created {} container", container_image),
+            Some("Creating containers"),
+        );
+    }
+
+    fn config_written(&self, instance_id: &str) {
+        let next = if self.start {
+            Some("Starting instance")
+        } else {
+            None
+        };
+        self.advance(format!("Wrote config for {}", instance_id), next);
+    }
 
-    let env_vars = match env_vars_str {
+    fn instance_started(&self, instance_id: &str) {
+        self.advance(format!("Started {}", instance_id), None);
+    }
+}
+
+fn print_progress(verb: &'static str) -> impl Fn(&BulkProgress) + Send + Sync {
+    move |progress| {
+        eprint!("\r{}/{} {}", progress.completed, progress.total, verb);
+        if progress.completed == progress.total {
+            eprintln!();
+        }
+    }
+}
+
+/// Builds bulk options that print progress to stderr and stop scheduling new operations on
+/// Ctrl-C, letting the in-flight ones finish.
+fn bulk_options(verb: &'static str) -> BulkOptions {
+    let cancel = CancellationToken::new();
+    let ctrl_c_cancel = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("\nCancelling, waiting for in-flight operations to finish");
+            ctrl_c_cancel.cancel();
+        }
+    });
+
+    BulkOptions {
+        progress: Some(Box::new(print_progress(verb))),
+        cancel: Some(cancel),
+    }
+}
+
+/// Parses the `--network` option, only `external:<name>` is supported.
+pub(crate) fn parse_network(value: &str) -> Result<String> {
+    match value.split_once(':') {
+        Some(("external", name)) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(AnyhowError::msg(format!(
+            "Invalid network {}, expected external:<name>",
+            value
+        ))),
+    }
+}
+
+/// The `create` flags that override the JSON env vars passed on the command line.
+pub(crate) struct CreateFlags {
+    pub external_db: Option<(String, String)>,
+    pub debug: DebugSettings,
+    pub log_max_size: Option<String>,
+    pub external_network: Option<String>,
+    pub labels: Vec<(String, String)>,
+    pub nginx_port: Option<u32>,
+    pub adminer_port: Option<u32>,
+    pub read_only: bool,
+    pub plugins: Vec<PathBuf>,
+    pub wordpress_version: Option<String>,
+    pub php_version: Option<String>,
+    pub database_engine: Option<DatabaseEngine>,
+}
+
+/// Merges the `create` flags into the JSON env vars passed on the command line.
+pub(crate) fn create_env_vars(
+    env_vars_str: Option<&String>,
+    flags: CreateFlags,
+) -> Result<ContainerEnvVars> {
+    let CreateFlags {
+        external_db,
+        debug,
+        log_max_size,
+        external_network,
+        labels,
+        nginx_port,
+        adminer_port,
+        read_only,
+        plugins,
+        wordpress_version,
+        php_version,
+        database_engine,
+    } = flags;
+    let mut env_vars = match env_vars_str {
         Some(str) => serde_json::from_str(str)?,
         None => ContainerEnvVars::default(),
     };
+    if let Some((host, name)) = external_db {
+        env_vars.external_db = Some(ExternalDatabase { host, name });
+    }
+    env_vars.debug = debug;
+    if log_max_size.is_some() {
+        env_vars.log_max_size = log_max_size;
+    }
+    if external_network.is_some() {
+        env_vars.external_network = external_network;
+    }
+    if !labels.is_empty() {
+        env_vars
+            .labels
+            .get_or_insert_with(Default::default)
+            .extend(labels);
+    }
+    if nginx_port.is_some() {
+        env_vars.nginx_port = nginx_port;
+    }
+    if adminer_port.is_some() {
+        env_vars.adminer_port = adminer_port;
+    }
+    if read_only {
+        env_vars.read_only = true;
+    }
+    if !plugins.is_empty() {
+        env_vars
+            .plugins
+            .get_or_insert_with(Default::default)
+            .extend(plugins);
+    }
+    if wordpress_version.is_some() {
+        env_vars.wordpress_version = wordpress_version;
+    }
+    if php_version.is_some() {
+        env_vars.php_version = php_version;
+    }
+    if let Some(database_engine) = database_engine {
+        env_vars.database_engine = database_engine;
+    }
+    Ok(env_vars)
+}
 
-    match Instance::new(&docker, &uuid, env_vars).await {
-        Ok(instance) => Ok(serde_json::to_value(instance)?),
-        Err(e) => Err(AnyhowError::from(e)),
+pub(crate) async fn import_instance(
+    network_name: &str,
+    name: Option<String>,
+) -> Result<AdoptReport> {
+    let docker = connect_docker().await?;
+    let instance_label = name.unwrap_or_else(|| Uuid::new_v4().to_string());
+    Instance::adopt(&docker, network_name, &instance_label).await
+}
+
+/// Works out what `create` would do without creating anything.
+pub(crate) async fn plan_instance(env_vars: ContainerEnvVars) -> Result<InstancePlan> {
+    let uuid = Uuid::new_v4().to_string();
+    Instance::plan(&uuid, env_vars).await
+}
+
+pub(crate) async fn export_compose(uuid: &String) -> Result<String, AnyhowError> {
+    let docker = connect_docker().await?;
+    Instance::to_compose(&docker, uuid).await
+}
+
+pub(crate) async fn backup_instance(
+    uuid: &String,
+    out_path: &PathBuf,
+    compression: BackupCompression,
+) -> Result<(), AnyhowError> {
+    let docker = connect_docker().await?;
+    Instance::backup(&docker, uuid, out_path, compression).await
+}
+
+pub(crate) async fn restore_instance(archive_path: &PathBuf) -> Result<Instance, AnyhowError> {
+    let docker = connect_docker().await?;
+    Instance::restore(&docker, archive_path).await
+}
+
+/// Clones a stopped instance under a new UUID and starts the copy.
+pub(crate) async fn clone_instance(uuid: &String) -> Result<Instance, AnyhowError> {
+    let docker = connect_docker().await?;
+    Instance::duplicate(&docker, uuid, &Uuid::new_v4().to_string()).await
+}
+
+pub(crate) async fn create_instance(
+    env_vars: ContainerEnvVars,
+    install: bool,
+    demo_content: bool,
+    tail_logs: bool,
+    start: bool,
+) -> Result<Instance, AnyhowError> {
+    let docker = connect_docker().await?;
+    let uuid = Uuid::new_v4().to_string();
+
+    let import_dump = env_vars.import_dump.clone();
+    let import_site_url = env_vars.import_site_url.clone();
+    // Installing and importing need the containers running.
+    let start = start || install || import_dump.is_some();
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let observer = SpinnerObserver::new("Creating network", start);
+    let instance = tail_on_error(
+        Instance::new_with_observer(&docker, &uuid, env_vars, start, &observer),
+        &format!("{}-{}", namespace, uuid),
+        tail_logs,
+    )
+    .await;
+    observer.finish();
+    let instance = instance?;
+    if let Some(dump) = import_dump {
+        let imported = tail_on_error(
+            utils::with_spinner(
+                Instance::import_sql_dump(
+                    &docker,
+                    &instance.uuid,
+                    &dump,
+                    import_site_url.as_deref(),
+                ),
+                "Importing database",
+            ),
+            &instance.uuid,
+            tail_logs,
+        )
+        .await;
+        // Like a failed install, a failed import leaves the instance running.
+        if let Err(e) = imported {
+            eprintln!(
+                "warning: instance {} was created but importing {:?} failed: {:#}",
+                instance.uuid, dump, e
+            );
+            return Instance::inspect(&docker, &instance.uuid).await;
+        }
+    }
+    if !install {
+        return Ok(instance);
     }
+
+    // The instance is kept when installing fails, so it can be inspected or installed again.
+    if let Err(e) = install_instance(&docker, &instance.uuid, demo_content, tail_logs).await {
+        eprintln!(
+            "warning: instance {} was created but installing WordPress failed: {:#}",
+            instance.uuid, e
+        );
+        return Instance::inspect(&docker, &instance.uuid).await;
+    }
+
+    let plugin_results = utils::with_spinner(
+        Instance::install_plugins(&docker, &instance.uuid),
+        "Installing plugins",
+    )
+    .await?;
+    for result in plugin_results.iter().filter(|result| !result.installed) {
+        eprintln!(
+            "warning: failed to install plugin {}: {}",
+            result.plugin,
+            result.error.as_deref().unwrap_or_default()
+        );
+    }
+    let mut instance = Instance::inspect(&docker, &instance.uuid).await?;
+    if !plugin_results.is_empty() {
+        instance.plugin_results = Some(plugin_results);
+    }
+    Ok(instance)
+}
+
+/// Waits for a new instance's database and site, then installs WordPress, activates the
+/// mounted plugins and optionally imports the demo content.
+async fn install_instance(
+    docker: &Docker,
+    uuid: &str,
+    demo_content: bool,
+    tail_logs: bool,
+) -> Result<(), AnyhowError> {
+    tail_on_error(
+        utils::with_spinner(
+            Instance::wait_for_database(docker, uuid, READY_TIMEOUT),
+            "Waiting for database",
+        ),
+        uuid,
+        tail_logs,
+    )
+    .await?;
+    tail_on_error(
+        utils::with_spinner(
+            Instance::wait_until_ready(uuid, None, READY_TIMEOUT),
+            "Waiting for instance",
+        ),
+        uuid,
+        tail_logs,
+    )
+    .await?;
+    tail_on_error(
+        utils::with_spinner(Instance::install(docker, uuid), "Installing WordPress"),
+        uuid,
+        tail_logs,
+    )
+    .await?;
+    utils::with_spinner(
+        Instance::activate_plugins(docker, uuid),
+        "Activating plugins",
+    )
+    .await?;
+    if demo_content {
+        utils::with_spinner(
+            Instance::import_demo_content(docker, uuid),
+            "Importing demo content",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Prints the logs of an instance's containers, each line prefixed with the container image.
+pub(crate) async fn instance_logs(
+    uuid: &String,
+    container_id: Option<&str>,
+    follow: bool,
+    tail: Option<usize>,
+) -> Result<(), AnyhowError> {
+    let docker = connect_docker().await?;
+    let mut logs = Instance::logs(&docker, uuid, container_id, follow, tail).await?;
+    while let Some(chunk) = logs.next().await {
+        let chunk = chunk?;
+        for line in chunk.output.lines() {
+            println!("{:<9} | {}", chunk.container_image.to_string(), line);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn credentials(uuid: &String) -> Result<Credentials, AnyhowError> {
+    Instance::credentials(uuid).await
 }
 
 pub(crate) async fn start_instance(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
+    let docker = connect_docker().await?;
     match Instance::start(&docker, uuid).await {
         Ok(instance) => Ok(serde_json::to_value(instance)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
+pub(crate) async fn wait_until_ready(
+    uuid: &String,
+    readiness_path: Option<&String>,
+) -> Result<(), AnyhowError> {
+    Instance::wait_until_ready(uuid, readiness_path.map(|p| p.as_str()), READY_TIMEOUT).await
+}
+
 pub(crate) async fn stop_instance(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
+    let docker = connect_docker().await?;
     match Instance::stop(&docker, uuid).await {
         Ok(instance) => Ok(serde_json::to_value(instance)?),
         Err(e) => Err(AnyhowError::from(e)),
@@ -40,71 +468,436 @@ pub(crate) async fn stop_instance(uuid: &String) -> Result<Json, AnyhowError> {
 }
 
 pub(crate) async fn restart_instance(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
+    let docker = connect_docker().await?;
     match Instance::restart(&docker, uuid).await {
         Ok(instance) => Ok(serde_json::to_value(instance)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
+pub(crate) async fn pause_instance(uuid: &String) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::pause(&docker, uuid).await {
+        Ok(instance) => Ok(serde_json::to_value(instance)?),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
+
+pub(crate) async fn unpause_instance(uuid: &String) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::unpause(&docker, uuid).await {
+        Ok(instance) => Ok(serde_json::to_value(instance)?),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
+
 pub(crate) async fn delete_instance(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::delete(&docker, uuid, false).await {
+    let docker = connect_docker().await?;
+    match Instance::delete(&docker, uuid, true).await {
         Ok(instance) => Ok(serde_json::to_value(instance)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
 pub(crate) async fn delete_all_instances() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::delete_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => Ok(serde_json::to_value(instances)?),
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let options = bulk_options("deleted");
+    match Instance::delete_all_tracked(&docker, &namespace, &options).await {
+        Ok(report) => Ok(serde_json::to_value(report)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
-pub(crate) async fn inspect_instance(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::inspect(&docker, uuid).await {
-        Ok(instance) => Ok(serde_json::to_value(instance)?),
+pub(crate) async fn inspect_instance(
+    uuid: &String,
+    check_updates: bool,
+) -> Result<Instance, AnyhowError> {
+    let docker = connect_docker().await?;
+    let mut instance = Instance::inspect(&docker, uuid).await?;
+    if check_updates {
+        instance.check_image_updates().await?;
+    }
+    Ok(instance)
+}
+
+pub(crate) async fn port_map() -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    match Instance::port_map(&docker, &namespace).await {
+        Ok(ports) => Ok(serde_json::to_value(ports)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
-pub(crate) async fn inspect_all_instances() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => Ok(serde_json::to_value(instances)?),
+const TOP_REFRESH: Duration = Duration::from_secs(2);
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+fn print_stats_table(stats: &[InstanceStats]) {
+    println!("{:<48} {:>8} {:>12}", "INSTANCE", "CPU %", "MEM USAGE");
+    for instance in stats {
+        println!(
+            "{:<48} {:>7.2}% {:>12}",
+            instance.instance_id,
+            instance.cpu_percent,
+            format_bytes(instance.memory_usage)
+        );
+        for container in &instance.containers {
+            println!(
+                "  {:<46} {:>7.2}% {:>12}",
+                container.container_image.to_string(),
+                container.cpu_percent,
+                format_bytes(container.memory_usage)
+            );
+        }
+    }
+}
+
+pub(crate) async fn instance_stats(uuid: &String) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::stats(&docker, uuid).await {
+        Ok(stats) => Ok(serde_json::to_value(stats)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
+/// Redraws a resource usage table of every instance until Ctrl-C, like `docker stats`.
+pub(crate) async fn top() -> Result<(), AnyhowError> {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    loop {
+        let stats = Instance::stats_all(&docker, &namespace).await?;
+        print!("\x1B[2J\x1B[H");
+        print_stats_table(&stats);
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(TOP_REFRESH) => {}
+        }
+    }
+}
+
+/// Redraws the status of the instance `id`, or of every instance, every `interval` until
+/// Ctrl-C.
+pub(crate) async fn watch_statuses(
+    id: Option<&str>,
+    interval: Duration,
+) -> Result<(), AnyhowError> {
+    let docker = connect_docker().await?;
+    let config = wpdev_core::config::read_or_create_config().await?;
+    let color = config.cli_colored_output && utils::is_interactive();
+    loop {
+        let mut statuses = match id {
+            Some(id) => vec![Instance::get_status(&docker, id).await?],
+            None => Instance::get_all_statuses(&docker, &config.namespace).await?,
+        };
+        statuses.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        print!("\x1B[2J\x1B[H");
+        println!("Every {}s, press Ctrl-C to exit\n", interval.as_secs());
+        print_status_table(&statuses, color);
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Prints the statuses with running ones in green, stopped ones in red and anything in
+/// between in yellow when `color` is set.
+fn print_status_table(statuses: &[InstanceInfo], color: bool) {
+    println!("{:<48} STATUS", "INSTANCE");
+    for info in statuses {
+        let ansi_color = match info.status.as_str() {
+            "Running" => Some(32),
+            "Stopped" | "Exited" | "Dead" => Some(31),
+            "PartiallyRunning" | "Restarting" | "Paused" => Some(33),
+            _ => None,
+        };
+        match ansi_color.filter(|_| color) {
+            Some(code) => println!("{:<48} \x1B[{}m{}\x1B[0m", info.uuid, code, info.status),
+            None => println!("{:<48} {}", info.uuid, info.status),
+        }
+    }
+}
+
+/// Prints the outcome of `wpdev doctor` checks, remembering whether a critical one failed.
+#[derive(Default)]
+struct Checklist {
+    failed: bool,
+}
+
+impl Checklist {
+    fn pass(&mut self, check: &str, detail: &str) {
+        println!("[ok]   {}: {}", check, detail);
+    }
+
+    fn warn(&mut self, check: &str, detail: &str, hint: &str) {
+        println!("[warn] {}: {}", check, detail);
+        println!("       hint: {}", hint);
+    }
+
+    fn fail(&mut self, check: &str, detail: &str, hint: &str) {
+        self.failed = true;
+        println!("[FAIL] {}: {}", check, detail);
+        println!("       hint: {}", hint);
+    }
+}
+
+/// Checks the config, `custom_root`, the Docker daemon, the configured images and the instance
+/// networks, printing a checklist. Returns whether every critical check passed. Missing
+/// images only warn, since they're pulled when needed.
+pub(crate) async fn doctor() -> Result<bool, AnyhowError> {
+    let mut checklist = Checklist::default();
+
+    let config = match wpdev_core::config::load_config().await {
+        Ok(config) => {
+            checklist.pass("Config", "parsed");
+            Some(config)
+        }
+        Err(e) => {
+            checklist.fail(
+                "Config",
+                &format!("{:#}", e),
+                "fix the reported setting, or move the file aside to start from the defaults",
+            );
+            None
+        }
+    };
+    if let Some(config) = &config {
+        match config.validate() {
+            Ok(()) => checklist.pass(
+                "custom_root",
+                &format!(
+                    "{:?} is writable",
+                    config.custom_root.clone().unwrap_or_default()
+                ),
+            ),
+            Err(e) => checklist.fail(
+                "custom_root",
+                &format!("{:#}", e),
+                "set custom_root to a directory you own, e.g. ~/wpdev/instances",
+            ),
+        }
+    }
+
+    let docker_host = config
+        .as_ref()
+        .and_then(|config| config.docker_host.as_deref());
+    let docker = match wpdev_core::docker::connect_docker_host(docker_host) {
+        Ok(docker) => match docker.version().await {
+            Ok(version) => {
+                checklist.pass(
+                    "Docker",
+                    &format!(
+                        "daemon reachable, version {}",
+                        version.version.unwrap_or_default()
+                    ),
+                );
+                Some(docker)
+            }
+            Err(e) => {
+                checklist.fail(
+                    "Docker",
+                    &e.to_string(),
+                    "start Docker, and check that your user can access its socket",
+                );
+                None
+            }
+        },
+        Err(e) => {
+            checklist.fail(
+                "Docker",
+                &format!("{:#}", e),
+                "check docker_host in the config, or DOCKER_HOST when it's unset",
+            );
+            None
+        }
+    };
+    let (Some(config), Some(docker)) = (config, docker) else {
+        println!("[skip] Images and networks: need the config and Docker");
+        return Ok(!checklist.failed);
+    };
+
+    for image in &config.docker_images {
+        match wpdev_core::config::image_exists(image).await {
+            Ok(true) => checklist.pass("Image", &format!("{} is pulled", image)),
+            Ok(false) => checklist.warn(
+                "Image",
+                &format!("{} is missing", image),
+                &format!("run `docker pull {}`", image),
+            ),
+            Err(e) => checklist.fail("Image", &format!("{}: {:#}", image, e), "check Docker"),
+        }
+    }
+
+    match docker.list_networks::<String>(None).await {
+        Ok(networks) => {
+            let prefix = format!("{}-", config.namespace);
+            let custom_root = config.custom_root.unwrap_or_default();
+            let names: Vec<String> = networks
+                .into_iter()
+                .filter_map(|network| network.name)
+                .filter(|name| name.starts_with(&prefix))
+                .collect();
+            checklist.pass(
+                "Networks",
+                &format!("{} {}* networks listed", names.len(), prefix),
+            );
+            for name in names.iter().filter(|name| !custom_root.join(name).exists()) {
+                checklist.warn(
+                    "Networks",
+                    &format!("{} has no instance directory", name),
+                    &format!(
+                        "it's left over from a removed instance, remove it with `docker network rm {}`",
+                        name
+                    ),
+                );
+            }
+        }
+        Err(e) => checklist.fail(
+            "Networks",
+            &e.to_string(),
+            "check that Docker is healthy, e.g. with `docker network ls`",
+        ),
+    }
+
+    Ok(!checklist.failed)
+}
+
+/// Parses an `<id>:<container>:<path>` copy operand, returning `None` for a local path.
+fn parse_container_path(operand: &str) -> Result<Option<(String, ContainerImage, String)>> {
+    let parts: Vec<&str> = operand.splitn(3, ':').collect();
+    match parts[..] {
+        [id, container, path] if path.starts_with('/') => {
+            match ContainerImage::from_str(container) {
+                ContainerImage::Unknown => Err(AnyhowError::msg(format!(
+                    "Unknown container {}, expected wordpress, mysql, mariadb, nginx or adminer",
+                    container
+                ))),
+                image => Ok(Some((id.to_string(), image, path.to_string()))),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+pub(crate) async fn copy(source: &str, destination: &str) -> Result<(), AnyhowError> {
+    let docker = connect_docker().await?;
+    match (
+        parse_container_path(source)?,
+        parse_container_path(destination)?,
+    ) {
+        (Some((id, image, path)), None) => {
+            Instance::copy_from(&docker, &id, image, &path, &PathBuf::from(destination)).await
+        }
+        (None, Some((id, image, path))) => {
+            Instance::copy_to(&docker, &id, image, &PathBuf::from(source), &path).await
+        }
+        _ => Err(AnyhowError::msg(
+            "Exactly one of source and destination must be <id>:<container>:<path>",
+        )),
+    }
+}
+
+pub(crate) async fn inspect_all_namespaces(
+    check_updates: bool,
+) -> Result<BTreeMap<String, Vec<Instance>>, AnyhowError> {
+    let docker = connect_docker().await?;
+    let mut namespaces = Instance::inspect_all_namespaces(&docker).await?;
+    if check_updates {
+        for instance in namespaces.values_mut().flatten() {
+            instance.check_image_updates().await?;
+        }
+    }
+    Ok(namespaces)
+}
+
+pub(crate) async fn inspect_all_instances(
+    check_updates: bool,
+) -> Result<Vec<Instance>, AnyhowError> {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let mut instances = Instance::inspect_all(&docker, &namespace).await?;
+    if check_updates {
+        for instance in instances.iter_mut() {
+            instance.check_image_updates().await?;
+        }
+    }
+    Ok(instances)
+}
+
 pub(crate) async fn restart_all_instances() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::restart_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => Ok(serde_json::to_value(instances)?),
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let options = bulk_options("restarted");
+    match Instance::restart_all_tracked(&docker, &namespace, &options).await {
+        Ok(report) => Ok(serde_json::to_value(report)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
 pub(crate) async fn stop_all_instances() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::stop_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => Ok(serde_json::to_value(instances)?),
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let options = bulk_options("stopped");
+    match Instance::stop_all_tracked(&docker, &namespace, &options).await {
+        Ok(report) => Ok(serde_json::to_value(report)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
 pub(crate) async fn start_all_instances() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::start_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => Ok(serde_json::to_value(instances)?),
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let options = bulk_options("started");
+    match Instance::start_all_tracked(&docker, &namespace, &options).await {
+        Ok(report) => Ok(serde_json::to_value(report)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
 
+/// Starts, or restarts with `restart`, only the instances that aren't fully running, leaving
+/// the healthy ones untouched.
+pub(crate) async fn retry_failed_instances(restart: bool) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    let running = format!("{:?}", InstanceStatus::Running);
+    let statuses = Instance::get_all_statuses(&docker, &namespace).await?;
+
+    let mut report = BulkReport::default();
+    for info in statuses.into_iter().filter(|info| info.status != running) {
+        eprintln!("Retrying {} ({})", info.uuid, info.status);
+        let result = if restart {
+            Instance::restart(&docker, &info.uuid).await
+        } else {
+            Instance::start(&docker, &info.uuid).await
+        };
+        match result {
+            Ok(retried) => report.completed.push(retried),
+            Err(e) => report.failed.push(BulkFailure {
+                instance_id: info.uuid,
+                error: e.to_string(),
+            }),
+        }
+    }
+    if report.completed.is_empty() && report.failed.is_empty() {
+        eprintln!("All instances are running, nothing to retry");
+    }
+
+    Ok(serde_json::to_value(report)?)
+}
+
 pub(crate) async fn get_status(uuid: &String) -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
+    let docker = connect_docker().await?;
     match Instance::get_status(&docker, uuid).await {
         Ok(status) => Ok(serde_json::to_value(status)?),
         Err(e) => Err(AnyhowError::from(e)),
@@ -112,9 +905,116 @@ pub(crate) async fn get_status(uuid: &String) -> Result<Json, AnyhowError> {
 }
 
 pub(crate) async fn get_all_statuses() -> Result<Json, AnyhowError> {
-    let docker = Docker::connect_with_defaults()?;
-    match Instance::get_all_statuses(&docker, wpdev_core::NETWORK_NAME).await {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    match Instance::get_all_statuses(&docker, &namespace).await {
         Ok(statuses) => Ok(serde_json::to_value(statuses)?),
         Err(e) => Err(AnyhowError::from(e)),
     }
 }
+
+/// The recorded site URL of an instance, or its Adminer URL with `adminer`, and whether the
+/// instance is running.
+pub(crate) async fn instance_url(uuid: &str, adminer: bool) -> Result<(String, bool), AnyhowError> {
+    let instance_data = wpdev_core::config::read_instance_data_from_toml(uuid).await?;
+    let docker = connect_docker().await?;
+    let info = Instance::get_status(&docker, uuid).await?;
+    let url = if adminer {
+        instance_data.adminer_url
+    } else {
+        instance_data.site_url
+    };
+    Ok((url, info.status == format!("{:?}", InstanceStatus::Running)))
+}
+
+pub(crate) async fn admin_login(uuid: &String) -> Result<AdminLogin, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::admin_url(&docker, uuid).await {
+        Ok(login) => Ok(login),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
+
+pub(crate) async fn set_admin_password(
+    uuid: &String,
+    password: Option<String>,
+) -> Result<AdminLogin, AnyhowError> {
+    let docker = connect_docker().await?;
+    Instance::set_admin_password(&docker, uuid, password).await
+}
+
+pub(crate) async fn regenerate_wpcli_config(
+    uuid: &String,
+) -> Result<Vec<InstanceInfo>, AnyhowError> {
+    let docker = connect_docker().await?;
+    Ok(vec![
+        Instance::regenerate_wpcli_config(&docker, uuid).await?,
+    ])
+}
+
+pub(crate) async fn regenerate_all_wpcli_configs() -> Result<Vec<InstanceInfo>, AnyhowError> {
+    let docker = connect_docker().await?;
+    let namespace = wpdev_core::config::read_or_create_config().await?.namespace;
+    Instance::regenerate_all_wpcli_configs(&docker, &namespace).await
+}
+
+pub(crate) async fn set_pinned(uuid: &String, pinned: bool) -> Result<(), AnyhowError> {
+    Instance::set_pinned(uuid, pinned).await
+}
+
+pub(crate) async fn describe_instance(uuid: &String) -> Result<InstanceDescription, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::describe(&docker, uuid).await {
+        Ok(description) => Ok(description),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
+
+/// Runs a WP-CLI command against an instance, writing its stdout and stderr straight to ours
+/// and returning its exit code. With `raw` the output is written byte for byte, otherwise it's
+/// decoded as UTF-8 and a trailing newline is ensured.
+pub(crate) async fn wp_cli_passthrough(
+    uuid: &String,
+    args: Vec<String>,
+    raw: bool,
+) -> Result<i32, AnyhowError> {
+    let docker = connect_docker().await?;
+    let output = Instance::run_wp_cli_output(&docker, uuid, args).await?;
+    write_output(&mut io::stdout(), &output.stdout, raw)?;
+    write_output(&mut io::stderr(), &output.stderr, raw)?;
+    Ok(output.exit_code as i32)
+}
+
+fn write_output(out: &mut impl Write, bytes: &[u8], raw: bool) -> io::Result<()> {
+    if raw {
+        out.write_all(bytes)?;
+    } else if !bytes.is_empty() {
+        let text = String::from_utf8_lossy(bytes);
+        out.write_all(text.as_bytes())?;
+        if !text.ends_with('\n') {
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush()
+}
+
+pub(crate) async fn reset_instance(uuid: &String, keep_uploads: bool) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    match Instance::reset(&docker, uuid, keep_uploads).await {
+        Ok(output) => Ok(serde_json::to_value(output)?),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
+
+pub(crate) async fn set_permalinks(
+    uuid: &String,
+    structure: Option<&String>,
+) -> Result<Json, AnyhowError> {
+    let docker = connect_docker().await?;
+    let config = wpdev_core::config::read_or_create_config().await?;
+    let structure = structure.unwrap_or(&config.permalink_structure);
+    match Instance::set_permalinks(&docker, uuid, structure).await {
+        Ok(output) => Ok(serde_json::to_value(output)?),
+        Err(e) => Err(AnyhowError::from(e)),
+    }
+}
@@ -1,19 +1,39 @@
-use anyhow::Result;
+use anyhow::{Error as AnyhowError, Result};
 use tokio;
 mod commands;
 use wpdev_core::config;
 use wpdev_core::utils;
+use wpdev_core::AppConfig;
 
 use anyhow::Context;
 use bat::PrettyPrinter;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use env_logger;
-use serde_json;
+use serde_json::{self, Value as Json};
+use std::path::PathBuf;
+use wpdev_core::docker::container::{ContainerEnvVars, DatabaseEngine, DebugSettings};
+use wpdev_core::docker::instance::{
+    BackupCompression, Instance, InstanceData, InstanceDescription,
+};
+use wpdev_core::error::WpdevError;
 
 /// A CLI for managing WordPress development environments.
 #[derive(Parser, Debug)]
 #[clap(name = "wpdev")]
 struct Cli {
+    /// Config profile to use, loads `config.<profile>.toml` instead of `config.toml`
+    #[clap(long, global = true, env = config::PROFILE_ENV)]
+    profile: Option<String>,
+
+    /// Include passwords in printed instances instead of redacting them
+    #[clap(long, global = true, action = clap::ArgAction::SetTrue)]
+    show_secrets: bool,
+
+    /// Output format. Defaults to table for `list -a`, `create`, `describe` and `config show`,
+    /// and to JSON everywhere else
+    #[clap(long, global = true, value_enum)]
+    output: Option<OutputFormat>,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -21,22 +41,372 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// List instances. If an ID is provided, details for that instance are shown. If -a is provided, lists all instances.
-    List(InstanceArgs),
+    List(ListArgs),
     /// Create a new instance
     Create {
         #[clap(value_parser, group = "listing")]
         options: Option<String>,
+
+        /// Name other commands accept in place of the instance's ID, must be unique
+        #[clap(long)]
+        name: Option<String>,
+
+        /// Start the instance and install WordPress core
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        install: bool,
+
+        /// Import demo content after installing
+        #[clap(long, action = clap::ArgAction::SetTrue, requires = "install")]
+        demo_content: bool,
+
+        /// Install and activate a plugin from wordpress.org (slug) or a zip URL after
+        /// installing, can be repeated
+        #[clap(long = "install-plugin", value_name = "SLUG|URL", requires = "install")]
+        install_plugins: Vec<String>,
+
+        /// Don't install the plugins listed in the options' `install_plugins`
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        skip_plugins: bool,
+
+        /// Import a SQL dump (`.sql` or `.sql.gz`) into the database once it's up
+        #[clap(long = "import", value_name = "FILE")]
+        import_dump: Option<PathBuf>,
+
+        /// URL of the site the dump was taken from, replaced with the instance's URL.
+        /// Defaults to the dump's siteurl option
+        #[clap(long, value_name = "URL", requires = "import_dump")]
+        import_site_url: Option<String>,
+
+        /// Use a shared MySQL container (`name[:port]`) instead of creating one
+        #[clap(long, requires = "db_name")]
+        db_host: Option<String>,
+
+        /// Database to create on the shared MySQL server
+        #[clap(long, requires = "db_host")]
+        db_name: Option<String>,
+
+        /// Enable WP_DEBUG
+        #[clap(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        debug: bool,
+
+        /// Log errors to wp-content/debug.log
+        #[clap(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        debug_log: bool,
+
+        /// Display errors on the page
+        #[clap(long, value_name = "BOOL", default_value_t = false, action = clap::ArgAction::Set)]
+        debug_display: bool,
+
+        /// Print the logs of any stopped containers if starting or installing fails
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        tail_on_error: bool,
+
+        /// Create the containers without starting them
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "install")]
+        no_start: bool,
+
+        /// Rotate container logs at this size (e.g. `50m`), defaults to `log_max_size` from the config
+        #[clap(long)]
+        log_max_size: Option<String>,
+
+        /// Attach the instance to an existing Docker network (`external:<name>`) instead of
+        /// creating one
+        #[clap(long, value_name = "external:NAME", value_parser = commands::parse_network)]
+        network: Option<String>,
+
+        /// Extra label (`key=value`) to set on the instance's containers, can be repeated
+        #[clap(long = "label", value_name = "KEY=VALUE", value_parser = utils::parse_label)]
+        labels: Vec<(String, String)>,
+
+        /// Pin the site to this host port instead of picking a free one
+        #[clap(long)]
+        nginx_port: Option<u32>,
+
+        /// Pin Adminer to this host port instead of picking a free one
+        #[clap(long)]
+        adminer_port: Option<u32>,
+
+        /// Run the containers with a read-only root filesystem, only the instance's volumes
+        /// and a few tmpfs paths stay writable
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        read_only: bool,
+
+        /// Mount a plugin directory into wp-content/plugins/<dirname>, can be repeated. With
+        /// --install the plugins are activated too
+        #[clap(long = "plugin", value_name = "DIR")]
+        plugins: Vec<PathBuf>,
+
+        /// Tag of the wordpress image to use, e.g. 6.3, pulled if it isn't already
+        #[clap(long)]
+        wordpress_version: Option<String>,
+
+        /// PHP version of the wordpress image, e.g. 8.1
+        #[clap(long)]
+        php_version: Option<String>,
+
+        /// Database server to create, mysql or mariadb
+        #[clap(long, value_parser = DatabaseEngine::parse)]
+        database_engine: Option<DatabaseEngine>,
+
+        /// Add a Redis container for the Redis Object Cache plugin
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        redis: bool,
+
+        /// Add a Mailpit container that catches the mail WordPress sends
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        mail: bool,
+
+        /// Publish the database port on a free host port, for Sequel Ace, TablePlus etc.
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        expose_mysql_port: bool,
+
+        /// Serve the site over HTTPS with a self-signed certificate for <uuid>.local
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        tls: bool,
+
+        /// Memory limit in MB for every container, instead of the per-service default
+        #[clap(long, value_name = "MB")]
+        memory_mb: Option<u64>,
+
+        /// CPU limit for every container, e.g. 0.5, instead of the per-service default
+        #[clap(long)]
+        cpus: Option<f64>,
+
+        #[clap(flatten)]
+        admin: AdminArgs,
+
+        /// Print the images, ports, directories, container specs and nginx config that would
+        /// be created, without touching Docker
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
     },
     /// Start instances. If an ID is provided, starts that instance. If -a is provided, starts all instances.
-    Start(InstanceArgs),
+    Start(StartArgs),
     /// Stop instances. If an ID is provided, stops that instance. If -a is provided, stops all instances.
     Stop(InstanceArgs),
     /// Restart instances. If an ID is provided, restarts that instance. If -a is provided, restarts all instances.
-    Restart(InstanceArgs),
+    Restart(RestartArgs),
+    /// Pause a running instance's containers, freezing them without losing state.
+    Pause {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Unpause a paused instance's containers.
+    Unpause {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
     /// Prune instances. If an ID is provided, prune that instance. If -a is provided, prune all instances.
     Prune(InstanceArgs),
     /// Get the status of an instance or all instances.
-    Status(InstanceArgs),
+    Status(StatusArgs),
+    /// Check that the config, custom_root, Docker and the configured images are usable.
+    /// Exits non-zero if a critical check fails.
+    Doctor,
+    /// Show the host ports recorded by every instance, flagging duplicates and ports in use.
+    Ports,
+    /// Show the CPU and memory usage of an instance.
+    Stats {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Show a live view of the CPU and memory usage of all instances.
+    Top,
+    /// Describe an instance, showing its recorded config, runtime state and any drift between them.
+    Describe {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Run a WP-CLI command against an instance, e.g. `wpdev wp <id> -- plugin list --format=json`.
+    /// Output and exit code are passed through unchanged.
+    Wp {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Write output byte for byte, without decoding or adding a trailing newline
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        raw: bool,
+
+        /// WP-CLI arguments
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Reset a running instance to a fresh WordPress install, keeping its ports and credentials.
+    Reset {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Keep wp-content/uploads
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        keep_uploads: bool,
+    },
+    /// Set the permalink structure of an installed instance.
+    Permalinks {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Permalink structure, defaults to `permalink_structure` from the config
+        #[clap(value_parser)]
+        structure: Option<String>,
+    },
+    /// Open an instance's site in the browser.
+    Open {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Open Adminer instead of the site
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        adminer: bool,
+
+        /// Only print the URL
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        print: bool,
+    },
+    /// Open wp-admin for an instance and print its admin credentials.
+    Admin {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Copy a stopped instance, including its files and database, into a new instance with its
+    /// own ports and start it.
+    Clone {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Archive a stopped instance, including its files and database, to a compressed tar.
+    Backup {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Archive to write
+        #[clap(value_parser)]
+        archive: PathBuf,
+
+        /// Compression of the archive: none, gzip or zstd
+        #[clap(long, value_parser = BackupCompression::parse, default_value = "gzip")]
+        compress: BackupCompression,
+
+        /// Write an uncompressed tar, same as `--compress none`
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "compress")]
+        no_compress: bool,
+    },
+    /// Recreate an instance from an archive written by `backup`, with new ports, and start it.
+    Restore {
+        /// Archive to restore
+        #[clap(value_parser)]
+        archive: PathBuf,
+    },
+    /// Print a docker-compose file that runs the instance without wpdev, e.g.
+    /// `wpdev export <id> > stack.yml`.
+    Export {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Print the logs of an instance's containers, each line prefixed with its image.
+    Logs {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// Keep streaming new output
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        follow: bool,
+
+        /// Only print this many lines of existing output per container
+        #[clap(long)]
+        tail: Option<usize>,
+
+        /// Only print the logs of the container with this ID or ID prefix
+        #[clap(long)]
+        container: Option<String>,
+    },
+    /// Show the WordPress admin and Adminer logins of an instance. Other commands redact
+    /// passwords unless `--show-secrets` is passed.
+    Credentials {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Pin an instance so it's listed before the others.
+    Pin {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Unpin an instance.
+    Unpin {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Set the WordPress admin password of an instance and print it.
+    SetAdminPassword {
+        /// Instance ID
+        #[clap(value_parser)]
+        id: String,
+
+        /// New password, a strong one is generated if omitted
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// Rewrite the wp-cli.local.yml/wp-cli.local.php of an instance, or of all instances
+    /// with -a, e.g. to repair ones generated by an older version.
+    WpcliReload(InstanceArgs),
+    /// Copy files between an instance container and the host. One side is
+    /// `<id>:<container>:<path>`, e.g. `wpdev cp <id>:wordpress:/var/www/html/wp-config.php .`
+    Cp {
+        /// Source path
+        #[clap(value_parser)]
+        source: String,
+
+        /// Destination path
+        #[clap(value_parser)]
+        destination: String,
+    },
+    /// Import an existing WordPress stack created outside wpdev from its Docker network.
+    /// The containers are left as they are and managed as an instance from then on.
+    Import {
+        /// Docker network the WordPress containers are attached to
+        #[clap(long)]
+        network: String,
+
+        /// Name for the instance, defaults to a new UUID
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Manage wpdev config.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// List the available config profiles.
+    Profiles,
+    /// Show the effective config, including defaults for settings that aren't set.
+    Show {
+        /// Show the default config instead
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "diff")]
+        defaults: bool,
+
+        /// Only show the settings that differ from the defaults
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        diff: bool,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -50,9 +420,201 @@ struct InstanceArgs {
     all: bool,
 }
 
+/// The WordPress admin login and site title `create --install` sets up.
+#[derive(Args, Debug)]
+struct AdminArgs {
+    /// WordPress admin username, defaults to the database user
+    #[clap(long)]
+    admin_user: Option<String>,
+
+    /// WordPress admin password, defaults to the database password
+    #[clap(long)]
+    admin_password: Option<String>,
+
+    /// WordPress admin email
+    #[clap(long)]
+    admin_email: Option<String>,
+
+    /// Site title
+    #[clap(long)]
+    site_title: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    #[clap(flatten)]
+    instance: InstanceArgs,
+
+    /// Check the registry for newer builds of each instance's images
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    check_updates: bool,
+
+    /// With -a, list the instances of every namespace, grouped by namespace
+    #[clap(long, action = clap::ArgAction::SetTrue, requires = "all")]
+    all_namespaces: bool,
+}
+
+#[derive(Args, Debug)]
+struct StartArgs {
+    #[clap(flatten)]
+    instance: InstanceArgs,
+
+    /// Print the logs of any stopped containers if the instance fails to start
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    tail_on_error: bool,
+
+    /// With -a, only start instances that aren't fully running
+    #[clap(long, action = clap::ArgAction::SetTrue, requires = "all")]
+    only_failed: bool,
+
+    /// Wait until the site responds before returning
+    #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "all")]
+    wait: bool,
+
+    /// Path checked by --wait, defaults to `readiness_path` from the config
+    #[clap(long, requires = "wait")]
+    readiness_path: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct StatusArgs {
+    #[clap(flatten)]
+    instance: InstanceArgs,
+
+    /// Keep redrawing the status until Ctrl-C
+    #[clap(short, long, action = clap::ArgAction::SetTrue)]
+    watch: bool,
+
+    /// Seconds between redraws with --watch
+    #[clap(long, default_value_t = 2, requires = "watch")]
+    interval: u64,
+}
+
+#[derive(Args, Debug)]
+struct RestartArgs {
+    #[clap(flatten)]
+    instance: InstanceArgs,
+
+    /// With -a, only restart instances that aren't fully running
+    #[clap(long, action = clap::ArgAction::SetTrue, requires = "all")]
+    only_failed: bool,
+}
+
+/// How results are printed, chosen with the global `--output` flag.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// Aligned rows, or a readable summary for `create`, `describe` and `config show`
+    Table,
+    Json,
+    /// One row per instance, for spreadsheets. Only supported by `list`
+    Csv,
+}
+
+impl Commands {
+    /// The instance a command operates on, as passed on the command line.
+    fn instance_id_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Commands::List(ListArgs { instance, .. })
+            | Commands::Start(StartArgs { instance, .. })
+            | Commands::Restart(RestartArgs { instance, .. })
+            | Commands::Status(StatusArgs { instance, .. })
+            | Commands::Stop(instance)
+            | Commands::Prune(instance)
+            | Commands::WpcliReload(instance) => instance.id.as_mut(),
+            Commands::Pause { id }
+            | Commands::Unpause { id }
+            | Commands::Stats { id }
+            | Commands::Describe { id, .. }
+            | Commands::Wp { id, .. }
+            | Commands::Reset { id, .. }
+            | Commands::Permalinks { id, .. }
+            | Commands::Open { id, .. }
+            | Commands::Admin { id }
+            | Commands::Clone { id }
+            | Commands::Backup { id, .. }
+            | Commands::Export { id }
+            | Commands::Logs { id, .. }
+            | Commands::Credentials { id }
+            | Commands::Pin { id }
+            | Commands::Unpin { id }
+            | Commands::SetAdminPassword { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+}
+
+fn print_description(description: &InstanceDescription) {
+    let config = &description.config;
+    println!("Instance: {}", description.uuid);
+    println!("\nConfig:");
+    println!("  site_url:     {}", config.site_url);
+    println!("  adminer_url:  {}", config.adminer_url);
+    println!("  network_name: {}", config.network_name);
+    println!("  nginx_port:   {}", config.nginx_port);
+    println!("  adminer_port: {}", config.adminer_port);
+    println!("\nRuntime:");
+    for container in &description.runtime {
+        println!(
+            "  {} ({}): image={} status={} ports={:?}",
+            container.name,
+            &container.container_id[..12.min(container.container_id.len())],
+            container.image,
+            container.status.to_string(),
+            container.host_ports
+        );
+    }
+    println!("\nDrift:");
+    if description.drift.is_empty() {
+        println!("  none");
+    }
+    for drift in &description.drift {
+        println!("  - {}", drift);
+    }
+}
+
+fn print_create_summary(instance_data: &InstanceData) {
+    println!("\nSite:        {}", instance_data.site_url);
+    println!(
+        "wp-admin:    {}/wp-admin/",
+        instance_data.site_url.trim_end_matches('/')
+    );
+    println!(
+        "Admin login: {} / {}",
+        instance_data.admin_user, instance_data.admin_password
+    );
+    println!("Adminer:     {}", instance_data.adminer_url);
+    println!(
+        "Database:    {} / {}",
+        instance_data.adminer_user, instance_data.adminer_password
+    );
+}
+
+/// Prints `value` as pretty JSON, or as a table of its fields with `--output table`.
+async fn print_output(output: OutputFormat, value: &Json) -> Result<()> {
+    match output {
+        OutputFormat::Table => utils::print_table(value, std::io::stdout().lock()),
+        OutputFormat::Json => pretty_print("json", &serde_json::to_string_pretty(value)?).await,
+        OutputFormat::Csv => Err(csv_unsupported()),
+    }
+}
+
+/// Prints `instance` as pretty JSON, or as a row of the instance table with `--output table`.
+async fn print_instance(output: OutputFormat, instance: &Instance) -> Result<()> {
+    match output {
+        OutputFormat::Table => {
+            utils::print_instances_table(std::slice::from_ref(instance), std::io::stdout().lock())
+        }
+        output => print_output(output, &serde_json::to_value(instance)?).await,
+    }
+}
+
+fn csv_unsupported() -> AnyhowError {
+    AnyhowError::msg("--output csv is only supported by list")
+}
+
 async fn pretty_print(language: &str, input: &str) -> Result<()> {
     let config = config::read_or_create_config().await?;
-    let color = config.cli_colored_output;
+    let color = config.cli_colored_output && utils::is_interactive();
     let theme = config.cli_theme;
     let mut printer = PrettyPrinter::new();
     printer.input_from_bytes(input.as_bytes());
@@ -67,124 +629,555 @@ async fn pretty_print(language: &str, input: &str) -> Result<()> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    match run().await {
+        // A missing instance or container is a usage error, not worth the whole error chain.
+        Err(e) if WpdevError::is_not_found(&e) => {
+            eprintln!("Error: {}", e.root_cause());
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+async fn run() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(profile) = &cli.profile {
+        // Core reads the profile from the environment, so every config read picks it up.
+        std::env::set_var(config::PROFILE_ENV, profile);
+    }
+    // Runs before the config is read, which needs a valid config and Docker to pull images.
+    if let Commands::Doctor = cli.command {
+        if !commands::doctor().await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let config = config::read_or_create_config()
         .await
         .context("Failed to read or create config")?;
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(config.log_level))
         .init();
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::List(args) => {
-            if args.all {
-                let instances =
-                    utils::with_spinner(commands::inspect_all_instances(), "Listing instances")
-                        .await?;
+    // Instances can be referred to by the name they were created with instead of their ID.
+    let mut command = cli.command;
+    if let Some(id) = command.instance_id_mut() {
+        *id = Instance::resolve_id(&config.namespace, id).await?;
+    }
+    let output = cli.output.unwrap_or(OutputFormat::Json);
+    match command {
+        Commands::List(ListArgs {
+            instance: args,
+            check_updates,
+            all_namespaces,
+        }) => {
+            let format = cli.output.unwrap_or(if args.all {
+                OutputFormat::Table
+            } else {
+                OutputFormat::Json
+            });
+            // CSV goes straight to stdout without spinners so it can be redirected to a file.
+            let csv = format == OutputFormat::Csv;
+            if all_namespaces {
+                let future = commands::inspect_all_namespaces(check_updates);
+                if csv {
+                    let instances: Vec<_> = future.await?.into_values().flatten().collect();
+                    utils::to_csv(&instances, std::io::stdout().lock()).await?;
+                    return Ok(());
+                }
+                let mut namespaces =
+                    utils::with_spinner(future, "Listing instances in all namespaces").await?;
+                if format == OutputFormat::Table {
+                    let instances: Vec<_> = namespaces.into_values().flatten().collect();
+                    println!("\n");
+                    utils::print_instances_table(&instances, std::io::stdout().lock())?;
+                    return Ok(());
+                }
+                if !cli.show_secrets {
+                    namespaces
+                        .values_mut()
+                        .flatten()
+                        .for_each(Instance::redact_secrets);
+                }
+                println!("\n");
+                let namespaces_str = serde_json::to_string_pretty(&namespaces)?;
+                pretty_print("json", &namespaces_str).await?;
+            } else if args.all {
+                let future = commands::inspect_all_instances(check_updates);
+                if csv {
+                    utils::to_csv(&future.await?, std::io::stdout().lock()).await?;
+                    return Ok(());
+                }
+                let mut instances = utils::with_spinner(future, "Listing instances").await?;
+                if format == OutputFormat::Table {
+                    println!("\n");
+                    utils::print_instances_table(&instances, std::io::stdout().lock())?;
+                    return Ok(());
+                }
+                if !cli.show_secrets {
+                    instances.iter_mut().for_each(Instance::redact_secrets);
+                }
                 println!("\n");
                 let instances_str = serde_json::to_string_pretty(&instances)?;
                 pretty_print("json", &instances_str).await?;
             } else if let Some(id) = args.id {
-                let instance = utils::with_spinner(
-                    commands::inspect_instance(&id),
-                    "Getting instance details",
-                )
-                .await?;
+                let future = commands::inspect_instance(&id, check_updates);
+                if csv {
+                    utils::to_csv(&[future.await?], std::io::stdout().lock()).await?;
+                    return Ok(());
+                }
+                let mut instance = utils::with_spinner(future, "Getting instance details").await?;
+                if format == OutputFormat::Table {
+                    println!("\n");
+                    utils::print_instances_table(&[instance], std::io::stdout().lock())?;
+                    return Ok(());
+                }
+                if !cli.show_secrets {
+                    instance.redact_secrets();
+                }
                 println!("\n");
                 let instance_str = serde_json::to_string_pretty(&instance)?;
                 pretty_print("json", &instance_str).await?;
             }
         }
-        Commands::Create { options } => {
-            let instance = utils::with_spinner(
-                commands::create_instance(options.as_ref()),
-                "Creating instance",
+        Commands::Create {
+            options,
+            name,
+            install,
+            demo_content,
+            install_plugins,
+            skip_plugins,
+            import_dump,
+            import_site_url,
+            db_host,
+            db_name,
+            debug,
+            debug_log,
+            debug_display,
+            tail_on_error,
+            no_start,
+            log_max_size,
+            network,
+            labels,
+            nginx_port,
+            adminer_port,
+            read_only,
+            plugins,
+            wordpress_version,
+            php_version,
+            database_engine,
+            redis,
+            mail,
+            expose_mysql_port,
+            tls,
+            memory_mb,
+            cpus,
+            admin,
+            dry_run,
+        } => {
+            let env_vars = commands::create_env_vars(
+                options.as_ref(),
+                commands::CreateFlags {
+                    external_db: db_host.zip(db_name),
+                    debug: DebugSettings {
+                        enabled: debug,
+                        log: debug_log,
+                        display: debug_display,
+                    },
+                    log_max_size,
+                    external_network: network,
+                    labels,
+                    nginx_port,
+                    adminer_port,
+                    read_only,
+                    plugins,
+                    wordpress_version,
+                    php_version,
+                    database_engine,
+                },
+            )?;
+            let env_vars = ContainerEnvVars {
+                admin_user: admin.admin_user.or(env_vars.admin_user),
+                admin_password: admin.admin_password.or(env_vars.admin_password),
+                admin_email: admin.admin_email.or(env_vars.admin_email),
+                site_title: admin.site_title.or(env_vars.site_title),
+                enable_redis: redis || env_vars.enable_redis,
+                enable_mail: mail || env_vars.enable_mail,
+                expose_mysql_port: expose_mysql_port || env_vars.expose_mysql_port,
+                enable_tls: tls || env_vars.enable_tls,
+                name: name.or(env_vars.name),
+                import_dump: import_dump.or(env_vars.import_dump),
+                import_site_url: import_site_url.or(env_vars.import_site_url),
+                memory_mb: memory_mb.or(env_vars.memory_mb),
+                cpus: cpus.or(env_vars.cpus),
+                install_plugins: if skip_plugins {
+                    None
+                } else {
+                    let mut plugins = env_vars.install_plugins.unwrap_or_default();
+                    plugins.extend(install_plugins);
+                    Some(plugins).filter(|plugins| !plugins.is_empty())
+                },
+                ..env_vars
+            };
+            if dry_run {
+                let plan = commands::plan_instance(env_vars).await?;
+                print_output(output, &serde_json::to_value(&plan)?).await?;
+                println!("\n");
+                pretty_print("nginx", &plan.nginx_config).await?;
+                return Ok(());
+            }
+            let output = cli.output.unwrap_or(OutputFormat::Table);
+            if output == OutputFormat::Csv {
+                return Err(csv_unsupported());
+            }
+            let mut instance = commands::create_instance(
+                env_vars,
+                install,
+                demo_content,
+                tail_on_error || config.tail_on_error,
+                !no_start,
             )
             .await?;
+            // Taken before redacting, the summary is where the new logins are shown.
+            let summary = match output {
+                OutputFormat::Json => None,
+                _ => instance.wordpress_data.clone(),
+            };
+            if !cli.show_secrets {
+                instance.redact_secrets();
+            }
             println!("\n");
-            let instance_str = serde_json::to_string_pretty(&instance)?;
-            pretty_print("json", &instance_str).await?;
+            print_instance(output, &instance).await?;
+            if let Some(instance_data) = summary {
+                print_create_summary(&instance_data);
+            }
         }
-        Commands::Start(args) => {
-            if args.all {
-                let instance =
-                    utils::with_spinner(commands::start_all_instances(), "Starting all instances")
-                        .await?;
+        Commands::Start(StartArgs {
+            instance: args,
+            tail_on_error,
+            only_failed,
+            wait,
+            readiness_path,
+        }) => {
+            if args.all && only_failed {
+                let report = commands::retry_failed_instances(false).await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &report).await?;
+            } else if args.all {
+                let instance = commands::start_all_instances().await?;
+                println!("\n");
+                print_output(output, &instance).await?;
             } else if let Some(id) = args.id {
-                let instance =
-                    utils::with_spinner(commands::start_instance(&id), "Starting instance").await?;
+                let instance = commands::tail_on_error(
+                    utils::with_spinner(commands::start_instance(&id), "Starting instance"),
+                    &id,
+                    tail_on_error || config.tail_on_error,
+                )
+                .await?;
+                if wait {
+                    commands::tail_on_error(
+                        utils::with_spinner(
+                            commands::wait_until_ready(&id, readiness_path.as_ref()),
+                            "Waiting for instance",
+                        ),
+                        &id,
+                        tail_on_error || config.tail_on_error,
+                    )
+                    .await?;
+                }
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             }
         }
         Commands::Stop(args) => {
             if args.all {
-                let instance =
-                    utils::with_spinner(commands::stop_all_instances(), "Stopping all instances")
-                        .await?;
+                let instance = commands::stop_all_instances().await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             } else if let Some(id) = args.id {
                 let instance =
                     utils::with_spinner(commands::stop_instance(&id), "Stopping instance").await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             }
         }
-        Commands::Restart(args) => {
-            if args.all {
-                let instance = utils::with_spinner(
-                    commands::restart_all_instances(),
-                    "Restarting all instances",
-                )
-                .await?;
+        Commands::Restart(RestartArgs {
+            instance: args,
+            only_failed,
+        }) => {
+            if args.all && only_failed {
+                let report = commands::retry_failed_instances(true).await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &report).await?;
+            } else if args.all {
+                let instance = commands::restart_all_instances().await?;
+                println!("\n");
+                print_output(output, &instance).await?;
             } else if let Some(id) = args.id {
                 let instance =
                     utils::with_spinner(commands::restart_instance(&id), "Restarting instance")
                         .await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             }
         }
+        Commands::Pause { id } => {
+            let instance =
+                utils::with_spinner(commands::pause_instance(&id), "Pausing instance").await?;
+            println!("\n");
+            print_output(output, &instance).await?;
+        }
+        Commands::Unpause { id } => {
+            let instance =
+                utils::with_spinner(commands::unpause_instance(&id), "Unpausing instance").await?;
+            println!("\n");
+            print_output(output, &instance).await?;
+        }
         Commands::Prune(args) => {
             if args.all {
-                let instance =
-                    utils::with_spinner(commands::delete_all_instances(), "Pruning all instances")
-                        .await?;
+                let instance = commands::delete_all_instances().await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             } else if let Some(id) = args.id {
                 let instance =
                     utils::with_spinner(commands::delete_instance(&id), "Pruning instance").await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             }
         }
-        Commands::Status(args) => {
-            if args.all {
+        Commands::Status(StatusArgs {
+            instance: args,
+            watch,
+            interval,
+        }) => {
+            if watch {
+                let interval = std::time::Duration::from_secs(interval.max(1));
+                commands::watch_statuses(args.id.as_deref(), interval).await?;
+            } else if args.all {
                 let instance =
                     utils::with_spinner(commands::get_all_statuses(), "Getting status").await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
             } else if let Some(id) = args.id {
                 let instance =
                     utils::with_spinner(commands::get_status(&id), "Getting instance status")
                         .await?;
                 println!("\n");
-                let instance_str = serde_json::to_string_pretty(&instance)?;
-                pretty_print("json", &instance_str).await?;
+                print_output(output, &instance).await?;
+            }
+        }
+        Commands::Ports => {
+            let ports = utils::with_spinner(commands::port_map(), "Mapping ports").await?;
+            println!("\n");
+            print_output(output, &ports).await?;
+        }
+        Commands::Stats { id } => {
+            let stats =
+                utils::with_spinner(commands::instance_stats(&id), "Getting instance stats")
+                    .await?;
+            println!("\n");
+            print_output(output, &stats).await?;
+        }
+        Commands::Top => commands::top().await?,
+        Commands::Describe { id } => {
+            let mut description =
+                utils::with_spinner(commands::describe_instance(&id), "Describing instance")
+                    .await?;
+            if !cli.show_secrets {
+                description.config.redact_secrets();
+            }
+            println!("\n");
+            match cli.output.unwrap_or(OutputFormat::Table) {
+                OutputFormat::Table => print_description(&description),
+                output => print_output(output, &serde_json::to_value(&description)?).await?,
+            }
+        }
+        Commands::Doctor => unreachable!("doctor runs before the config is read"),
+        Commands::Wp { id, raw, args } => {
+            let exit_code = commands::wp_cli_passthrough(&id, args, raw).await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+        }
+        Commands::WpcliReload(args) => {
+            let instances = if args.all {
+                utils::with_spinner(
+                    commands::regenerate_all_wpcli_configs(),
+                    "Regenerating wp-cli configs",
+                )
+                .await?
+            } else if let Some(id) = args.id {
+                utils::with_spinner(
+                    commands::regenerate_wpcli_config(&id),
+                    "Regenerating wp-cli config",
+                )
+                .await?
+            } else {
+                Vec::new()
+            };
+            println!("\n");
+            print_output(output, &serde_json::to_value(&instances)?).await?;
+        }
+        Commands::Reset { id, keep_uploads } => {
+            let result = utils::with_spinner(
+                commands::reset_instance(&id, keep_uploads),
+                "Resetting instance",
+            )
+            .await?;
+            println!("\n");
+            print_output(output, &result).await?;
+        }
+        Commands::Permalinks { id, structure } => {
+            let result = utils::with_spinner(
+                commands::set_permalinks(&id, structure.as_ref()),
+                "Setting permalink structure",
+            )
+            .await?;
+            println!("\n");
+            print_output(output, &result).await?;
+        }
+        Commands::Open { id, adminer, print } => {
+            let (url, running) = commands::instance_url(&id, adminer).await?;
+            if !running {
+                eprintln!("warning: {} isn't running, the site may be unreachable", id);
+            }
+            if print {
+                println!("{}", url);
+            } else {
+                open::that(&url).context(format!("Failed to open {}", url))?;
+                println!("Opened {}", url);
+            }
+        }
+        Commands::Admin { id } => {
+            let login =
+                utils::with_spinner(commands::admin_login(&id), "Resolving admin url").await?;
+            println!("\n");
+            if !login.installed {
+                println!("WordPress is not installed yet, opening the installer");
+            }
+            open::that(&login.url).context(format!("Failed to open {}", login.url))?;
+            print_output(output, &serde_json::to_value(&login)?).await?;
+        }
+        Commands::Clone { id } => {
+            let mut instance =
+                utils::with_spinner(commands::clone_instance(&id), "Cloning instance").await?;
+            if !cli.show_secrets {
+                instance.redact_secrets();
+            }
+            println!("\n");
+            print_instance(output, &instance).await?;
+        }
+        Commands::Backup {
+            id,
+            archive,
+            compress,
+            no_compress,
+        } => {
+            let compression = if no_compress {
+                BackupCompression::None
+            } else {
+                compress
+            };
+            utils::with_spinner(
+                commands::backup_instance(&id, &archive, compression),
+                "Backing up instance",
+            )
+            .await?;
+            println!("\nBacked up {} to {}", id, archive.display());
+        }
+        Commands::Restore { archive } => {
+            let mut instance =
+                utils::with_spinner(commands::restore_instance(&archive), "Restoring instance")
+                    .await?;
+            if !cli.show_secrets {
+                instance.redact_secrets();
+            }
+            println!("\n");
+            print_instance(output, &instance).await?;
+        }
+        Commands::Export { id } => {
+            print!("{}", commands::export_compose(&id).await?);
+        }
+        Commands::Logs {
+            id,
+            follow,
+            tail,
+            container,
+        } => {
+            commands::instance_logs(&id, container.as_deref(), follow, tail).await?;
+        }
+        Commands::Credentials { id } => {
+            let credentials = commands::credentials(&id).await?;
+            print_output(output, &serde_json::to_value(&credentials)?).await?;
+        }
+        Commands::Pin { id } => {
+            commands::set_pinned(&id, true).await?;
+            println!("Pinned {}", id);
+        }
+        Commands::Unpin { id } => {
+            commands::set_pinned(&id, false).await?;
+            println!("Unpinned {}", id);
+        }
+        Commands::SetAdminPassword { id, password } => {
+            let login = utils::with_spinner(
+                commands::set_admin_password(&id, password),
+                "Setting admin password",
+            )
+            .await?;
+            println!("\n");
+            print_output(output, &serde_json::to_value(&login)?).await?;
+        }
+        Commands::Cp {
+            source,
+            destination,
+        } => {
+            utils::with_spinner(commands::copy(&source, &destination), "Copying").await?;
+            println!("\nCopied {} to {}", source, destination);
+        }
+        Commands::Import { network, name } => {
+            let mut report = utils::with_spinner(
+                commands::import_instance(&network, name),
+                "Importing instance",
+            )
+            .await?;
+            if !cli.show_secrets {
+                report.instance.redact_secrets();
+            }
+            println!("\n");
+            for warning in &report.warnings {
+                eprintln!("warning: {}", warning);
             }
+            print_instance(output, &report.instance).await?;
+        }
+        Commands::Config {
+            command: ConfigCommands::Profiles,
+        } => {
+            let profiles = config::list_profiles().await?;
+            if profiles.is_empty() {
+                println!("No profiles found, using config.toml");
+            }
+            for profile in profiles {
+                let active = cli.profile.as_deref() == Some(profile.as_str());
+                println!("{}{}", profile, if active { " (active)" } else { "" });
+            }
+        }
+        Commands::Config {
+            command: ConfigCommands::Show { defaults, diff },
+        } => {
+            let config = if defaults {
+                AppConfig::default()
+            } else {
+                config::read_or_create_config().await?
+            };
+            // The table form of the config is the TOML it's written in.
+            let (language, config_str) = match (cli.output.unwrap_or(OutputFormat::Table), diff) {
+                (OutputFormat::Json, false) => ("json", serde_json::to_string_pretty(&config)?),
+                (OutputFormat::Json, true) => {
+                    ("json", serde_json::to_string_pretty(&config.overrides()?)?)
+                }
+                (OutputFormat::Table, false) => ("toml", AppConfig::to_toml(&config)?),
+                (OutputFormat::Table, true) => ("toml", AppConfig::to_toml(&config.overrides()?)?),
+                (OutputFormat::Csv, _) => return Err(csv_unsupported()),
+            };
+            pretty_print(language, &config_str).await?;
         }
     }
 
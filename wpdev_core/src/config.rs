@@ -1,29 +1,76 @@
 use bollard::image::{CreateImageOptions, ListImagesOptions};
-use bollard::network::CreateNetworkOptions;
+use bollard::models::{ImageSummary, Network};
+use bollard::network::{CreateNetworkOptions, ListNetworksOptions};
 use bollard::Docker;
+use flate2::read::GzDecoder;
 use futures::stream::StreamExt;
 use log::{error, info};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use rand::distributions::{Alphanumeric, DistString};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
 use dirs;
 
 use anyhow::{Context, Error as AnyhowError, Result};
 use tokio::fs::{self};
 
-use crate::docker::container::{ContainerEnvVars, ContainerImage, EnvVars};
+use crate::docker::container::{
+    ContainerEnvVars, ContainerImage, EnvVars, LogRotation, ResourceLimits,
+};
 use crate::docker::instance::InstanceData;
+use crate::error::WpdevError;
 use crate::utils;
-use crate::AppConfig;
+use crate::{AppConfig, WpCliDbConnection};
+
+/// Network label recording the namespace an instance was created in.
+pub const NAMESPACE_LABEL: &str = "wpdev.namespace";
+
+/// Length of the UUIDs instances are labelled with.
+pub(crate) const UUID_LEN: usize = 36;
+
+/// Environment variable selecting the config profile used by `read_or_create_config`.
+pub const PROFILE_ENV: &str = "WPDEV_PROFILE";
 
 pub async fn read_or_create_config() -> Result<crate::AppConfig> {
+    let profile = std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty());
+    read_or_create_profile_config(profile.as_deref()).await
+}
+
+/// Reads `config.<profile>.toml` from the config directory, or `config.toml` when no profile is
+/// given. A profile whose file doesn't exist is an error rather than a fallback, since the
+/// fallback's namespace would be another tenant's.
+pub async fn read_or_create_profile_config(profile: Option<&str>) -> Result<crate::AppConfig> {
+    let config = load_profile_config(profile).await?;
+    pull_docker_images_from_config(&config).await?;
+    Ok(config)
+}
+
+/// Reads the config like `read_or_create_config` without pulling images, so it works without
+/// Docker.
+pub async fn load_config() -> Result<crate::AppConfig> {
+    let profile = std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty());
+    load_profile_config(profile.as_deref()).await
+}
+
+async fn load_profile_config(profile: Option<&str>) -> Result<crate::AppConfig> {
     info!("Reading or creating config file");
     let config_dir = get_config_dir().await?;
     fs::create_dir_all(&config_dir)
         .await
         .context("Failed to create config directory")?;
 
-    let config_path = config_dir.join("config.toml");
+    let mut config_path = config_dir.join("config.toml");
+    if let Some(profile) = profile {
+        let profile_path = config_dir.join(format!("config.{}.toml", profile));
+        if !fs::try_exists(&profile_path).await.unwrap_or(false) {
+            return Err(AnyhowError::msg(format!(
+                "Config profile {:?} not found, {:?} doesn't exist",
+                profile, profile_path
+            )));
+        }
+        config_path = profile_path;
+    }
     let default_config_dir = config_dir.join("instances");
 
     match fs::read_to_string(&config_path).await {
@@ -32,14 +79,18 @@ pub async fn read_or_create_config() -> Result<crate::AppConfig> {
             let mut config: AppConfig = toml::from_str(&contents)
                 .with_context(|| format!("Failed to parse config file at {:?}", config_path))?;
             info!("Checking if custom root is set");
-            if config.custom_root.is_none() {
-                info!("Custom root not found in config, setting to default value");
-                config.custom_root = Some(default_config_dir);
+            match &config.custom_root {
+                None => {
+                    info!("Custom root not found in config, setting to default value");
+                    config.custom_root = Some(default_config_dir);
+                }
+                Some(custom_root) if custom_root.is_relative() => {
+                    info!("Resolving relative custom root against home directory");
+                    let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+                    config.custom_root = Some(home_dir.join(custom_root));
+                }
+                Some(_) => {}
             }
-            //TODO: Add a more performant method to check if images have been pulled.
-            // Currently the first time this runs it will block for a while until all images have
-            // been pulled.
-            pull_docker_images_from_config(&config).await?;
             info!("Config file read successfully");
             Ok(config)
         }
@@ -49,13 +100,38 @@ pub async fn read_or_create_config() -> Result<crate::AppConfig> {
                 custom_root: Some(config_dir.join("instances")),
                 ..AppConfig::default()
             };
-            pull_docker_images_from_config(&config).await?;
             info!("Writing default config to {:?}", config_path);
             Ok(config)
         }
     }
 }
 
+/// Lists the names of the config profiles (`config.<profile>.toml`) in the config directory.
+pub async fn list_profiles() -> Result<Vec<String>> {
+    let config_dir = get_config_dir().await?;
+    let mut profiles = Vec::new();
+    let mut entries = match fs::read_dir(&config_dir).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(profiles),
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read config directory")?
+    {
+        let file_name = entry.file_name();
+        if let Some(profile) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("config."))
+            .and_then(|name| name.strip_suffix(".toml"))
+        {
+            profiles.push(profile.to_string());
+        }
+    }
+    profiles.sort();
+    Ok(profiles)
+}
+
 pub(crate) async fn get_config_dir() -> Result<PathBuf> {
     info!("Getting root directory");
     let config_dir = dirs::config_dir().context("Failed to find home directory")?;
@@ -65,7 +141,7 @@ pub(crate) async fn get_config_dir() -> Result<PathBuf> {
 
 pub(crate) async fn get_instance_dir() -> Result<PathBuf> {
     info!("Getting instance directory");
-    let config = read_or_create_config().await?;
+    let config = load_config().await?;
     let instance_dir = config
         .custom_root
         .ok_or_else(|| AnyhowError::msg("Custom root not found"))?;
@@ -75,87 +151,292 @@ pub(crate) async fn get_instance_dir() -> Result<PathBuf> {
 
 pub async fn image_exists(image_name: &str) -> Result<bool> {
     info!("Checking if image {} has been pulled...", image_name);
-    let docker = Docker::connect_with_defaults()?;
+    let docker = crate::docker::connect_docker().await?;
     let options = Some(ListImagesOptions::<String> {
         ..Default::default()
     });
     let images = docker.list_images(options).await?;
-    Ok(images.iter().any(|image| {
-        info!("Checking image {:?}", image);
+    Ok(image_listed(&images, image_name))
+}
+
+fn image_listed(images: &[ImageSummary], image_name: &str) -> bool {
+    let image_name = normalize_image_ref(image_name);
+    images.iter().any(|image| {
         image
             .repo_tags
             .iter()
-            .any(|tag| tag.contains(&image_name.to_string()))
-    }))
+            .any(|tag| normalize_image_ref(tag) == image_name)
+    })
+}
+
+/// `image_ref` the way Docker lists it in `RepoTags`: without the implied Docker Hub registry
+/// and `library/` namespace, and tagged `latest` if it has no tag.
+fn normalize_image_ref(image_ref: &str) -> String {
+    let image_ref = image_ref.strip_prefix("docker.io/").unwrap_or(image_ref);
+    let image_ref = image_ref.strip_prefix("library/").unwrap_or(image_ref);
+    let name = image_ref.rsplit('/').next().unwrap_or(image_ref);
+    if name.contains(':') {
+        image_ref.to_string()
+    } else {
+        format!("{}:latest", image_ref)
+    }
+}
+
+/// Checks whether the registry has a newer build of `image_ref` than the one pulled locally,
+/// by comparing the registry's manifest digest against the local image's repo digests.
+pub async fn image_update_available(image_ref: &str) -> Result<bool> {
+    info!("Checking if image {} is up to date", image_ref);
+    let docker = crate::docker::connect_docker().await?;
+    let local = docker
+        .inspect_image(image_ref)
+        .await
+        .context(format!("Failed to inspect local image {}", image_ref))?;
+    let remote = docker
+        .inspect_registry_image(image_ref, None)
+        .await
+        .context(format!("Failed to query registry for {}", image_ref))?;
+    let remote_digest = remote.descriptor.digest.ok_or_else(|| {
+        AnyhowError::msg(format!("Registry returned no digest for {}", image_ref))
+    })?;
+
+    Ok(!local
+        .repo_digests
+        .unwrap_or_default()
+        .iter()
+        .any(|digest| digest.ends_with(&format!("@{}", remote_digest))))
 }
 
-async fn pull_docker_image(image_name: &str) -> Result<()> {
+pub(crate) async fn pull_docker_image(image_name: &str) -> Result<()> {
     info!("Pulling image {} if it doesn't exist locally", image_name);
     let image = image_exists(image_name).await?;
     if !image {
-        let docker = Docker::connect_with_defaults()?;
-        let options = CreateImageOptions {
-            from_image: image_name,
-            ..Default::default()
-        };
-        let mut stream = docker.create_image(Some(options), None, None);
+        let docker = crate::docker::connect_docker().await?;
+        pull_image(&docker, image_name).await?;
+    }
 
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(_) => {
-                    info!("Image {} pulled successfully", image_name);
-                }
-                Err(err) => {
-                    error!("Error pulling image: {:?}", err);
-                }
+    Ok(())
+}
+
+async fn pull_image(docker: &Docker, image_name: &str) -> Result<()> {
+    let options = CreateImageOptions {
+        from_image: image_name,
+        ..Default::default()
+    };
+    let mut stream = docker.create_image(Some(options), None, None);
+    let mut progress = utils::PullProgress::new(image_name);
+
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(info) => progress.update(&info),
+            Err(err) => {
+                progress.finish(false);
+                return Err(
+                    AnyhowError::new(err).context(format!("Failed to pull image {}", image_name))
+                );
             }
         }
     }
-
+    progress.finish(true);
+    info!("Image {} pulled successfully", image_name);
     Ok(())
 }
 
 pub async fn pull_docker_images_from_config(config: &AppConfig) -> Result<()> {
     info!("Pulling docker images from config");
+    if !config.auto_pull_images {
+        info!("auto_pull_images is disabled, skipping image pull");
+        return Ok(());
+    }
     if config.docker_images.is_empty() {
         info!("No images to pull");
         return Ok(());
     }
 
-    for image_name in config.docker_images.iter() {
+    // List the local images once and check every configured image against that, rather than
+    // listing them again for each image.
+    let docker = crate::docker::connect_docker_host(config.docker_host.as_deref())?;
+    let images = docker
+        .list_images(Some(ListImagesOptions::<String> {
+            ..Default::default()
+        }))
+        .await
+        .context("Failed to list local images")?;
+    let missing: Vec<&String> = config
+        .docker_images
+        .iter()
+        .filter(|image_name| !image_listed(&images, image_name))
+        .collect();
+    if missing.is_empty() {
+        info!("All images already pulled");
+        return Ok(());
+    }
+
+    // Images that fail to pull here are pulled again, and the error reported, when a
+    // container needs them.
+    for image_name in missing {
         info!("Pulling image {}", image_name);
-        pull_docker_image(&image_name)
-            .await
-            .context(format!("Failed to pull image {}", image_name))?;
+        if let Err(e) = pull_image(&docker, image_name).await {
+            error!("{:#}", e);
+        }
     }
-    info!("All images pulled successfully");
+    info!("Finished pulling images");
 
     Ok(())
 }
 
-/// Creates a Docker Network if it doesn't already exist.
-///
-/// # Arguments
-///
-/// * `docker` - &Docker
-/// * `network_name` - name of the network
+/// Creates the instance network unless it's already there, returning whether it was created.
 pub(crate) async fn create_network_if_not_exists(
     docker: &Docker,
     network_prefix: &str,
     id: &str,
-) -> Result<()> {
+) -> Result<bool> {
     info!("Creating network if it doesn't exist");
     let network_name = format!("{}-{}", network_prefix, id);
+    // The `name` filter matches substrings, so compare the names exactly.
+    let existing = docker
+        .list_networks(Some(ListNetworksOptions {
+            filters: HashMap::from([("name", vec![network_name.as_str()])]),
+        }))
+        .await
+        .context("Failed to list networks")?;
+    if existing
+        .iter()
+        .any(|network| network.name.as_deref() == Some(network_name.as_str()))
+    {
+        info!("Network {} already exists", network_name);
+        return Ok(false);
+    }
     let options = CreateNetworkOptions {
         name: network_name,
         driver: "bridge".to_string(),
         check_duplicate: true,
+        labels: HashMap::from([(NAMESPACE_LABEL.to_string(), network_prefix.to_string())]),
         ..Default::default()
     };
     docker
         .create_network(options)
         .await
         .context("Failed to create network")?;
+    Ok(true)
+}
+
+/// Checks that an externally managed network exists before instance containers are attached
+/// to it.
+pub(crate) async fn ensure_network_exists(docker: &Docker, network_name: &str) -> Result<()> {
+    info!("Checking that network {} exists", network_name);
+    docker
+        .inspect_network::<String>(network_name, None)
+        .await
+        .context(format!(
+            "External network {} not found, create it with `docker network create {}`",
+            network_name, network_name
+        ))?;
+    Ok(())
+}
+
+/// Returns the IDs of the instances under `network_prefix` that are attached to an external
+/// network. Those networks aren't named after the instance, so they're found through the
+/// instance directories instead.
+pub(crate) async fn external_network_instances(network_prefix: &str) -> Result<Vec<String>> {
+    let mut instance_ids = Vec::new();
+    for instance_id in recorded_instances(network_prefix).await? {
+        if let Ok(instance_data) = read_instance_data_from_toml(&instance_id).await {
+            if instance_data.external_network.is_some() {
+                instance_ids.push(instance_id);
+            }
+        }
+    }
+    Ok(instance_ids)
+}
+
+/// ID of the instance in `network_prefix` that was given `name` at create, if any.
+pub(crate) async fn instance_id_by_name(
+    network_prefix: &str,
+    name: &str,
+) -> Result<Option<String>> {
+    for instance_id in recorded_instances(network_prefix).await? {
+        if let Ok(instance_data) = read_instance_data_from_toml(&instance_id).await {
+            if instance_data.name.as_deref() == Some(name) {
+                return Ok(Some(instance_id));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `instance_id` belongs to `network_prefix`. Namespaces may contain `-`, so a plain
+/// prefix check would put `wp-network-<uuid>` in `wp` too; since instance labels are UUIDs,
+/// only a single UUID may follow the namespace.
+pub(crate) fn in_namespace(instance_id: &str, network_prefix: &str) -> bool {
+    instance_id
+        .strip_prefix(network_prefix)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|label| label.len() == UUID_LEN)
+}
+
+/// Whether `network` holds an instance of `network_prefix`, going by its namespace label.
+/// Networks created before the label was written fall back to `in_namespace`.
+pub(crate) fn network_in_namespace(network: &Network, network_prefix: &str) -> bool {
+    match network
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(NAMESPACE_LABEL))
+    {
+        Some(namespace) => namespace == network_prefix,
+        None => network
+            .name
+            .as_deref()
+            .is_some_and(|name| in_namespace(name, network_prefix)),
+    }
+}
+
+/// IDs of the instances in `network_prefix` that have an `instance.toml`.
+pub(crate) async fn recorded_instances(network_prefix: &str) -> Result<Vec<String>> {
+    let instance_dir = get_instance_dir().await?;
+    let mut instance_ids = Vec::new();
+    let mut entries = match fs::read_dir(&instance_dir).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(instance_ids),
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read instance directory")?
+    {
+        let instance_id = entry.file_name().to_string_lossy().to_string();
+        if in_namespace(&instance_id, network_prefix) && entry.path().join("instance.toml").exists()
+        {
+            instance_ids.push(instance_id);
+        }
+    }
+    Ok(instance_ids)
+}
+
+/// Site and Adminer ports recorded by the instances in `network_prefix`, including stopped
+/// ones whose ports aren't bound right now.
+pub(crate) async fn recorded_ports(network_prefix: &str) -> Result<HashSet<u32>> {
+    let mut ports = HashSet::new();
+    for instance_id in recorded_instances(network_prefix).await? {
+        if let Ok(instance_data) = read_instance_data_from_toml(&instance_id).await {
+            ports.extend([instance_data.nginx_port, instance_data.adminer_port]);
+            ports.extend(instance_data.mailpit_port);
+            ports.extend(instance_data.mysql_port);
+            ports.extend(instance_data.tls_port);
+        }
+    }
+    ports.remove(&0);
+    Ok(ports)
+}
+
+/// Moves an instance's `instance.toml` aside to `instance.toml.stale`, so it's no longer
+/// listed. Nothing else in the instance directory is touched.
+pub(crate) async fn mark_instance_stale(instance_id: &str) -> Result<()> {
+    let instance_path = get_instance_dir().await?.join(instance_id);
+    let instance_toml = instance_path.join("instance.toml");
+    fs::rename(&instance_toml, instance_path.join("instance.toml.stale"))
+        .await
+        .context(format!("Failed to move {:?} aside", instance_toml))?;
+    info!("Marked instance {} as stale", instance_id);
     Ok(())
 }
 
@@ -182,11 +463,90 @@ fn merge_env_vars(
         .collect()
 }
 
+/// Env vars that must agree between containers, as `[mysql, wordpress, adminer]` keys for the
+/// database user, password and name.
+const SHARED_DB_VARS: [[&str; 3]; 3] = [
+    [
+        "MYSQL_USER",
+        "WORDPRESS_DB_USER",
+        "ADMINER_DEFAULT_USERNAME",
+    ],
+    [
+        "MYSQL_PASSWORD",
+        "WORDPRESS_DB_PASSWORD",
+        "ADMINER_DEFAULT_PASSWORD",
+    ],
+    [
+        "MYSQL_DATABASE",
+        "WORDPRESS_DB_NAME",
+        "ADMINER_DEFAULT_DATABASE",
+    ],
+];
+
+/// Returns the MySQL, WordPress and Adminer overrides with the shared database credentials
+/// synced, so overriding e.g. `MYSQL_PASSWORD` also sets `WORDPRESS_DB_PASSWORD` and
+/// `ADMINER_DEFAULT_PASSWORD`. Fails if the overrides set different values for the same
+/// credential.
+fn sync_db_overrides(
+    user_env_vars: &ContainerEnvVars,
+) -> Result<[HashMap<String, String>; 3], AnyhowError> {
+    let engine = user_env_vars.database_engine;
+    let mut overrides = [
+        user_env_vars.mysql.clone().unwrap_or_default(),
+        user_env_vars.wordpress.clone().unwrap_or_default(),
+        user_env_vars.adminer.clone().unwrap_or_default(),
+    ];
+    for [mysql_key, wordpress_key, adminer_key] in SHARED_DB_VARS {
+        let database_key = engine.env_var(mysql_key);
+        let keys = [database_key.as_str(), wordpress_key, adminer_key];
+        let values: Vec<&String> = overrides
+            .iter()
+            .zip(keys)
+            .filter_map(|(vars, key)| vars.get(key))
+            .collect();
+        let value = match values.first() {
+            Some(value) => value.to_string(),
+            None => continue,
+        };
+        if values.iter().any(|other| **other != value) {
+            return Err(AnyhowError::msg(format!(
+                "Conflicting overrides for {}, set them to the same value or only set one",
+                keys.join(", ")
+            )));
+        }
+        info!("Syncing {} across containers", keys.join(", "));
+        for (vars, key) in overrides.iter_mut().zip(keys) {
+            vars.insert(key.to_string(), value.clone());
+        }
+    }
+    Ok(overrides)
+}
+
+/// Password used for every instance when `use_random_passwords` is turned off.
+const STATIC_PASSWORD: &str = "password";
+
+/// A 24 character alphanumeric password from the thread-local CSPRNG.
+fn random_password() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 24)
+}
+
 pub(crate) async fn initialize_env_vars(
     instance_label: &str,
     user_env_vars: &ContainerEnvVars,
 ) -> Result<EnvVars, AnyhowError> {
     info!("Initializing environment variables");
+    let config = load_config().await?;
+    let (db_password, root_password) = if config.use_random_passwords {
+        info!("Generating database passwords");
+        (random_password(), random_password())
+    } else {
+        (STATIC_PASSWORD.to_string(), STATIC_PASSWORD.to_string())
+    };
+    let database_host = format!(
+        "{}-{}",
+        instance_label,
+        user_env_vars.database_engine.image().to_string()
+    );
     let default_adminer_vars = HashMap::from([
         ("ADMINER_DESIGN".to_string(), "nette".to_string()),
         (
@@ -194,71 +554,341 @@ pub(crate) async fn initialize_env_vars(
             "tables-filter tinymce".to_string(),
         ),
         ("MYSQL_PORT".to_string(), "3306".to_string()),
-        (
-            "ADMINER_DEFAULT_SERVER".to_string(),
-            format!("{}-{}", instance_label, ContainerImage::MySQL.to_string()).to_string(),
-        ),
+        ("ADMINER_DEFAULT_SERVER".to_string(), database_host.clone()),
         (
             "ADMINER_DEFAULT_USERNAME".to_string(),
             "wordpress".to_string(),
         ),
-        (
-            "ADMINER_DEFAULT_PASSWORD".to_string(),
-            "password".to_string(),
-        ),
+        ("ADMINER_DEFAULT_PASSWORD".to_string(), db_password.clone()),
         (
             "ADMINER_DEFAULT_DATABASE".to_string(),
             "wordpress".to_string(),
         ),
     ]);
 
+    let engine = user_env_vars.database_engine;
     let default_mysql_vars = HashMap::from([
-        ("MYSQL_ROOT_PASSWORD".to_string(), "password".to_string()),
-        ("MYSQL_DATABASE".to_string(), "wordpress".to_string()),
-        ("MYSQL_USER".to_string(), "wordpress".to_string()),
-        ("MYSQL_PASSWORD".to_string(), "password".to_string()),
+        (engine.env_var("MYSQL_ROOT_PASSWORD"), root_password),
+        (engine.env_var("MYSQL_DATABASE"), "wordpress".to_string()),
+        (engine.env_var("MYSQL_USER"), "wordpress".to_string()),
+        (engine.env_var("MYSQL_PASSWORD"), db_password.clone()),
     ]);
 
     let default_wordpress_vars = HashMap::from([
-        (
-            "WORDPRESS_DB_HOST".to_string(),
-            format!("{}-{}", instance_label, ContainerImage::MySQL.to_string()).to_string(),
-        ),
+        ("WORDPRESS_DB_HOST".to_string(), database_host),
         ("WORDPRESS_DB_USER".to_string(), "wordpress".to_string()),
-        ("WORDPRESS_DB_PASSWORD".to_string(), "password".to_string()),
+        ("WORDPRESS_DB_PASSWORD".to_string(), db_password),
         ("WORDPRESS_DB_NAME".to_string(), "wordpress".to_string()),
         ("WORDPRESS_TABLE_PREFIX".to_string(), "wp_".to_string()),
-        ("WORDPRESS_DEBUG".to_string(), "1".to_string()),
-        ("WORDPRESS_CONFIG_EXTRA".to_string(), "".to_string()),
+        (
+            "WORDPRESS_DEBUG".to_string(),
+            // The image treats any non-empty value as enabled.
+            if user_env_vars.debug.enabled { "1" } else { "" }.to_string(),
+        ),
+        (
+            "WORDPRESS_CONFIG_EXTRA".to_string(),
+            wordpress_config_extra(instance_label, user_env_vars),
+        ),
     ]);
 
-    let adminer_env_vars = merge_env_vars(default_adminer_vars, &None);
-    let mysql_env_vars = merge_env_vars(default_mysql_vars, &None);
-    let wordpress_env_vars = merge_env_vars(default_wordpress_vars, &user_env_vars.wordpress);
+    let mut default_adminer_vars = default_adminer_vars;
+    let mut default_wordpress_vars = default_wordpress_vars;
+    if user_env_vars.enable_mail {
+        default_wordpress_vars.insert(
+            "WORDPRESS_SMTP_HOST".to_string(),
+            format!("{}-{}", instance_label, ContainerImage::Mailpit.to_string()),
+        );
+        default_wordpress_vars.insert("WORDPRESS_SMTP_PORT".to_string(), "1025".to_string());
+    }
+    if let Some(external_db) = &user_env_vars.external_db {
+        info!("Using external database {}", external_db.host);
+        for (vars, host_key, name_key) in [
+            (
+                &mut default_adminer_vars,
+                "ADMINER_DEFAULT_SERVER",
+                "ADMINER_DEFAULT_DATABASE",
+            ),
+            (
+                &mut default_wordpress_vars,
+                "WORDPRESS_DB_HOST",
+                "WORDPRESS_DB_NAME",
+            ),
+        ] {
+            vars.insert(host_key.to_string(), external_db.host.clone());
+            vars.insert(name_key.to_string(), external_db.name.clone());
+        }
+    }
+
+    let [mysql_overrides, wordpress_overrides, adminer_overrides] =
+        sync_db_overrides(user_env_vars)?;
+    let adminer_env_vars = merge_env_vars(default_adminer_vars, &Some(adminer_overrides));
+    let mysql_env_vars = merge_env_vars(default_mysql_vars, &Some(mysql_overrides));
+    let wordpress_env_vars = merge_env_vars(default_wordpress_vars, &Some(wordpress_overrides));
+
+    let limits = container_limits(&config, user_env_vars)?;
+    let log_rotation = LogRotation {
+        max_size: user_env_vars
+            .log_max_size
+            .clone()
+            .unwrap_or(config.log_max_size),
+        max_files: user_env_vars.log_max_files.unwrap_or(config.log_max_files),
+    };
+
+    let network_name = user_env_vars
+        .external_network
+        .clone()
+        .unwrap_or_else(|| format!("{}-{}", config.namespace, instance_label));
 
     Ok(EnvVars {
         adminer: adminer_env_vars,
         mysql: mysql_env_vars,
         wordpress: wordpress_env_vars,
+        log_rotation,
+        network_name,
+        read_only: user_env_vars.read_only,
+        plugins: resolve_plugins(user_env_vars.plugins.as_deref().unwrap_or_default())?,
+        wordpress_tag: wordpress_tag(user_env_vars)?,
+        database_engine: user_env_vars.database_engine,
+        limits,
+        restart_policy: config.restart_policy,
+        user: format!("{}:{}", config.container_uid, config.container_gid),
     })
 }
 
-pub(crate) async fn generate_nginx_config(
-    instance_label: &str,
+/// The limits of each service the instance runs: `container_limits` from the payload or the
+/// config, with `memory_mb` and `cpus` from the payload replacing them.
+fn container_limits(
+    config: &AppConfig,
+    user_env_vars: &ContainerEnvVars,
+) -> Result<BTreeMap<String, ResourceLimits>> {
+    // Docker refuses memory limits below 6MB.
+    if user_env_vars
+        .memory_mb
+        .is_some_and(|memory_mb| memory_mb < 6)
+    {
+        return Err(AnyhowError::msg("memory_mb must be at least 6"));
+    }
+    if user_env_vars.cpus.is_some_and(|cpus| cpus <= 0.0) {
+        return Err(AnyhowError::msg("cpus must be greater than 0"));
+    }
+    let configured = user_env_vars
+        .container_limits
+        .as_ref()
+        .unwrap_or(&config.container_limits);
+    let overrides = ResourceLimits {
+        memory_mb: user_env_vars.memory_mb,
+        cpus: user_env_vars.cpus,
+    };
+
+    let mut services = vec![
+        ContainerImage::Wordpress,
+        ContainerImage::Nginx,
+        ContainerImage::Adminer,
+    ];
+    if user_env_vars.external_db.is_none() {
+        services.push(user_env_vars.database_engine.image());
+    }
+    if user_env_vars.enable_redis {
+        services.push(ContainerImage::Redis);
+    }
+    if user_env_vars.enable_mail {
+        services.push(ContainerImage::Mailpit);
+    }
+    Ok(services
+        .into_iter()
+        .map(|image| {
+            let service = image.to_string();
+            let limits = configured
+                .get(&service)
+                .copied()
+                .unwrap_or_default()
+                .overridden_by(overrides);
+            (service, limits)
+        })
+        .filter(|(_, limits)| !limits.is_unlimited())
+        .collect())
+}
+
+/// PHP for `WORDPRESS_CONFIG_EXTRA`: the debug settings and, with Redis enabled, the host the
+/// Redis Object Cache plugin connects to. With mail enabled, a `phpmailer_init` hook sends
+/// mail to the SMTP server in `WORDPRESS_SMTP_HOST`/`WORDPRESS_SMTP_PORT`. Plugins aren't
+/// loaded yet in wp-config.php, so the hook is preregistered through `$wp_filter`.
+fn wordpress_config_extra(instance_label: &str, user_env_vars: &ContainerEnvVars) -> String {
+    let mut extra = format!(
+        "define('WP_DEBUG_LOG', {});\ndefine('WP_DEBUG_DISPLAY', {});",
+        user_env_vars.debug.log, user_env_vars.debug.display
+    );
+    if user_env_vars.enable_redis {
+        extra.push_str(&format!(
+            "\ndefine('WP_REDIS_HOST', '{}-{}');",
+            instance_label,
+            ContainerImage::Redis.to_string()
+        ));
+    }
+    if user_env_vars.enable_mail {
+        extra.push_str(concat!(
+            "\n$GLOBALS['wp_filter']['phpmailer_init'][10]['wpdev_smtp'] = array(",
+            "'function' => function ($phpmailer) {",
+            " $phpmailer->isSMTP();",
+            " $phpmailer->Host = getenv_docker('WORDPRESS_SMTP_HOST', '');",
+            " $phpmailer->Port = (int) getenv_docker('WORDPRESS_SMTP_PORT', '25');",
+            " $phpmailer->SMTPAuth = false;",
+            " $phpmailer->SMTPAutoTLS = false;",
+            " }, 'accepted_args' => 1);",
+        ));
+    }
+    extra
+}
+
+/// The WordPress image tag for `wordpress_version` and `php_version`, following the official
+/// image's `<version>-php<php>-apache` scheme. `None` when neither is set.
+fn wordpress_tag(user_env_vars: &ContainerEnvVars) -> Result<Option<String>> {
+    let version = validated_tag_part("wordpress_version", &user_env_vars.wordpress_version)?;
+    let php = validated_tag_part("php_version", &user_env_vars.php_version)?;
+    if let Some(php) = php {
+        if !php.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return Err(AnyhowError::msg(format!(
+                "php_version {:?} should look like 8.2",
+                php
+            )));
+        }
+    }
+    Ok(match (version, php) {
+        (None, None) => None,
+        (Some(version), None) => Some(version.to_string()),
+        (None, Some(php)) => Some(format!("php{}-apache", php)),
+        (Some(version), Some(php)) => Some(format!("{}-php{}-apache", version, php)),
+    })
+}
+
+/// Splits a `wordpress` image reference back into the `wordpress_version` and `php_version`
+/// that `wordpress_tag` would compose it from.
+pub(crate) fn split_wordpress_tag(image: &str) -> (Option<String>, Option<String>) {
+    let tag = match image.rsplit_once(':') {
+        Some((_, tag)) if !tag.contains('/') && tag != "latest" => tag,
+        _ => return (None, None),
+    };
+    let tag = tag.strip_suffix("-apache").unwrap_or(tag);
+    match tag.split_once("php") {
+        Some((version, php)) => {
+            let version = version.trim_end_matches('-');
+            (
+                (!version.is_empty()).then(|| version.to_string()),
+                Some(php.to_string()),
+            )
+        }
+        None => (Some(tag.to_string()), None),
+    }
+}
+
+fn validated_tag_part<'a>(name: &str, value: &'a Option<String>) -> Result<Option<&'a str>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(AnyhowError::msg(format!("{} can't be empty", name)));
+    }
+    if value.contains(|c: char| c.is_whitespace() || c == ':' || c == '/') {
+        return Err(AnyhowError::msg(format!(
+            "{} {:?} isn't a valid image tag",
+            name, value
+        )));
+    }
+    Ok(Some(value))
+}
+
+/// The directory name a plugin is mounted under in `wp-content/plugins`.
+pub(crate) fn plugin_name(plugin: &Path) -> String {
+    plugin
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Canonicalizes the plugin directories to mount, failing if one doesn't exist or two share a
+/// directory name.
+fn resolve_plugins(plugins: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut resolved: Vec<PathBuf> = Vec::new();
+    for plugin in plugins {
+        let path = std::fs::canonicalize(plugin)
+            .context(format!("Plugin directory {:?} not found", plugin))?;
+        if !path.is_dir() {
+            return Err(AnyhowError::msg(format!(
+                "Plugin {:?} is not a directory",
+                plugin
+            )));
+        }
+        let name = plugin_name(&path);
+        if resolved.iter().any(|other| plugin_name(other) == name) {
+            return Err(AnyhowError::msg(format!(
+                "More than one plugin directory is named {}",
+                name
+            )));
+        }
+        resolved.push(path);
+    }
+    Ok(resolved)
+}
+
+/// Renders the nginx config that proxies an instance's port to WordPress and Adminer, and
+/// `tls_port` over HTTPS to WordPress when set. A `template` has `{nginx_port}`,
+/// `{tls_port}`, `{wordpress_name}`, `{adminer_name}` and `{adminer_listen_port}` replaced,
+/// otherwise the built-in config is used.
+pub(crate) fn render_nginx_config(
+    template: Option<&str>,
     nginx_port: u32,
+    tls_port: Option<u32>,
+    adminer_listen_port: u16,
     adminer_name: &str,
     wordpress_name: &str,
-    instance_dir: &PathBuf,
-) -> Result<PathBuf, AnyhowError> {
-    info!("Generating nginx config");
-    let nginx_config = format!(
+) -> String {
+    if let Some(template) = template {
+        return template
+            .replace("{nginx_port}", &nginx_port.to_string())
+            .replace(
+                "{tls_port}",
+                &tls_port.map(|port| port.to_string()).unwrap_or_default(),
+            )
+            .replace("{wordpress_name}", wordpress_name)
+            .replace("{adminer_name}", adminer_name)
+            .replace("{adminer_listen_port}", &adminer_listen_port.to_string());
+    }
+    let tls_server = match tls_port {
+        Some(tls_port) => format!(
+            r#"
+server {{
+    listen {tls_port} ssl;
+    server_name localhost;
+
+    ssl_certificate {tls_dir}/cert.pem;
+    ssl_certificate_key {tls_dir}/key.pem;
+
+    location / {{
+        proxy_pass http://{wordpress_name}:80;
+        proxy_set_header Host $host:$server_port;
+        proxy_set_header X-Real-IP $remote_addr;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+        proxy_set_header X-Forwarded-Proto $scheme;
+    }}
+}}
+"#,
+            tls_port = tls_port,
+            tls_dir = crate::docker::config::NGINX_TLS_DIR,
+            wordpress_name = wordpress_name,
+        ),
+        None => String::new(),
+    };
+    format!(
         r#"
 server {{
     listen {nginx_port};
     server_name localhost;
 
+    # Pass the original request URI through untouched so WordPress can route
+    # pretty permalinks through its own front controller.
     location / {{
-        proxy_pass http://{wordpress_name}:80/;
+        proxy_pass http://{wordpress_name}:80;
         proxy_set_header Host $host:$server_port;
         proxy_set_header X-Real-IP $remote_addr;
         proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
@@ -267,7 +897,7 @@ server {{
 }}
 
 server {{
-    listen 8080;
+    listen {adminer_listen_port};
     server_name localhost;
 
     location / {{
@@ -278,41 +908,190 @@ server {{
         proxy_set_header X-Forwarded-Proto $scheme;
     }}
 }}
+{tls_server}
         "#,
+        tls_server = tls_server,
         nginx_port = nginx_port,
+        adminer_listen_port = adminer_listen_port,
         wordpress_name = wordpress_name,
         adminer_name = adminer_name,
-    );
+    )
+}
 
-    let instance_path = instance_dir.join("nginx");
-    utils::create_path(&instance_path)
+/// Reads `nginx_template_path` from the config, resolving a relative path against the config
+/// directory. `None` when no template is configured.
+pub(crate) async fn read_nginx_template(config: &AppConfig) -> Result<Option<String>> {
+    let template_path = match &config.nginx_template_path {
+        Some(path) if path.is_relative() => get_config_dir().await?.join(path),
+        Some(path) => path.clone(),
+        None => return Ok(None),
+    };
+    info!("Reading nginx template from {:?}", template_path);
+    let template = fs::read_to_string(&template_path).await.context(format!(
+        "Failed to read nginx template at {:?}",
+        template_path
+    ))?;
+    Ok(Some(template))
+}
+
+/// `url` with `http://` replaced by `https://`.
+fn https_url(url: &str) -> String {
+    match url.strip_prefix("http://") {
+        Some(rest) => format!("https://{}", rest),
+        None => url.to_string(),
+    }
+}
+
+/// Marks the start of the block of `/etc/hosts` entries wpdev manages.
+const HOSTS_START_MARKER: &str = "# START WP_DEV";
+/// Marks the end of the block of `/etc/hosts` entries wpdev manages.
+const HOSTS_END_MARKER: &str = "# END WP_DEV";
+
+/// A change to the wpdev block of the hosts file.
+pub(crate) enum HostsAction<'a> {
+    /// Points `{label}.local` at `127.0.0.1` for the instance.
+    Add {
+        instance_id: &'a str,
+        instance_label: &'a str,
+    },
+    /// Drops the instance's entry.
+    Remove { instance_id: &'a str },
+    /// Drops the whole wpdev block.
+    Purge,
+}
+
+fn hosts_file_path() -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(r"C:\Windows\System32\drivers\etc\hosts")
+    } else {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+/// Returns `hosts` with `action` applied to the block between `# START WP_DEV` and
+/// `# END WP_DEV`. Entries end in `# {instance_id}` so they can be removed again, and the
+/// block is dropped once it's empty. Lines outside the block are left alone.
+fn apply_hosts_action(hosts: &str, action: &HostsAction) -> String {
+    let mut before = Vec::new();
+    let mut entries = Vec::new();
+    let mut after = Vec::new();
+    let mut section = 0;
+    for line in hosts.lines() {
+        match (section, line.trim()) {
+            (0, HOSTS_START_MARKER) => section = 1,
+            (1, HOSTS_END_MARKER) => section = 2,
+            (0, _) => before.push(line),
+            (1, _) => entries.push(line.to_string()),
+            _ => after.push(line),
+        }
+    }
+
+    match action {
+        HostsAction::Add {
+            instance_id,
+            instance_label,
+        } => {
+            let suffix = format!("# {}", instance_id);
+            entries.retain(|entry| !entry.ends_with(&suffix));
+            entries.push(format!("127.0.0.1 {}.local {}", instance_label, suffix));
+        }
+        HostsAction::Remove { instance_id } => {
+            let suffix = format!("# {}", instance_id);
+            entries.retain(|entry| !entry.ends_with(&suffix));
+        }
+        HostsAction::Purge => entries.clear(),
+    }
+
+    let mut lines: Vec<String> = before.into_iter().map(str::to_string).collect();
+    if !entries.is_empty() {
+        lines.push(HOSTS_START_MARKER.to_string());
+        lines.extend(entries);
+        lines.push(HOSTS_END_MARKER.to_string());
+    }
+    lines.extend(after.into_iter().map(str::to_string));
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Applies `action` to the hosts file. Writing it needs root (or an administrator on
+/// Windows), which is reported as such rather than as a bare I/O error.
+pub(crate) async fn update_hosts_file(action: HostsAction<'_>) -> Result<()> {
+    let path = hosts_file_path();
+    let hosts = fs::read_to_string(&path)
         .await
-        .context("Failed to create nginx directory")?;
-    let nginx_config_path = instance_path.join(format!("{}-nginx.conf", instance_label));
-    fs::write(&nginx_config_path, nginx_config)
+        .context(format!("Failed to read {}", path.display()))?;
+    let updated = apply_hosts_action(&hosts, &action);
+    if updated == hosts {
+        return Ok(());
+    }
+    info!("Updating {}", path.display());
+    // Written in place rather than renamed over, since the hosts file is often a bind mount.
+    match fs::write(&path, updated).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Err(AnyhowError::msg(format!(
+                "Permission denied writing {}: run wpdev as root or set manage_hosts_file = false",
+                path.display()
+            )))
+        }
+        Err(e) => Err(e).context(format!("Failed to write {}", path.display())),
+    }
+}
+
+/// Applies `action` to the hosts file when `manage_hosts_file` is enabled. Failures are only
+/// logged, a missing hosts entry shouldn't undo creating or deleting the instance.
+pub(crate) async fn sync_hosts_file(action: HostsAction<'_>) {
+    match load_config().await {
+        Ok(config) if config.manage_hosts_file => {
+            if let Err(e) = update_hosts_file(action).await {
+                error!("Failed to update hosts file: {:#}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to read config for the hosts file: {:#}", e),
+    }
+}
+
+/// Directory of the instance's self-signed certificate, mounted into the nginx container.
+pub(crate) fn tls_dir(instance_dir: &Path) -> PathBuf {
+    instance_dir.join("certs")
+}
+
+/// Path of an instance's nginx config inside its instance directory.
+pub(crate) fn nginx_config_path(instance_label: &str, instance_dir: &Path) -> PathBuf {
+    instance_dir
+        .join("nginx")
+        .join(format!("{}-nginx.conf", instance_label))
+}
+
+pub(crate) async fn write_nginx_config(
+    nginx_config: &str,
+    nginx_config_path: &PathBuf,
+) -> Result<(), AnyhowError> {
+    info!("Writing nginx config");
+    if let Some(nginx_dir) = nginx_config_path.parent() {
+        utils::create_path(&nginx_dir.to_path_buf())
+            .await
+            .context("Failed to create nginx directory")?;
+    }
+    fs::write(nginx_config_path, nginx_config)
         .await
         .context(format!(
             "Failed to write nginx config to {:?}",
             nginx_config_path
         ))?;
-
-    Ok(nginx_config_path)
+    Ok(())
 }
 
+/// Writes `wp-cli.local.yml` and `wp-cli.local.php` to `instance_dir`, overwriting any
+/// existing ones. `wordpress_env_vars` are the `KEY=value` env vars of the WordPress container.
 pub(crate) async fn generate_wpcli_config(
-    config: &PathBuf,
+    instance_dir: &Path,
     instance_label: &str,
-    home_dir: &PathBuf,
+    wordpress_env_vars: &[String],
 ) -> Result<(), AnyhowError> {
     info!("Generating wp-cli config");
-    let instance_dir = home_dir.join(format!(
-        "{}/{}-{}/",
-        &config
-            .to_str()
-            .ok_or_else(|| AnyhowError::msg("Config directory not found"))?,
-        crate::NETWORK_NAME,
-        instance_label
-    ));
     let wpcli_yml = format!(
         r#"path: wordpress
 require:
@@ -320,24 +1099,48 @@ require:
         "#,
     );
 
+    // Use the same database settings the WordPress container was created with.
+    let env_value = |key: &str, default: &str| {
+        wordpress_env_vars
+            .iter()
+            .find_map(|var| var.strip_prefix(&format!("{}=", key)))
+            .unwrap_or(default)
+            .replace('\\', "\\\\")
+            .replace('\'', "\\'")
+    };
+    let db_host = match read_or_create_config().await?.wpcli_db_connection {
+        WpCliDbConnection::Tcp => env_value(
+            "WORDPRESS_DB_HOST",
+            &format!("{}-{}", instance_label, ContainerImage::MySQL.to_string()),
+        ),
+        WpCliDbConnection::Socket => format!(
+            "localhost:{}",
+            instance_dir
+                .join("mysql/mysqld.sock")
+                .to_str()
+                .ok_or_else(|| AnyhowError::msg("Instance directory not found"))?
+        ),
+    };
+
     let wpcli_php = format!(
         r#"<?php
 
-define('DB_HOST', 'localhost:{instance_dir}mysql/mysqld.sock');
-define('DB_NAME', 'wordpress');
-define('DB_USER', 'wordpress');
-define('DB_PASSWORD', 'password');
+define('DB_HOST', '{db_host}');
+define('DB_NAME', '{db_name}');
+define('DB_USER', '{db_user}');
+define('DB_PASSWORD', '{db_password}');
 
 // disables errors when using wp-cli
 error_reporting(E_ERROR);
 define('WP_DEBUG', false);
         "#,
-        instance_dir = instance_dir
-            .to_str()
-            .ok_or_else(|| AnyhowError::msg("Instance directory not found"))?,
+        db_host = db_host,
+        db_name = env_value("WORDPRESS_DB_NAME", "wordpress"),
+        db_user = env_value("WORDPRESS_DB_USER", "wordpress"),
+        db_password = env_value("WORDPRESS_DB_PASSWORD", STATIC_PASSWORD),
     );
 
-    utils::create_path(&instance_dir)
+    utils::create_path(&instance_dir.to_path_buf())
         .await
         .context("Failed to create instance directory")?;
     let wpcli_yml_path = instance_dir.join("wp-cli.local.yml");
@@ -366,16 +1169,16 @@ pub async fn read_instance_data_from_toml(instance_label: &str) -> Result<Instan
 
     if !instance_dir.exists() {
         error!("Instance file not found at {:?}", instance_dir);
-        return Err(AnyhowError::msg(format!(
-            "Instance file not found at {:?}",
-            instance_dir
-        )));
+        return Err(WpdevError::NotFound(format!("Instance {}", instance_label)).into());
     }
 
-    let contents = fs::read_to_string(&instance_dir).await.context(format!(
-        "Failed to read instance file at {:?}",
-        instance_dir
-    ))?;
+    let contents = fs::read_to_string(&instance_dir)
+        .await
+        .map_err(WpdevError::from)
+        .context(format!(
+            "Failed to read instance file at {:?}",
+            instance_dir
+        ))?;
 
     let instance_data: InstanceData = toml::from_str(&contents).context(format!(
         "Failed to parse instance data from file at {:?}",
@@ -385,47 +1188,193 @@ pub async fn read_instance_data_from_toml(instance_label: &str) -> Result<Instan
     Ok(instance_data)
 }
 
+/// Overwrites an existing instance's `instance.toml`.
+pub(crate) async fn write_instance_data_to_toml(
+    instance_id: &str,
+    instance_data: &InstanceData,
+) -> Result<()> {
+    let instance_config_dir = get_instance_dir().await?;
+    let instance_toml = instance_config_dir.join(format!("{}/instance.toml", instance_id));
+    fs::write(&instance_toml, toml::to_string(instance_data)?)
+        .await
+        .context(format!(
+            "Failed to write instance data to {:?}",
+            instance_toml
+        ))?;
+    info!("Instance data written to {:?}", instance_toml);
+    Ok(())
+}
+
+/// Opens a SQL dump for reading, decompressing `.gz` dumps on the fly.
+pub(crate) fn open_sql_dump(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = std::fs::File::open(path).context(format!("Failed to open SQL dump {:?}", path))?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Fails if the dump at `path` creates or switches to a database other than `database`, e.g.
+/// one taken with `mysqldump --databases`, since its tables would end up where WordPress
+/// doesn't look.
+pub(crate) fn check_sql_dump(path: &Path, database: &str) -> Result<()> {
+    info!("Checking SQL dump {:?}", path);
+    let mut reader = open_sql_dump(path)?;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .context(format!("Failed to read SQL dump {:?}", path))?;
+        if read == 0 {
+            return Ok(());
+        }
+        if let Some(name) = dump_database_name(&String::from_utf8_lossy(&line)) {
+            if name != database {
+                return Err(AnyhowError::msg(format!(
+                    "SQL dump {:?} uses database `{}` but the instance's database is `{}`, dump it without --databases or remove its CREATE DATABASE and USE statements",
+                    path, name, database
+                )));
+            }
+        }
+    }
+}
+
+/// The database a dump line creates or switches to with `CREATE DATABASE` or `USE`.
+fn dump_database_name(line: &str) -> Option<String> {
+    let line = line.trim();
+    let upper = line.to_ascii_uppercase();
+    let rest = if upper.starts_with("USE ") {
+        &line["USE ".len()..]
+    } else if upper.starts_with("CREATE DATABASE ") {
+        &line["CREATE DATABASE ".len()..]
+    } else {
+        return None;
+    };
+    if let Some((_, quoted)) = rest.split_once('`') {
+        return quoted.split('`').next().map(str::to_string);
+    }
+    rest.split_whitespace()
+        .find(|word| !["IF", "NOT", "EXISTS"].contains(&word.to_ascii_uppercase().as_str()))
+        .map(|name| name.trim_end_matches(';').to_string())
+}
+
+pub(crate) fn extract_value(vars: &[String], key: &str) -> String {
+    info!("Extracting value for key {}", key);
+    vars.iter()
+        .find_map(|s| {
+            let parts: Vec<&str> = s.splitn(2, '=').collect();
+            if parts.len() == 2 && parts[0] == key {
+                Some(parts[1].to_string())
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| "defaultValue".to_string())
+}
+
+/// Where a new instance lives and the host ports it was given, for `parse_instance_data`.
+pub(crate) struct InstanceDataOptions<'a> {
+    pub namespace: &'a str,
+    pub instance_label: &'a str,
+    pub nginx_port: u32,
+    pub adminer_port: u32,
+    pub mailpit_port: Option<u32>,
+    pub mysql_port: Option<u32>,
+    pub tls_port: Option<u32>,
+}
+
 pub(crate) async fn parse_instance_data(
     env_vars: &EnvVars,
-    nginx_port: &u32,
-    adminer_port: &u32,
-    instance_label: &str,
+    user_env_vars: &ContainerEnvVars,
+    options: &InstanceDataOptions<'_>,
 ) -> Result<InstanceData> {
     info!("Parsing instance data");
+    let InstanceDataOptions {
+        namespace,
+        instance_label,
+        nginx_port,
+        adminer_port,
+        mailpit_port,
+        mysql_port,
+        tls_port,
+    } = *options;
     let instance_config_dir = get_instance_dir().await?;
     let config = read_or_create_config().await?;
-    let instance_dir = instance_config_dir.join(format!(
-        "{}-{}/instance.toml",
-        crate::NETWORK_NAME,
-        instance_label
-    ));
-
-    fn extract_value(vars: &Vec<String>, key: &str) -> String {
-        info!("Extracting value for key {}", key);
-        vars.iter()
-            .find_map(|s| {
-                let parts: Vec<&str> = s.splitn(2, '=').collect();
-                if parts.len() == 2 && parts[0] == key {
-                    Some(parts[1].to_string())
-                } else {
-                    None
-                }
-            })
-            .unwrap_or_else(|| "defaultValue".to_string())
+    let instance_dir =
+        instance_config_dir.join(format!("{}-{}/instance.toml", namespace, instance_label));
+
+    let provided = |name: &str, value: &Option<String>| -> Result<Option<String>> {
+        match value.as_deref().map(str::trim) {
+            Some("") => Err(AnyhowError::msg(format!("{} can't be empty", name))),
+            value => Ok(value.map(str::to_string)),
+        }
+    };
+    let admin_email = provided("admin_email", &user_env_vars.admin_email)?
+        .unwrap_or_else(|| "admin@example.com".to_string());
+    if !admin_email.contains('@') {
+        return Err(AnyhowError::msg(format!(
+            "admin_email {:?} isn't an email address",
+            admin_email
+        )));
+    }
+    let install_plugins = user_env_vars.install_plugins.clone().unwrap_or_default();
+    if install_plugins
+        .iter()
+        .any(|plugin| plugin.trim().is_empty())
+    {
+        return Err(AnyhowError::msg(
+            "install_plugins can't contain empty entries",
+        ));
     }
 
     let instance_data = InstanceData {
-        admin_user: extract_value(&env_vars.wordpress, "WORDPRESS_DB_USER"),
-        admin_password: extract_value(&env_vars.wordpress, "WORDPRESS_DB_PASSWORD"),
-        admin_email: "admin@example.com".to_string(),
-        site_title: "My Wordpress Site".to_string(),
-        site_url: format!("{}:{}", config.site_url, &nginx_port),
+        admin_user: provided("admin_user", &user_env_vars.admin_user)?
+            .unwrap_or_else(|| extract_value(&env_vars.wordpress, "WORDPRESS_DB_USER")),
+        admin_password: provided("admin_password", &user_env_vars.admin_password)?
+            .unwrap_or_else(|| extract_value(&env_vars.wordpress, "WORDPRESS_DB_PASSWORD")),
+        admin_email,
+        site_title: provided("site_title", &user_env_vars.site_title)?
+            .unwrap_or_else(|| "My Wordpress Site".to_string()),
+        site_url: match tls_port {
+            Some(tls_port) => format!("{}:{}", https_url(&config.site_url), tls_port),
+            None => format!("{}:{}", config.site_url, &nginx_port),
+        },
         adminer_url: format!("{}:{}", config.adminer_url, &adminer_port),
         adminer_user: extract_value(&env_vars.adminer, "ADMINER_DEFAULT_USERNAME"),
         adminer_password: extract_value(&env_vars.adminer, "ADMINER_DEFAULT_PASSWORD"),
-        network_name: format!("{}-{}", crate::NETWORK_NAME, instance_label),
-        nginx_port: *nginx_port,
-        adminer_port: *adminer_port,
+        database_root_password: extract_value(
+            &env_vars.mysql,
+            &user_env_vars.database_engine.env_var("MYSQL_ROOT_PASSWORD"),
+        ),
+        network_name: format!("{}-{}", namespace, instance_label),
+        nginx_port,
+        adminer_port,
+        external_db_host: user_env_vars
+            .external_db
+            .as_ref()
+            .map(|external_db| external_db.host.clone()),
+        debug: user_env_vars.debug.clone(),
+        log_rotation: env_vars.log_rotation.clone(),
+        external_network: user_env_vars.external_network.clone(),
+        adopted: false,
+        pinned_ports: user_env_vars.nginx_port.is_some() || user_env_vars.adminer_port.is_some(),
+        read_only: user_env_vars.read_only,
+        plugins: env_vars.plugins.clone(),
+        pinned: false,
+        database_engine: user_env_vars.database_engine,
+        redis: user_env_vars.enable_redis,
+        mailpit_port,
+        mailpit_url: mailpit_port.map(|port| format!("{}:{}", config.site_url, port)),
+        mysql_port,
+        tls_port,
+        name: user_env_vars.name.clone(),
+        created_at: Some(chrono::Utc::now()),
+        updated_at: None,
+        install_plugins,
+        wordpress_tag: env_vars.wordpress_tag.clone(),
+        limits: env_vars.limits.clone(),
     };
 
     fs::write(&instance_dir, toml::to_string(&instance_data)?)
@@ -438,3 +1387,280 @@ pub(crate) async fn parse_instance_data(
 
     Ok(instance_data)
 }
+
+/// Writes the `instance.toml` for an instance that wasn't created by wpdev, creating its
+/// instance directory. Fails if the directory already exists.
+pub(crate) async fn write_adopted_instance_data(
+    instance_id: &str,
+    instance_data: &InstanceData,
+) -> Result<()> {
+    let instance_path = get_instance_dir().await?.join(instance_id);
+    if instance_path.exists() {
+        return Err(AnyhowError::msg(format!(
+            "Instance {} already exists",
+            instance_id
+        )));
+    }
+    utils::create_path(&instance_path)
+        .await
+        .context("Failed to create instance directory")?;
+    write_instance_data_to_toml(instance_id, instance_data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::DatabaseEngine;
+    use crate::test_support::{MockDocker, TestEnv, NAMESPACE};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn overrides(vars: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn sync_db_overrides_copies_a_password_to_every_container() {
+        let user_env_vars = ContainerEnvVars {
+            mysql: overrides(&[("MYSQL_PASSWORD", "secret")]),
+            ..Default::default()
+        };
+        let [mysql, wordpress, adminer] = sync_db_overrides(&user_env_vars).unwrap();
+        assert_eq!(mysql["MYSQL_PASSWORD"], "secret");
+        assert_eq!(wordpress["WORDPRESS_DB_PASSWORD"], "secret");
+        assert_eq!(adminer["ADMINER_DEFAULT_PASSWORD"], "secret");
+        assert!(!mysql.contains_key("MYSQL_USER"));
+    }
+
+    #[test]
+    fn sync_db_overrides_uses_mariadb_names() {
+        let user_env_vars = ContainerEnvVars {
+            database_engine: DatabaseEngine::MariaDB,
+            wordpress: overrides(&[("WORDPRESS_DB_USER", "editor")]),
+            ..Default::default()
+        };
+        let [mysql, wordpress, adminer] = sync_db_overrides(&user_env_vars).unwrap();
+        assert_eq!(mysql["MARIADB_USER"], "editor");
+        assert!(!mysql.contains_key("MYSQL_USER"));
+        assert_eq!(wordpress["WORDPRESS_DB_USER"], "editor");
+        assert_eq!(adminer["ADMINER_DEFAULT_USERNAME"], "editor");
+    }
+
+    #[test]
+    fn sync_db_overrides_rejects_conflicts() {
+        let user_env_vars = ContainerEnvVars {
+            mysql: overrides(&[("MYSQL_PASSWORD", "one")]),
+            wordpress: overrides(&[("WORDPRESS_DB_PASSWORD", "two")]),
+            ..Default::default()
+        };
+        assert!(sync_db_overrides(&user_env_vars).is_err());
+    }
+
+    #[tokio::test]
+    async fn initialize_env_vars_applies_synced_overrides() {
+        let _env = TestEnv::new("").await;
+        let user_env_vars = ContainerEnvVars {
+            wordpress: overrides(&[("WORDPRESS_DB_PASSWORD", "secret")]),
+            ..Default::default()
+        };
+        let env_vars = initialize_env_vars("sync", &user_env_vars).await.unwrap();
+        assert_eq!(extract_value(&env_vars.mysql, "MYSQL_PASSWORD"), "secret");
+        assert_eq!(
+            extract_value(&env_vars.wordpress, "WORDPRESS_DB_PASSWORD"),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn image_listed_matches_tags_exactly() {
+        let images: Vec<ImageSummary> = serde_json::from_value(json!([{
+            "Id": "sha256:mock",
+            "ParentId": "",
+            "RepoTags": ["wordpress:6.5", "axllent/mailpit:latest"],
+            "RepoDigests": [],
+            "Created": 0,
+            "Size": 0,
+            "SharedSize": 0,
+            "Labels": {},
+            "Containers": 0,
+        }]))
+        .unwrap();
+
+        assert!(image_listed(&images, "wordpress:6.5"));
+        assert!(image_listed(&images, "docker.io/library/wordpress:6.5"));
+        assert!(image_listed(&images, "axllent/mailpit"));
+        assert!(!image_listed(&images, "wordpress:6"));
+        assert!(!image_listed(&images, "wordpress"));
+        assert!(!image_listed(&images, "mailpit"));
+    }
+
+    #[tokio::test]
+    async fn load_profile_config_reads_the_profile_file() {
+        let env = TestEnv::new("").await;
+        env.write_config(
+            "config.staging.toml",
+            "namespace = \"staging\"\nsite_url = \"http://staging.test\"\n",
+        );
+
+        let config = load_profile_config(Some("staging")).await.unwrap();
+        assert_eq!(config.namespace, "staging");
+        assert_eq!(config.site_url, "http://staging.test");
+        // Without custom_root it defaults to the instances directory next to the config.
+        assert_eq!(config.custom_root, Some(env.config_dir().join("instances")));
+    }
+
+    #[tokio::test]
+    async fn load_profile_config_rejects_missing_profiles() {
+        let env = TestEnv::new("").await;
+
+        let config = load_profile_config(None).await.unwrap();
+        assert_eq!(config.namespace, NAMESPACE);
+        assert_eq!(config.custom_root, Some(env.instances_dir()));
+
+        let Err(error) = load_profile_config(Some("missing")).await else {
+            panic!("a missing profile was loaded");
+        };
+        assert!(
+            error.to_string().contains("config.missing.toml"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn list_profiles_lists_profile_files() {
+        let env = TestEnv::new("").await;
+        assert!(list_profiles().await.unwrap().is_empty());
+
+        env.write_config("config.staging.toml", "");
+        env.write_config("config.ci.toml", "");
+        env.write_config("notes.toml", "");
+        assert_eq!(list_profiles().await.unwrap(), vec!["ci", "staging"]);
+    }
+
+    #[tokio::test]
+    async fn generate_wpcli_config_uses_the_configured_db_host() {
+        let wordpress_env_vars = vec![
+            "WORDPRESS_DB_HOST=wpcli-mysql:3306".to_string(),
+            "WORDPRESS_DB_PASSWORD=it's".to_string(),
+        ];
+        for connection in ["tcp", "socket"] {
+            let env = TestEnv::new(&format!("wpcli_db_connection = {:?}\n", connection)).await;
+            let instance_dir = env.instances_dir().join(format!("{}-wpcli", NAMESPACE));
+            let expected_host = match connection {
+                "tcp" => "wpcli-mysql:3306".to_string(),
+                _ => format!(
+                    "localhost:{}",
+                    instance_dir.join("mysql/mysqld.sock").display()
+                ),
+            };
+
+            generate_wpcli_config(&instance_dir, "wpcli", &wordpress_env_vars)
+                .await
+                .unwrap();
+
+            let wpcli_php = std::fs::read_to_string(instance_dir.join("wp-cli.local.php")).unwrap();
+            assert!(
+                wpcli_php.contains(&format!("define('DB_HOST', '{}');", expected_host)),
+                "{} config has the wrong DB_HOST:\n{}",
+                connection,
+                wpcli_php
+            );
+            assert!(wpcli_php.contains("define('DB_PASSWORD', 'it\\'s');"));
+            assert!(instance_dir.join("wp-cli.local.yml").exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_instance_data_writes_instance_toml() {
+        let env = TestEnv::new("site_url = \"http://localhost\"\n").await;
+        let instance_id = format!("{}-parse", NAMESPACE);
+        std::fs::create_dir_all(env.instances_dir().join(&instance_id)).unwrap();
+        let user_env_vars = ContainerEnvVars {
+            admin_email: Some("dev@example.com".to_string()),
+            site_title: Some("Parsed".to_string()),
+            name: Some("parsed".to_string()),
+            ..Default::default()
+        };
+        let env_vars = initialize_env_vars("parse", &user_env_vars).await.unwrap();
+
+        let instance_data = parse_instance_data(
+            &env_vars,
+            &user_env_vars,
+            &InstanceDataOptions {
+                namespace: NAMESPACE,
+                instance_label: "parse",
+                nginx_port: 8001,
+                adminer_port: 8002,
+                mailpit_port: None,
+                mysql_port: Some(3307),
+                tls_port: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let written: InstanceData = toml::from_str(
+            &std::fs::read_to_string(env.instances_dir().join(&instance_id).join("instance.toml"))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(written.site_url, "http://localhost:8001");
+        assert_eq!(written.adminer_port, 8002);
+        assert_eq!(written.mysql_port, Some(3307));
+        assert_eq!(written.admin_email, "dev@example.com");
+        assert_eq!(written.site_title, "Parsed");
+        assert_eq!(written.name.as_deref(), Some("parsed"));
+        assert_eq!(written.network_name, instance_id);
+        assert_eq!(
+            written.admin_password,
+            extract_value(&env_vars.wordpress, "WORDPRESS_DB_PASSWORD")
+        );
+        assert_eq!(written.admin_password, instance_data.admin_password);
+        assert!(written.created_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn create_network_if_not_exists_is_idempotent() {
+        // The daemon already has a network whose name starts with the new one, which the
+        // `name` filter matches too.
+        let created = Arc::new(AtomicBool::new(false));
+        let docker = {
+            let created = created.clone();
+            MockDocker::start(move |request| {
+                if request.is("GET", "/networks") {
+                    let mut networks = vec![json!({"Name": "test-site-2"})];
+                    if created.load(Ordering::SeqCst) {
+                        networks.push(json!({"Name": "test-site"}));
+                    }
+                    return (200, json!(networks).to_string());
+                }
+                if request.is("POST", "/networks/create") {
+                    created.store(true, Ordering::SeqCst);
+                    return (201, json!({"Id": "network"}).to_string());
+                }
+                (404, String::new())
+            })
+            .await
+        };
+        let client = docker.client();
+
+        assert!(create_network_if_not_exists(&client, "test", "site")
+            .await
+            .unwrap());
+        assert!(!create_network_if_not_exists(&client, "test", "site")
+            .await
+            .unwrap());
+        let creates = docker
+            .requests()
+            .iter()
+            .filter(|request| request.is("POST", "/networks/create"))
+            .count();
+        assert_eq!(creates, 1);
+    }
+}
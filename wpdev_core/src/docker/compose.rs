@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use bollard::Docker;
+use log::info;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::docker::container::{ContainerImage, InstanceContainer};
+
+const COMPOSE_VERSION: &str = "3.8";
+
+#[derive(Serialize)]
+struct ComposeFile {
+    version: &'static str,
+    services: BTreeMap<String, ComposeService>,
+}
+
+#[derive(Serialize)]
+struct ComposeService {
+    image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    volumes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    read_only: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tmpfs: Vec<String>,
+    restart: &'static str,
+}
+
+/// Renders a compose file running `containers` as they're currently configured. Services are
+/// named after the containers, so the hostnames in the nginx config and `WORDPRESS_DB_HOST`
+/// still resolve on the compose network. Env vars the image sets itself are left out.
+pub(crate) async fn render(docker: &Docker, containers: &[InstanceContainer]) -> Result<String> {
+    let mut names = BTreeMap::new();
+    let mut services = BTreeMap::new();
+    for container in containers {
+        let info = docker
+            .inspect_container(&container.container_id, None)
+            .await
+            .context(format!(
+                "Failed to inspect {} container",
+                container.container_image
+            ))?;
+        let name = info
+            .name
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        let config = info.config.unwrap_or_default();
+        let host_config = info.host_config.unwrap_or_default();
+        let image = config.image.unwrap_or_else(|| container.image.clone());
+        info!("Exporting {} container as service {}", image, name);
+
+        let image_env: HashSet<String> = docker
+            .inspect_image(&image)
+            .await
+            .ok()
+            .and_then(|image| image.config)
+            .and_then(|config| config.env)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let environment = config
+            .env
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|var| !image_env.contains(var))
+            .filter_map(|var| {
+                let (key, value) = var.split_once('=')?;
+                // Compose interpolates `$`, escape it so values are passed through literally.
+                Some((key.to_string(), value.replace('$', "$$")))
+            })
+            .collect();
+
+        let mut ports: Vec<String> = host_config
+            .port_bindings
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(container_port, bindings)| {
+                let container_port = container_port.trim_end_matches("/tcp").to_string();
+                bindings
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(move |binding| {
+                        Some(format!("{}:{}", binding.host_port?, container_port))
+                    })
+            })
+            .collect();
+        ports.sort();
+
+        names.insert(container.container_image.to_string(), name.clone());
+        services.insert(
+            name,
+            (
+                container.container_image.clone(),
+                ComposeService {
+                    image,
+                    user: config.user.filter(|user| !user.is_empty()),
+                    environment,
+                    volumes: host_config.binds.unwrap_or_default(),
+                    ports,
+                    depends_on: Vec::new(),
+                    read_only: host_config.readonly_rootfs.unwrap_or_default(),
+                    tmpfs: host_config.tmpfs.unwrap_or_default().into_keys().collect(),
+                    restart: "on-failure",
+                },
+            ),
+        );
+    }
+
+    let database = names
+        .iter()
+        .find(|(image, _)| ContainerImage::from_image_ref(image).is_database())
+        .map(|(_, name)| name.clone());
+    let dependencies = |image: &ContainerImage| -> Vec<String> {
+        let mut dependencies: Vec<String> = match image {
+            ContainerImage::Wordpress | ContainerImage::Adminer => {
+                database.iter().cloned().collect()
+            }
+            ContainerImage::Nginx => [ContainerImage::Wordpress, ContainerImage::Adminer]
+                .iter()
+                .filter_map(|image| names.get(&image.to_string()).cloned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        dependencies.sort();
+        dependencies
+    };
+    let services = services
+        .into_iter()
+        .map(|(name, (image, mut service))| {
+            service.depends_on = dependencies(&image);
+            (name, service)
+        })
+        .collect();
+
+    serde_yaml::to_string(&ComposeFile {
+        version: COMPOSE_VERSION,
+        services,
+    })
+    .context("Failed to serialize compose file")
+}
@@ -2,114 +2,437 @@ use crate::config;
 use crate::docker::container;
 use crate::utils;
 use anyhow::{Context, Result};
+use bollard::network::ConnectNetworkOptions;
+use bollard::Docker;
 use log::info;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::docker::container::{ContainerImage, ContainerStatus, EnvVars};
+use crate::docker::container::{ContainerImage, ContainerSpec, EnvVars, ExternalDatabase};
 
-pub(crate) async fn configure_wordpress_container(
+/// Where the nginx container finds `cert.pem` and `key.pem`.
+pub(crate) const NGINX_TLS_DIR: &str = "/etc/nginx/certs";
+
+pub(crate) fn wordpress_container_spec(
+    namespace: &str,
     instance_label: &str,
     instance_path: &PathBuf,
     labels: &HashMap<String, String>,
     env_vars: &EnvVars,
-) -> Result<(String, ContainerStatus)> {
-    info!("Configuring wordpress container");
-    let wordpress_config_dir = instance_path.join("wordpress");
-    let wordpress_path = utils::create_path(&wordpress_config_dir)
-        .await
-        .context("Failed to create wordpress directory")?;
-    let (ids, status) = container::InstanceContainer::new(
+) -> ContainerSpec {
+    let mut builder = ContainerSpec::builder(
+        namespace,
         instance_label,
         instance_path,
         ContainerImage::Wordpress,
-        labels,
-        env_vars.wordpress.clone(),
-        Some("1000:1000".to_string()),
-        Some((Some(wordpress_path.to_path_buf()), "/var/www/html/")),
-        None,
     )
-    .await?;
-    Ok((ids, status))
+    .tag(env_vars.wordpress_tag.as_deref())
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .env(env_vars.wordpress.clone())
+    .user(&env_vars.user)
+    .volume(Some(instance_path.join("wordpress")), "/var/www/html/")
+    .read_only(env_vars.read_only, &["/tmp", "/run"]);
+    for plugin in &env_vars.plugins {
+        builder = builder.bind(
+            plugin,
+            &format!(
+                "/var/www/html/wp-content/plugins/{}",
+                config::plugin_name(plugin)
+            ),
+        );
+    }
+    builder.build()
 }
 
-pub(crate) async fn configure_mysql_container(
+/// With `mysql_port` the database port is published on that host port, e.g. for Sequel Ace or
+/// TablePlus.
+pub(crate) fn mysql_container_spec(
+    namespace: &str,
     instance_label: &str,
     instance_path: &PathBuf,
     labels: &HashMap<String, String>,
     env_vars: &EnvVars,
-) -> Result<(String, ContainerStatus)> {
-    info!("Configuring mysql container");
-    let mysql_config_dir = instance_path.join("mysql");
-    let mysql_socket_path = utils::create_path(&mysql_config_dir)
-        .await
-        .context("Failed to create mysql directory")?;
-    let (ids, status) = container::InstanceContainer::new(
+    mysql_port: Option<u32>,
+) -> ContainerSpec {
+    let builder = ContainerSpec::builder(
+        namespace,
         instance_label,
         instance_path,
-        ContainerImage::MySQL,
-        labels,
-        env_vars.mysql.clone(),
-        Some("1000:1000".to_string()),
-        Some((Some(mysql_socket_path.to_path_buf()), "/var/run/mysqld")),
+        env_vars.database_engine.image(),
+    )
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .env(env_vars.mysql.clone())
+    .user(&env_vars.user)
+    .volume(Some(instance_path.join("mysql")), "/var/run/mysqld")
+    .read_only(env_vars.read_only, &["/tmp"]);
+    match mysql_port {
+        Some(mysql_port) => builder.port(mysql_port, 3306).build(),
+        None => builder.build(),
+    }
+}
+
+/// Attaches a shared MySQL container to the instance network and creates the instance's
+/// database and user on it. The container's own `MYSQL_ROOT_PASSWORD` is used to do so.
+pub(crate) async fn configure_external_database(
+    docker: &Docker,
+    network_name: &str,
+    external_db: &ExternalDatabase,
+    env_vars: &EnvVars,
+) -> Result<()> {
+    info!("Configuring external database {}", external_db.host);
+    let container_name = external_db.container_name();
+    docker
+        .connect_network(
+            network_name,
+            ConnectNetworkOptions {
+                container: container_name,
+                ..Default::default()
+            },
+        )
+        .await
+        .context(format!(
+            "Failed to attach {} to network {}",
+            container_name, network_name
+        ))?;
+
+    let db_user = config::extract_value(&env_vars.wordpress, "WORDPRESS_DB_USER");
+    let db_password = config::extract_value(&env_vars.wordpress, "WORDPRESS_DB_PASSWORD");
+    let script = format!(
+        r#"mysql -uroot -p"$MYSQL_ROOT_PASSWORD" -e "CREATE DATABASE IF NOT EXISTS \`{name}\`; CREATE USER IF NOT EXISTS '{user}'@'%' IDENTIFIED BY '{password}'; GRANT ALL PRIVILEGES ON \`{name}\`.* TO '{user}'@'%';""#,
+        name = external_db.name,
+        user = db_user,
+        password = db_password,
+    );
+    container::InstanceContainer::exec(docker, container_name, vec!["sh", "-c", &script])
+        .await
+        .context(format!(
+            "Failed to create database {} on {}",
+            external_db.name, external_db.host
+        ))?;
+
+    Ok(())
+}
+
+/// Generates a self-signed certificate for `{label}.local` and `localhost` into `tls_dir` as
+/// `cert.pem` and `key.pem`, which the nginx container serves HTTPS with. It's made with the
+/// `openssl` of the nginx image, so no other image or dependency is needed.
+pub(crate) async fn generate_tls_certificate(
+    docker: &Docker,
+    instance_label: &str,
+    tls_dir: &PathBuf,
+) -> Result<()> {
+    info!("Generating TLS certificate for {}.local", instance_label);
+    utils::create_path(tls_dir)
+        .await
+        .context("Failed to create TLS directory")?;
+    let tls_dir_str = tls_dir
+        .to_str()
+        .context("Failed to convert TLS directory to string")?;
+    let host = format!("{}.local", instance_label);
+    let cmd = [
+        "openssl",
+        "req",
+        "-x509",
+        "-newkey",
+        "rsa:2048",
+        "-nodes",
+        "-days",
+        "825",
+        "-subj",
+        &format!("/CN={}", host),
+        "-addext",
+        &format!("subjectAltName=DNS:{},DNS:localhost,IP:127.0.0.1", host),
+        "-keyout",
+        "/certs/key.pem",
+        "-out",
+        "/certs/cert.pem",
+    ];
+    container::InstanceContainer::run_once(
+        docker,
+        &ContainerImage::Nginx.image_ref(),
+        cmd.iter().map(|arg| arg.to_string()).collect(),
+        Vec::new(),
+        vec![format!("{}:/certs", tls_dir_str)],
+        "none",
         None,
     )
-    .await?;
-    Ok((ids, status))
+    .await
+    .context(format!("Failed to generate TLS certificate for {}", host))?;
+    Ok(())
 }
 
-pub(crate) async fn configure_adminer_container(
+pub(crate) fn adminer_container_spec(
+    namespace: &str,
     instance_label: &str,
     instance_path: &PathBuf,
     labels: &HashMap<String, String>,
     env_vars: &EnvVars,
     adminer_port: u32,
-) -> Result<(String, ContainerStatus)> {
-    info!("Configuring adminer container");
-    let (ids, status) = container::InstanceContainer::new(
+) -> ContainerSpec {
+    ContainerSpec::builder(
+        namespace,
         instance_label,
         instance_path,
         ContainerImage::Adminer,
-        labels,
-        env_vars.adminer.clone(),
-        None,
-        None,
-        Some((adminer_port, 8080)),
     )
-    .await?;
-    Ok((ids, status))
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .env(env_vars.adminer.clone())
+    .port(adminer_port, 8080)
+    .read_only(env_vars.read_only, &["/tmp"])
+    .build()
 }
 
-pub(crate) async fn configure_nginx_container(
-    instance_path: &PathBuf,
+/// Redis for the Redis Object Cache plugin, reached by WordPress as `{label}-redis` on the
+/// instance network. Nothing is published on the host.
+pub(crate) fn redis_container_spec(
+    namespace: &str,
     instance_label: &str,
+    instance_path: &Path,
     labels: &HashMap<String, String>,
-    nginx_port: u32,
-) -> Result<(String, ContainerStatus)> {
-    info!("Configuring nginx container");
-    let nginx_config_path = config::generate_nginx_config(
+    env_vars: &EnvVars,
+) -> ContainerSpec {
+    ContainerSpec::builder(
+        namespace,
         instance_label,
-        nginx_port,
-        &format!("{}-{}", instance_label, ContainerImage::Adminer.to_string()),
-        &format!(
-            "{}-{}",
-            instance_label,
-            ContainerImage::Wordpress.to_string()
-        ),
         instance_path,
+        ContainerImage::Redis,
     )
-    .await?;
-    let (ids, status) = container::InstanceContainer::new(
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .read_only(env_vars.read_only, &["/tmp"])
+    .build()
+}
+
+/// Mailpit catches the mail WordPress sends over SMTP on port 1025. Its web UI, for reading
+/// the captured messages, is published on `mailpit_port`.
+pub(crate) fn mailpit_container_spec(
+    namespace: &str,
+    instance_label: &str,
+    instance_path: &Path,
+    labels: &HashMap<String, String>,
+    env_vars: &EnvVars,
+    mailpit_port: u32,
+) -> ContainerSpec {
+    ContainerSpec::builder(
+        namespace,
+        instance_label,
+        instance_path,
+        ContainerImage::Mailpit,
+    )
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .port(mailpit_port, 8025)
+    .read_only(env_vars.read_only, &["/tmp"])
+    .build()
+}
+
+/// nginx in front of WordPress and Adminer. With `tls_port` it also serves HTTPS there, with
+/// the certificate from `generate_tls_certificate`.
+///
+/// The nginx config itself is rendered with `config::render_nginx_config` and written to
+/// `config::nginx_config_path` before the container is created.
+pub(crate) fn nginx_container_spec(
+    namespace: &str,
+    instance_path: &PathBuf,
+    instance_label: &str,
+    labels: &HashMap<String, String>,
+    env_vars: &EnvVars,
+    nginx_port: u32,
+    tls_port: Option<u32>,
+) -> ContainerSpec {
+    let builder = ContainerSpec::builder(
+        namespace,
         instance_label,
         instance_path,
         ContainerImage::Nginx,
-        labels,
-        Vec::new(),
-        None,
-        Some((Some(nginx_config_path), "/etc/nginx/conf.d/default.conf")),
-        Some((nginx_port, nginx_port)),
     )
-    .await?;
+    .labels(labels)
+    .log_rotation(&env_vars.log_rotation)
+    .limits(&env_vars.limits)
+    .restart_policy(env_vars.restart_policy)
+    .network(&env_vars.network_name)
+    .volume(
+        Some(config::nginx_config_path(instance_label, instance_path)),
+        "/etc/nginx/conf.d/default.conf",
+    )
+    .port(nginx_port, nginx_port)
+    .read_only(env_vars.read_only, &["/tmp", "/run", "/var/cache/nginx"]);
+    match tls_port {
+        Some(tls_port) => builder
+            .port(tls_port, tls_port)
+            .bind(&config::tls_dir(instance_path), NGINX_TLS_DIR)
+            .build(),
+        None => builder.build(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::{DatabaseEngine, LogRotation};
+    use crate::ContainerRestartPolicy;
+    use std::collections::BTreeMap;
+
+    fn env_vars() -> EnvVars {
+        EnvVars {
+            adminer: vec!["ADMINER_DEFAULT_SERVER=spec-mysql".to_string()],
+            mysql: vec!["MYSQL_DATABASE=wordpress".to_string()],
+            wordpress: vec!["WORDPRESS_DB_HOST=spec-mysql".to_string()],
+            log_rotation: LogRotation::default(),
+            network_name: "test-spec".to_string(),
+            read_only: false,
+            plugins: vec![PathBuf::from("/src/my-plugin")],
+            wordpress_tag: Some("6.5-php8.2-apache".to_string()),
+            database_engine: DatabaseEngine::MySQL,
+            limits: BTreeMap::new(),
+            restart_policy: ContainerRestartPolicy::default(),
+            user: "1000:1000".to_string(),
+        }
+    }
+
+    fn labels() -> HashMap<String, String> {
+        HashMap::from([("instance".to_string(), "spec".to_string())])
+    }
+
+    /// The volume and bind mounts of `spec` as `(host path, container path)`.
+    fn binds(spec: &ContainerSpec) -> Vec<(Option<PathBuf>, String)> {
+        spec.volume
+            .iter()
+            .chain(&spec.binds)
+            .map(|binding| (binding.host_path.clone(), binding.container_path.clone()))
+            .collect()
+    }
+
+    fn ports(spec: &ContainerSpec) -> Vec<(u32, u32)> {
+        spec.ports
+            .iter()
+            .map(|port| (port.host_port, port.container_port))
+            .collect()
+    }
+
+    #[test]
+    fn wordpress_spec() {
+        let instance_path = PathBuf::from("/instances/test-spec");
+        let spec = wordpress_container_spec("test", "spec", &instance_path, &labels(), &env_vars());
+        assert_eq!(spec.image_ref(), "wordpress:6.5-php8.2-apache");
+        assert_eq!(
+            binds(&spec),
+            vec![
+                (
+                    Some(instance_path.join("wordpress")),
+                    "/var/www/html/".to_string()
+                ),
+                (
+                    Some(PathBuf::from("/src/my-plugin")),
+                    "/var/www/html/wp-content/plugins/my-plugin".to_string()
+                ),
+            ]
+        );
+        assert!(ports(&spec).is_empty());
+        assert_eq!(spec.labels, labels());
+        assert_eq!(spec.network.as_deref(), Some("test-spec"));
+        assert_eq!(spec.user.as_deref(), Some("1000:1000"));
+    }
+
+    #[test]
+    fn mysql_spec() {
+        let instance_path = PathBuf::from("/instances/test-spec");
+        let spec = mysql_container_spec(
+            "test",
+            "spec",
+            &instance_path,
+            &labels(),
+            &env_vars(),
+            Some(3307),
+        );
+        assert_eq!(spec.image_ref(), "mysql:latest");
+        assert_eq!(
+            binds(&spec),
+            vec![(
+                Some(instance_path.join("mysql")),
+                "/var/run/mysqld".to_string()
+            )]
+        );
+        assert_eq!(ports(&spec), vec![(3307, 3306)]);
+        assert_eq!(spec.labels, labels());
+
+        let mut mariadb = env_vars();
+        mariadb.database_engine = DatabaseEngine::MariaDB;
+        let spec = mysql_container_spec("test", "spec", &instance_path, &labels(), &mariadb, None);
+        assert_eq!(spec.image_ref(), "mariadb:latest");
+        assert!(ports(&spec).is_empty());
+    }
+
+    #[test]
+    fn nginx_spec() {
+        let instance_path = PathBuf::from("/instances/test-spec");
+        let nginx_config_path = instance_path.join("nginx").join("spec-nginx.conf");
+        let spec = nginx_container_spec(
+            "test",
+            &instance_path,
+            "spec",
+            &labels(),
+            &env_vars(),
+            8001,
+            None,
+        );
+        assert_eq!(spec.image_ref(), "nginx:latest");
+        assert_eq!(
+            binds(&spec),
+            vec![(
+                Some(nginx_config_path.clone()),
+                "/etc/nginx/conf.d/default.conf".to_string()
+            )]
+        );
+        assert_eq!(ports(&spec), vec![(8001, 8001)]);
+        assert_eq!(spec.labels, labels());
+
+        let spec = nginx_container_spec(
+            "test",
+            &instance_path,
+            "spec",
+            &labels(),
+            &env_vars(),
+            8001,
+            Some(8443),
+        );
+        assert_eq!(ports(&spec), vec![(8001, 8001), (8443, 8443)]);
+        assert_eq!(
+            binds(&spec).last(),
+            Some(&(
+                Some(config::tls_dir(&instance_path)),
+                NGINX_TLS_DIR.to_string()
+            ))
+        );
+    }
 
-    Ok((ids, status))
+    #[test]
+    fn adminer_spec() {
+        let instance_path = PathBuf::from("/instances/test-spec");
+        let spec =
+            adminer_container_spec("test", "spec", &instance_path, &labels(), &env_vars(), 8002);
+        assert_eq!(spec.image_ref(), "adminer:latest");
+        assert!(binds(&spec).is_empty());
+        assert_eq!(ports(&spec), vec![(8002, 8080)]);
+        assert_eq!(spec.labels, labels());
+        assert_eq!(spec.env, env_vars().adminer);
+    }
 }
@@ -1,26 +1,286 @@
+use crate::error::WpdevError;
 use crate::utils;
+use crate::ContainerRestartPolicy;
 use anyhow::{Context, Error as AnyhowError, Result};
 use bollard::container::{
-    Config, CreateContainerOptions, RemoveContainerOptions, RestartContainerOptions,
-    StartContainerOptions, StopContainerOptions,
+    Config, CreateContainerOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+    RestartContainerOptions, StartContainerOptions, StatsOptions, StopContainerOptions,
+    UpdateContainerOptions, WaitContainerOptions,
 };
-use bollard::models::{HostConfig, PortBinding};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::models::{ContainerStateStatusEnum, HostConfig, HostConfigLogConfig, PortBinding};
 use bollard::models::{RestartPolicy, RestartPolicyNameEnum};
 use bollard::Docker;
+use futures::stream::StreamExt;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+const DOCKER_RETRY_ATTEMPTS: u32 = 3;
+const DOCKER_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const EXEC_INPUT_CHUNK_SIZE: usize = 64 * 1024;
 
 #[derive(Deserialize)]
 pub struct ContainerEnvVars {
     pub wordpress: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub mysql: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub adminer: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub external_db: Option<ExternalDatabase>,
+    #[serde(default)]
+    pub debug: DebugSettings,
+    #[serde(default)]
+    pub log_max_size: Option<String>,
+    #[serde(default)]
+    pub log_max_files: Option<u32>,
+    #[serde(default)]
+    pub external_network: Option<String>,
+    /// Pins the site to this host port instead of picking a free one.
+    #[serde(default)]
+    pub nginx_port: Option<u32>,
+    /// Pins Adminer to this host port instead of picking a free one.
+    #[serde(default)]
+    pub adminer_port: Option<u32>,
+    /// Extra labels set on every container, e.g. to query instances by client.
+    #[serde(default)]
+    pub labels: Option<HashMap<String, String>>,
+    /// Human name the instance can be referred to by instead of its id. Unique within the
+    /// namespace.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Runs the containers with a read-only root filesystem, see `ContainerSpecBuilder::read_only`.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Host plugin directories mounted into `wp-content/plugins/<dirname>`.
+    #[serde(default)]
+    pub plugins: Option<Vec<PathBuf>>,
+    /// Tag of the `wordpress` image to use, e.g. `6.3`. Defaults to `latest`.
+    #[serde(default)]
+    pub wordpress_version: Option<String>,
+    /// PHP version of the `wordpress` image, e.g. `8.1`, combined with `wordpress_version`
+    /// into a tag such as `6.5-php8.1-apache`.
+    #[serde(default)]
+    pub php_version: Option<String>,
+    #[serde(default)]
+    pub database_engine: DatabaseEngine,
+    /// WordPress admin login used by `install`, defaults to the database user and password.
+    #[serde(default)]
+    pub admin_user: Option<String>,
+    #[serde(default)]
+    pub admin_password: Option<String>,
+    #[serde(default)]
+    pub admin_email: Option<String>,
+    #[serde(default)]
+    pub site_title: Option<String>,
+    /// Adds a Redis container and points the Redis Object Cache plugin at it.
+    #[serde(default)]
+    pub enable_redis: bool,
+    /// Adds a Mailpit container that catches the mail WordPress sends.
+    #[serde(default)]
+    pub enable_mail: bool,
+    /// Publishes the database port on a free host port for external database tools.
+    #[serde(default)]
+    pub expose_mysql_port: bool,
+    /// Serves the site over HTTPS with a self-signed certificate on a free host port, and
+    /// uses that as the site URL.
+    #[serde(default)]
+    pub enable_tls: bool,
+    /// Plugin slugs or zip URLs installed and activated with WP-CLI after WordPress is
+    /// installed.
+    #[serde(default)]
+    pub install_plugins: Option<Vec<String>>,
+    /// SQL dump, optionally gzipped, imported into the database once it's up.
+    #[serde(default)]
+    pub import_dump: Option<PathBuf>,
+    /// Site URL the dump was taken from, replaced with the instance's URL after importing.
+    /// Defaults to the dump's `siteurl` option.
+    #[serde(default)]
+    pub import_site_url: Option<String>,
+    /// Memory limit in MB for every container, replacing the configured per-service default.
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
+    /// CPU limit for every container, e.g. `0.5`, replacing the configured per-service
+    /// default.
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Per-service limits used instead of `AppConfig::container_limits`, e.g. so a clone
+    /// keeps the limits of the original.
+    #[serde(default)]
+    pub container_limits: Option<BTreeMap<String, ResourceLimits>>,
 }
 
 impl Default for ContainerEnvVars {
     fn default() -> Self {
-        ContainerEnvVars { wordpress: None }
+        ContainerEnvVars {
+            wordpress: None,
+            mysql: None,
+            adminer: None,
+            external_db: None,
+            debug: DebugSettings::default(),
+            log_max_size: None,
+            log_max_files: None,
+            external_network: None,
+            nginx_port: None,
+            adminer_port: None,
+            labels: None,
+            name: None,
+            read_only: false,
+            plugins: None,
+            wordpress_version: None,
+            php_version: None,
+            database_engine: DatabaseEngine::default(),
+            admin_user: None,
+            admin_password: None,
+            admin_email: None,
+            site_title: None,
+            enable_redis: false,
+            enable_mail: false,
+            expose_mysql_port: false,
+            enable_tls: false,
+            install_plugins: None,
+            import_dump: None,
+            import_site_url: None,
+            memory_mb: None,
+            cpus: None,
+            container_limits: None,
+        }
+    }
+}
+
+/// The database server created for an instance.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseEngine {
+    #[default]
+    MySQL,
+    MariaDB,
+}
+
+impl DatabaseEngine {
+    pub fn parse(engine: &str) -> Result<Self> {
+        match engine.to_lowercase().as_str() {
+            "mysql" => Ok(DatabaseEngine::MySQL),
+            "mariadb" => Ok(DatabaseEngine::MariaDB),
+            _ => Err(AnyhowError::msg(format!(
+                "Unknown database engine {}, expected mysql or mariadb",
+                engine
+            ))),
+        }
+    }
+
+    pub fn image(&self) -> ContainerImage {
+        match self {
+            DatabaseEngine::MySQL => ContainerImage::MySQL,
+            DatabaseEngine::MariaDB => ContainerImage::MariaDB,
+        }
+    }
+
+    /// The engine's name for a `MYSQL_*` env var, e.g. `MARIADB_PASSWORD` for MariaDB.
+    pub fn env_var(&self, mysql_var: &str) -> String {
+        match self {
+            DatabaseEngine::MySQL => mysql_var.to_string(),
+            DatabaseEngine::MariaDB => mysql_var.replacen("MYSQL_", "MARIADB_", 1),
+        }
+    }
+}
+
+/// Rotation for a container's `json-file` logs, so long-running instances don't fill the disk.
+/// `max_size` uses Docker's size format, e.g. `10m`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogRotation {
+    pub max_size: String,
+    pub max_files: u32,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation {
+            max_size: "10m".to_string(),
+            max_files: 3,
+        }
+    }
+}
+
+/// Memory and CPU limits of a container, unset limits leave it unconstrained. Swap is capped
+/// at the memory limit, so a container can't page its way past it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+}
+
+impl ResourceLimits {
+    pub fn new(memory_mb: u64, cpus: f64) -> Self {
+        ResourceLimits {
+            memory_mb: Some(memory_mb),
+            cpus: Some(cpus),
+        }
+    }
+
+    /// These limits with the ones set in `overrides` taking precedence.
+    pub fn overridden_by(self, overrides: ResourceLimits) -> Self {
+        ResourceLimits {
+            memory_mb: overrides.memory_mb.or(self.memory_mb),
+            cpus: overrides.cpus.or(self.cpus),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.memory_mb.is_none() && self.cpus.is_none()
+    }
+
+    fn memory_bytes(&self) -> Option<i64> {
+        self.memory_mb.map(|mb| mb as i64 * 1024 * 1024)
+    }
+
+    fn nano_cpus(&self) -> Option<i64> {
+        self.cpus.map(|cpus| (cpus * 1e9) as i64)
+    }
+}
+
+/// WordPress debug constants. Defaults to debugging on, logged to `wp-content/debug.log`
+/// rather than displayed on the page.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DebugSettings {
+    pub enabled: bool,
+    pub log: bool,
+    pub display: bool,
+}
+
+impl Default for DebugSettings {
+    fn default() -> Self {
+        DebugSettings {
+            enabled: true,
+            log: true,
+            display: false,
+        }
+    }
+}
+
+/// A MySQL server shared between instances instead of a per-instance container.
+///
+/// `host` must be the name of a running MySQL container (optionally followed by `:port`) so
+/// that it can be attached to the instance's network.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExternalDatabase {
+    pub host: String,
+    pub name: String,
+}
+
+impl ExternalDatabase {
+    pub fn container_name(&self) -> &str {
+        self.host.split(':').next().unwrap_or(&self.host)
     }
 }
 
@@ -28,18 +288,34 @@ pub struct EnvVars {
     pub adminer: Vec<String>,
     pub mysql: Vec<String>,
     pub wordpress: Vec<String>,
+    pub log_rotation: LogRotation,
+    pub network_name: String,
+    pub read_only: bool,
+    /// Canonical paths of the plugin directories to mount.
+    pub plugins: Vec<PathBuf>,
+    /// Tag of the WordPress image, `None` for `latest`.
+    pub wordpress_tag: Option<String>,
+    pub database_engine: DatabaseEngine,
+    /// Limits of each of the instance's services, keyed by service name.
+    pub limits: BTreeMap<String, ResourceLimits>,
+    pub restart_policy: ContainerRestartPolicy,
+    /// `uid:gid` WordPress and the database run as.
+    pub user: String,
 }
 
 pub enum ContainerOperation {
     Start,
     Stop,
     Restart,
+    Pause,
+    Unpause,
     Delete,
     Inspect,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ContainerStatus {
+    Created,
     Running,
     Stopped,
     Restarting,
@@ -54,6 +330,7 @@ pub enum ContainerStatus {
 impl ContainerStatus {
     pub fn to_string(&self) -> String {
         match self {
+            ContainerStatus::Created => "created".to_string(),
             ContainerStatus::Running => "running".to_string(),
             ContainerStatus::Stopped => "stopped".to_string(),
             ContainerStatus::Restarting => "restarting".to_string(),
@@ -70,8 +347,11 @@ impl ContainerStatus {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ContainerImage {
     Adminer,
+    Mailpit,
     MySQL,
+    MariaDB,
     Nginx,
+    Redis,
     Wordpress,
     Unknown,
 }
@@ -80,9 +360,12 @@ impl fmt::Display for ContainerImage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ContainerImage::MySQL => write!(f, "MySQL"),
+            ContainerImage::MariaDB => write!(f, "MariaDB"),
             ContainerImage::Wordpress => write!(f, "Wordpress"),
             ContainerImage::Nginx => write!(f, "Nginx"),
             ContainerImage::Adminer => write!(f, "Adminer"),
+            ContainerImage::Redis => write!(f, "Redis"),
+            ContainerImage::Mailpit => write!(f, "Mailpit"),
             ContainerImage::Unknown => write!(f, "Unknown"),
         }
     }
@@ -93,26 +376,63 @@ impl ContainerImage {
         match self {
             ContainerImage::Adminer => "adminer".to_string(),
             ContainerImage::MySQL => "mysql".to_string(),
+            ContainerImage::MariaDB => "mariadb".to_string(),
             ContainerImage::Nginx => "nginx".to_string(),
+            ContainerImage::Redis => "redis".to_string(),
+            ContainerImage::Mailpit => "mailpit".to_string(),
             ContainerImage::Wordpress => "wordpress".to_string(),
             ContainerImage::Unknown => "unknown".to_string(),
         }
     }
 
+    /// The image repository, which is the service name except for images outside the
+    /// official library.
+    pub fn repository(&self) -> String {
+        match self {
+            ContainerImage::Mailpit => "axllent/mailpit".to_string(),
+            image => image.to_string(),
+        }
+    }
+
+    /// The image reference, with tag, that containers are created from.
+    pub fn image_ref(&self) -> String {
+        format!("{}:latest", self.repository())
+    }
+
     pub fn from_str(image: &str) -> Self {
         match image {
             "adminer" => ContainerImage::Adminer,
             "mysql" => ContainerImage::MySQL,
+            "mariadb" => ContainerImage::MariaDB,
             "nginx" => ContainerImage::Nginx,
+            "redis" => ContainerImage::Redis,
+            "mailpit" => ContainerImage::Mailpit,
             "wordpress" => ContainerImage::Wordpress,
             _ => ContainerImage::Unknown,
         }
     }
+
+    /// Classifies a full image reference such as `docker.io/library/mariadb:10.11`, ignoring
+    /// the registry and tag. The WP-CLI image isn't a service.
+    pub fn from_image_ref(image_ref: &str) -> Self {
+        let name = image_ref.rsplit('/').next().unwrap_or(image_ref);
+        let (name, tag) = name.split_once(':').unwrap_or((name, ""));
+        match (name, tag) {
+            ("wordpress", tag) if tag.starts_with("cli") => ContainerImage::Unknown,
+            (name, _) => Self::from_str(name),
+        }
+    }
+
+    /// Whether this is the instance's database server, MySQL or MariaDB.
+    pub fn is_database(&self) -> bool {
+        matches!(self, ContainerImage::MySQL | ContainerImage::MariaDB)
+    }
 }
 
 impl ContainerStatus {
     pub fn from_str(status: &str) -> Self {
         match status {
+            "created" => ContainerStatus::Created,
             "running" => ContainerStatus::Running,
             "stopped" => ContainerStatus::Stopped,
             "restarting" => ContainerStatus::Restarting,
@@ -124,26 +444,249 @@ impl ContainerStatus {
     }
 }
 
+/// A bind mount for a container. Without a `host_path` the container's directory inside the
+/// instance directory is mounted.
+#[derive(Clone, Debug, Serialize)]
+pub struct VolumeBinding {
+    pub host_path: Option<PathBuf>,
+    pub container_path: String,
+}
+
+/// A container port published on the host.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct HostPort {
+    pub host_port: u32,
+    pub container_port: u32,
+}
+
+/// Everything `InstanceContainer::new` needs to create a container. Build one with
+/// `ContainerSpec::builder`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ContainerSpec {
+    pub namespace: String,
+    pub instance_label: String,
+    pub instance_path: PathBuf,
+    pub image: ContainerImage,
+    /// Image tag, `None` for `latest`.
+    pub tag: Option<String>,
+    pub labels: HashMap<String, String>,
+    pub env: Vec<String>,
+    pub user: Option<String>,
+    pub volume: Option<VolumeBinding>,
+    /// Bind mounts on top of `volume`.
+    pub binds: Vec<VolumeBinding>,
+    pub ports: Vec<HostPort>,
+    pub log_rotation: Option<LogRotation>,
+    pub network: Option<String>,
+    pub read_only: bool,
+    /// Container paths mounted as tmpfs, so they stay writable with `read_only`.
+    pub tmpfs: Vec<String>,
+    pub limits: ResourceLimits,
+    pub restart_policy: ContainerRestartPolicy,
+}
+
+impl ContainerSpec {
+    pub fn builder(
+        namespace: &str,
+        instance_label: &str,
+        instance_path: &Path,
+        image: ContainerImage,
+    ) -> ContainerSpecBuilder {
+        ContainerSpecBuilder {
+            spec: ContainerSpec {
+                namespace: namespace.to_string(),
+                instance_label: instance_label.to_string(),
+                instance_path: instance_path.to_path_buf(),
+                image,
+                tag: None,
+                labels: HashMap::new(),
+                env: Vec::new(),
+                user: None,
+                volume: None,
+                binds: Vec::new(),
+                ports: Vec::new(),
+                log_rotation: None,
+                network: None,
+                read_only: false,
+                tmpfs: Vec::new(),
+                limits: ResourceLimits::default(),
+                restart_policy: ContainerRestartPolicy::default(),
+            },
+        }
+    }
+
+    /// The image reference, with tag, the container is created from.
+    pub fn image_ref(&self) -> String {
+        match &self.tag {
+            Some(tag) => format!("{}:{}", self.image.repository(), tag),
+            None => self.image.image_ref(),
+        }
+    }
+}
+
+pub struct ContainerSpecBuilder {
+    spec: ContainerSpec,
+}
+
+impl ContainerSpecBuilder {
+    pub fn tag(mut self, tag: Option<&str>) -> Self {
+        self.spec.tag = tag.map(str::to_string);
+        self
+    }
+
+    pub fn labels(mut self, labels: &HashMap<String, String>) -> Self {
+        self.spec.labels = labels.clone();
+        self
+    }
+
+    pub fn env(mut self, env: Vec<String>) -> Self {
+        self.spec.env = env;
+        self
+    }
+
+    pub fn user(mut self, user: &str) -> Self {
+        self.spec.user = Some(user.to_string());
+        self
+    }
+
+    pub fn volume(mut self, host_path: Option<PathBuf>, container_path: &str) -> Self {
+        self.spec.volume = Some(VolumeBinding {
+            host_path,
+            container_path: container_path.to_string(),
+        });
+        self
+    }
+
+    /// Adds a bind mount of `host_path`, can be called repeatedly.
+    pub fn bind(mut self, host_path: &Path, container_path: &str) -> Self {
+        self.spec.binds.push(VolumeBinding {
+            host_path: Some(host_path.to_path_buf()),
+            container_path: container_path.to_string(),
+        });
+        self
+    }
+
+    /// Publishes `container_port` on `host_port`, can be called repeatedly.
+    pub fn port(mut self, host_port: u32, container_port: u32) -> Self {
+        self.spec.ports.push(HostPort {
+            host_port,
+            container_port,
+        });
+        self
+    }
+
+    pub fn log_rotation(mut self, log_rotation: &LogRotation) -> Self {
+        self.spec.log_rotation = Some(log_rotation.clone());
+        self
+    }
+
+    /// Picks the container's limits from `limits`, keyed by service name. Services missing
+    /// from it are left unlimited.
+    pub fn limits(mut self, limits: &BTreeMap<String, ResourceLimits>) -> Self {
+        self.spec.limits = limits
+            .get(&self.spec.image.to_string())
+            .copied()
+            .unwrap_or_default();
+        self
+    }
+
+    pub fn restart_policy(mut self, restart_policy: ContainerRestartPolicy) -> Self {
+        self.spec.restart_policy = restart_policy;
+        self
+    }
+
+    /// Attaches the container to `network` instead of the instance's own network.
+    pub fn network(mut self, network: &str) -> Self {
+        self.spec.network = Some(network.to_string());
+        self
+    }
+
+    /// With `enabled`, makes the root filesystem read-only and mounts `writable_paths` as
+    /// tmpfs. The volume stays writable, so data written there is still persisted.
+    pub fn read_only(mut self, enabled: bool, writable_paths: &[&str]) -> Self {
+        if enabled {
+            self.spec.read_only = true;
+            self.spec.tmpfs = writable_paths.iter().map(|path| path.to_string()).collect();
+        }
+        self
+    }
+
+    pub fn build(self) -> ContainerSpec {
+        self.spec
+    }
+}
+
+/// Raw output of a command run in a container.
+pub struct CommandOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i64,
+}
+
+impl CommandOutput {
+    /// Collapses the output into stdout followed by stderr, failing on a non-zero exit code.
+    pub fn into_result(self) -> Result<String> {
+        let mut output = String::from_utf8_lossy(&self.stdout).to_string();
+        output.push_str(&String::from_utf8_lossy(&self.stderr));
+        if self.exit_code != 0 {
+            error!("Command exited with code {}: {}", self.exit_code, output);
+            return Err(AnyhowError::msg(format!(
+                "Command exited with code {}: {}",
+                self.exit_code,
+                output.trim()
+            )));
+        }
+        Ok(output)
+    }
+}
+
+/// Resource usage of a single container, memory is in bytes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContainerStats {
+    pub container_id: String,
+    pub container_image: ContainerImage,
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InstanceContainer {
     pub container_id: String,
     pub container_image: ContainerImage,
     pub container_status: ContainerStatus,
+    /// The image reference the container was created from, e.g. `wordpress:6.5-php8.2-apache`.
+    #[serde(default)]
+    pub image: String,
 }
 
 impl InstanceContainer {
-    pub async fn new(
-        instance_label: &str,
-        instance_path: &PathBuf,
-        container_image: ContainerImage,
-        labels: &HashMap<String, String>,
-        env_vars: Vec<String>,
-        user: Option<String>,
-        volume_binding: Option<(Option<PathBuf>, &str)>,
-        port: Option<(u32, u32)>,
-    ) -> Result<(String, ContainerStatus)> {
+    pub async fn new(spec: ContainerSpec) -> Result<(String, ContainerStatus)> {
+        let image_ref = spec.image_ref();
+        let ContainerSpec {
+            namespace,
+            instance_label,
+            instance_path,
+            image: container_image,
+            tag: _,
+            labels,
+            env: env_vars,
+            user,
+            volume,
+            binds,
+            ports,
+            log_rotation,
+            network,
+            read_only,
+            tmpfs,
+            limits,
+            restart_policy,
+        } = spec;
         info!("Creating container for image: {:?}", container_image);
-        let docker = Docker::connect_with_defaults().context("Failed to connect to Docker")?;
+        let docker = crate::docker::connect_docker().await?;
+        // Only the images in the config are pulled up front, other tags and MariaDB are
+        // pulled on demand.
+        crate::config::pull_docker_image(&image_ref).await?;
         let config_dir = instance_path.join(&container_image.to_string());
 
         let path = utils::create_path(&config_dir)
@@ -153,11 +696,15 @@ impl InstanceContainer {
             .to_str()
             .context("Failed to convert instance directory to string")?;
 
-        let container_labels = utils::create_labels(container_image.clone(), labels.clone());
+        let container_labels = utils::create_labels(container_image.clone(), labels);
         let labels_view = container_labels.into_iter().collect();
 
         let mut port_bindings = HashMap::new();
-        if let Some((host_port, container_port)) = port {
+        for HostPort {
+            host_port,
+            container_port,
+        } in &ports
+        {
             let port_key = format!("{}/tcp", container_port);
             let binding = PortBinding {
                 host_ip: None,
@@ -166,38 +713,83 @@ impl InstanceContainer {
             port_bindings.insert(port_key, Some(vec![binding]));
         }
 
-        let host_config = HostConfig {
-            binds: match volume_binding {
-                Some((Some(config_path), container_path)) => {
+        let mut container_binds = Vec::new();
+        for binding in volume.into_iter().chain(binds) {
+            match binding {
+                VolumeBinding {
+                    host_path: Some(config_path),
+                    container_path,
+                } => {
                     let config_path_str = config_path
                         .to_str()
                         .context("Failed to convert config path to string")?;
-                    Some(vec![format!("{}:{}", config_path_str, container_path)])
-                }
-                Some((None, container_path)) => {
-                    Some(vec![format!("{}:{}", path_str, container_path)])
+                    container_binds.push(format!("{}:{}", config_path_str, container_path));
                 }
-                None => None,
+                VolumeBinding {
+                    host_path: None,
+                    container_path,
+                } => container_binds.push(format!("{}:{}", path_str, container_path)),
+            }
+        }
+
+        let host_config = HostConfig {
+            binds: if container_binds.is_empty() {
+                None
+            } else {
+                Some(container_binds)
             },
-            network_mode: Some(format!(
-                "{}-{}",
-                crate::NETWORK_NAME.to_string(),
-                instance_label
-            )),
+            network_mode: Some(
+                network.unwrap_or_else(|| format!("{}-{}", namespace, instance_label)),
+            ),
             port_bindings: if port_bindings.is_empty() {
                 None
             } else {
                 Some(port_bindings)
             },
-            restart_policy: Some(RestartPolicy {
-                name: Some(RestartPolicyNameEnum::ON_FAILURE),
-                maximum_retry_count: Some(3),
+            restart_policy: Some(match restart_policy {
+                ContainerRestartPolicy::No => RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::NO),
+                    maximum_retry_count: None,
+                },
+                ContainerRestartPolicy::OnFailure => RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                    maximum_retry_count: Some(3),
+                },
+                ContainerRestartPolicy::UnlessStopped => RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                    maximum_retry_count: None,
+                },
+                ContainerRestartPolicy::Always => RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::ALWAYS),
+                    maximum_retry_count: None,
+                },
+            }),
+            log_config: log_rotation.map(|log_rotation| HostConfigLogConfig {
+                typ: Some("json-file".to_string()),
+                config: Some(HashMap::from([
+                    ("max-size".to_string(), log_rotation.max_size),
+                    ("max-file".to_string(), log_rotation.max_files.to_string()),
+                ])),
             }),
+            readonly_rootfs: read_only.then_some(true),
+            memory: limits.memory_bytes(),
+            memory_swap: limits.memory_bytes(),
+            nano_cpus: limits.nano_cpus(),
+            tmpfs: if tmpfs.is_empty() {
+                None
+            } else {
+                Some(
+                    tmpfs
+                        .into_iter()
+                        .map(|path| (path, String::new()))
+                        .collect(),
+                )
+            },
             ..Default::default()
         };
 
         let mut container_config = Config {
-            image: Some(container_image.to_string()),
+            image: Some(image_ref),
             env: Some(env_vars),
             labels: Some(labels_view),
             user,
@@ -205,9 +797,11 @@ impl InstanceContainer {
             ..Default::default()
         };
 
-        if let Some((_, container_port)) = port {
-            let port_key = format!("{}/tcp", container_port);
-            let exposed_ports = HashMap::from([(port_key.clone(), HashMap::new())]);
+        if !ports.is_empty() {
+            let exposed_ports = ports
+                .iter()
+                .map(|port| (format!("{}/tcp", port.container_port), HashMap::new()))
+                .collect();
             container_config.exposed_ports = Some(exposed_ports);
         }
 
@@ -244,13 +838,20 @@ impl InstanceContainer {
 
     pub async fn get_status(docker: &Docker, container_id: &str) -> Result<ContainerStatus> {
         info!("Getting status for container: {}", container_id);
-        let container_info = docker
-            .inspect_container(container_id, None)
-            .await
-            .context("Failed to inspect container")?;
+        let container_info = utils::retry(
+            || docker.inspect_container(container_id, None),
+            DOCKER_RETRY_ATTEMPTS,
+            DOCKER_RETRY_BACKOFF,
+        )
+        .await
+        .context("Failed to inspect container")?;
         let status = match container_info.state.and_then(|state| state.status) {
-            Some(bollard::models::ContainerStateStatusEnum::RUNNING) => ContainerStatus::Running,
-            Some(bollard::models::ContainerStateStatusEnum::EXITED) => ContainerStatus::Stopped,
+            Some(ContainerStateStatusEnum::CREATED) => ContainerStatus::Created,
+            Some(ContainerStateStatusEnum::RUNNING) => ContainerStatus::Running,
+            Some(ContainerStateStatusEnum::EXITED) => ContainerStatus::Stopped,
+            Some(ContainerStateStatusEnum::PAUSED) => ContainerStatus::Paused,
+            Some(ContainerStateStatusEnum::RESTARTING) => ContainerStatus::Restarting,
+            Some(ContainerStateStatusEnum::DEAD) => ContainerStatus::Dead,
             _ => ContainerStatus::Unknown,
         };
         Ok(status)
@@ -285,6 +886,32 @@ impl InstanceContainer {
         .await
     }
 
+    pub async fn pause(docker: &Docker, container_id: &str) -> Result<InstanceContainer> {
+        handle_container(docker, container_id, ContainerOperation::Pause).await
+    }
+
+    pub async fn unpause(docker: &Docker, container_id: &str) -> Result<InstanceContainer> {
+        handle_container(docker, container_id, ContainerOperation::Unpause).await
+    }
+
+    /// Applies `limits` to an existing container, running or not.
+    pub async fn update_limits(
+        docker: &Docker,
+        container_id: &str,
+        limits: &ResourceLimits,
+    ) -> Result<()> {
+        let options = UpdateContainerOptions::<String> {
+            memory: limits.memory_bytes(),
+            memory_swap: limits.memory_bytes(),
+            nano_cp_us: limits.nano_cpus(),
+            ..Default::default()
+        };
+        docker
+            .update_container(container_id, options)
+            .await
+            .with_context(|| format!("Failed to update limits of container {}", container_id))
+    }
+
     pub async fn delete(docker: &Docker, container_id: &str) -> Result<InstanceContainer> {
         handle_container(
             docker,
@@ -293,6 +920,304 @@ impl InstanceContainer {
         )
         .await
     }
+
+    /// Runs a command in a throwaway container and returns its combined output, failing if it
+    /// exits non-zero. The container is removed once the command exits.
+    pub async fn run_once(
+        docker: &Docker,
+        image: &str,
+        cmd: Vec<String>,
+        env: Vec<String>,
+        binds: Vec<String>,
+        network: &str,
+        user: Option<String>,
+    ) -> Result<String> {
+        Self::run_once_output(docker, image, cmd, env, binds, network, user)
+            .await?
+            .into_result()
+    }
+
+    /// Like `run_once`, but returns stdout, stderr and the exit code untouched instead of
+    /// failing on a non-zero exit.
+    pub async fn run_once_output(
+        docker: &Docker,
+        image: &str,
+        cmd: Vec<String>,
+        env: Vec<String>,
+        binds: Vec<String>,
+        network: &str,
+        user: Option<String>,
+    ) -> Result<CommandOutput> {
+        info!("Running {:?} in a one-off {} container", cmd, image);
+        let container_config = Config {
+            image: Some(image.to_string()),
+            cmd: Some(cmd),
+            env: Some(env),
+            user,
+            host_config: Some(HostConfig {
+                binds: Some(binds),
+                network_mode: Some(network.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let container_id = docker
+            .create_container(None::<CreateContainerOptions<String>>, container_config)
+            .await
+            .context("Failed to create one-off container")?
+            .id;
+
+        let result = async {
+            docker
+                .start_container(&container_id, None::<StartContainerOptions<String>>)
+                .await
+                .context("Failed to start one-off container")?;
+
+            let mut exit_code = 0;
+            let mut wait_stream =
+                docker.wait_container(&container_id, None::<WaitContainerOptions<String>>);
+            while let Some(response) = wait_stream.next().await {
+                match response {
+                    Ok(response) => exit_code = response.status_code,
+                    Err(bollard::errors::Error::DockerContainerWaitError { code, .. }) => {
+                        exit_code = code
+                    }
+                    Err(err) => return Err(AnyhowError::from(err)),
+                }
+            }
+
+            let mut output = CommandOutput {
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                exit_code,
+            };
+            let mut log_stream = docker.logs(
+                &container_id,
+                Some(LogsOptions::<String> {
+                    stdout: true,
+                    stderr: true,
+                    ..Default::default()
+                }),
+            );
+            while let Some(chunk) = log_stream.next().await {
+                match chunk.context("Failed to read container logs")? {
+                    LogOutput::StdErr { message } => output.stderr.extend_from_slice(&message),
+                    LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                        output.stdout.extend_from_slice(&message)
+                    }
+                    LogOutput::StdIn { .. } => {}
+                }
+            }
+            Ok(output)
+        }
+        .await;
+
+        docker
+            .remove_container(
+                &container_id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .context("Failed to remove one-off container")?;
+
+        result
+    }
+
+    /// Runs a command inside a running container and returns its combined output.
+    pub async fn exec(docker: &Docker, container_id: &str, cmd: Vec<&str>) -> Result<String> {
+        info!("Executing {:?} in container: {}", cmd, container_id);
+        let exec = docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    cmd: Some(cmd),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("Failed to create exec")?;
+
+        let mut output = String::new();
+        if let StartExecResults::Attached {
+            output: mut stream, ..
+        } = docker
+            .start_exec(&exec.id, None)
+            .await
+            .context("Failed to start exec")?
+        {
+            while let Some(chunk) = stream.next().await {
+                output.push_str(&chunk.context("Failed to read exec output")?.to_string());
+            }
+        }
+
+        Self::exec_result(docker, &exec.id, output).await
+    }
+
+    /// Runs a command inside a running container like `exec`, streaming `input` to its stdin
+    /// in chunks while the output is collected.
+    pub async fn exec_with_input(
+        docker: &Docker,
+        container_id: &str,
+        cmd: Vec<&str>,
+        mut input: impl Read + Send,
+    ) -> Result<String> {
+        info!(
+            "Executing {:?} with input in container: {}",
+            cmd, container_id
+        );
+        let exec = docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    cmd: Some(cmd),
+                    attach_stdin: Some(true),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("Failed to create exec")?;
+
+        let mut output = String::new();
+        if let StartExecResults::Attached {
+            output: mut stream,
+            input: mut stdin,
+        } = docker
+            .start_exec(&exec.id, None)
+            .await
+            .context("Failed to start exec")?
+        {
+            let write = async {
+                let mut buffer = vec![0; EXEC_INPUT_CHUNK_SIZE];
+                loop {
+                    let read = input.read(&mut buffer).context("Failed to read input")?;
+                    if read == 0 {
+                        break;
+                    }
+                    stdin
+                        .write_all(&buffer[..read])
+                        .await
+                        .context("Failed to write to exec stdin")?;
+                }
+                stdin.shutdown().await.context("Failed to close exec stdin")
+            };
+            let read = async {
+                while let Some(chunk) = stream.next().await {
+                    output.push_str(&chunk.context("Failed to read exec output")?.to_string());
+                }
+                Ok::<_, AnyhowError>(())
+            };
+            let (written, read) = futures::future::join(write, read).await;
+            written?;
+            read?;
+        }
+
+        Self::exec_result(docker, &exec.id, output).await
+    }
+
+    /// Fails with `output` if the exec exited non-zero, otherwise returns it.
+    async fn exec_result(docker: &Docker, exec_id: &str, output: String) -> Result<String> {
+        let exit_code = docker
+            .inspect_exec(exec_id)
+            .await
+            .context("Failed to inspect exec")?
+            .exit_code
+            .unwrap_or_default();
+        if exit_code != 0 {
+            error!("Command exited with code {}: {}", exit_code, output);
+            return Err(AnyhowError::msg(format!(
+                "Command exited with code {}: {}",
+                exit_code,
+                output.trim()
+            )));
+        }
+
+        Ok(output)
+    }
+
+    /// Samples a container's CPU and memory usage. Docker waits for a second sample to
+    /// compute the CPU delta, so this takes about a second.
+    pub async fn stats(
+        docker: &Docker,
+        container_id: &str,
+        container_image: ContainerImage,
+    ) -> Result<ContainerStats> {
+        info!("Getting stats for container: {}", container_id);
+        let stats = docker
+            .stats(
+                container_id,
+                Some(StatsOptions {
+                    stream: false,
+                    one_shot: false,
+                }),
+            )
+            .next()
+            .await
+            .ok_or_else(|| AnyhowError::msg("No stats returned for container"))?
+            .context("Failed to get container stats")?;
+
+        // Same calculation as `docker stats`.
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or_default()
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+        let online_cpus = stats.cpu_stats.online_cpus.unwrap_or_else(|| {
+            stats
+                .cpu_stats
+                .cpu_usage
+                .percpu_usage
+                .as_ref()
+                .map_or(1, |usage| usage.len() as u64)
+        });
+        let cpu_percent = if system_delta > 0 {
+            cpu_delta as f64 / system_delta as f64 * online_cpus as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        Ok(ContainerStats {
+            container_id: container_id.to_string(),
+            container_image,
+            cpu_percent,
+            memory_usage: stats.memory_stats.usage.unwrap_or_default(),
+            memory_limit: stats.memory_stats.limit.unwrap_or_default(),
+        })
+    }
+
+    /// Returns the last `tail` lines of a container's combined stdout and stderr.
+    pub async fn logs(docker: &Docker, container_id: &str, tail: usize) -> Result<String> {
+        info!(
+            "Fetching last {} log lines for container: {}",
+            tail, container_id
+        );
+        let mut output = String::new();
+        let mut log_stream = docker.logs(
+            container_id,
+            Some(LogsOptions::<String> {
+                stdout: true,
+                stderr: true,
+                tail: tail.to_string(),
+                ..Default::default()
+            }),
+        );
+        while let Some(chunk) = log_stream.next().await {
+            output.push_str(&chunk.context("Failed to read container logs")?.to_string());
+        }
+
+        Ok(output)
+    }
 }
 
 async fn handle_container(
@@ -304,7 +1229,7 @@ async fn handle_container(
     let container_info = docker
         .inspect_container(container_id, None)
         .await
-        .map_err(AnyhowError::from)?;
+        .map_err(WpdevError::from)?;
     let container_status = InstanceContainer::get_status(docker, container_id)
         .await
         .context("Failed to get container status")?;
@@ -321,10 +1246,13 @@ async fn handle_container(
         ContainerOperation::Start => {
             info!("Starting container: {}", container_id);
             if container_status != ContainerStatus::Running {
-                docker
-                    .start_container(container_id, None::<StartContainerOptions<String>>)
-                    .await
-                    .context("Failed to start container")?;
+                utils::retry(
+                    || docker.start_container(container_id, None::<StartContainerOptions<String>>),
+                    DOCKER_RETRY_ATTEMPTS,
+                    DOCKER_RETRY_BACKOFF,
+                )
+                .await
+                .context("Failed to start container")?;
                 info!("{} container successfully started", container_id);
             } else {
                 info!(
@@ -336,11 +1264,18 @@ async fn handle_container(
         ContainerOperation::Stop => {
             info!("Stopping container: {}", container_id);
             match container_status {
-                ContainerStatus::Running => {
-                    docker
-                        .stop_container(container_id, None::<StopContainerOptions>)
-                        .await
-                        .context("Failed to stop container")?;
+                // A container the restart policy keeps restarting has to be stopped too,
+                // otherwise Docker brings it back.
+                ContainerStatus::Running
+                | ContainerStatus::Restarting
+                | ContainerStatus::Paused => {
+                    utils::retry(
+                        || docker.stop_container(container_id, None::<StopContainerOptions>),
+                        DOCKER_RETRY_ATTEMPTS,
+                        DOCKER_RETRY_BACKOFF,
+                    )
+                    .await
+                    .context("Failed to stop container")?;
                     info!("{} container successfully stopped", container_id);
                 }
                 ContainerStatus::Stopped => {
@@ -361,17 +1296,28 @@ async fn handle_container(
             info!("Restarting container: {}", container_id);
             match container_status {
                 ContainerStatus::Running => {
-                    docker
-                        .restart_container(container_id, None::<RestartContainerOptions>)
-                        .await
-                        .context("Failed to restart container")?;
+                    utils::retry(
+                        || docker.restart_container(container_id, None::<RestartContainerOptions>),
+                        DOCKER_RETRY_ATTEMPTS,
+                        DOCKER_RETRY_BACKOFF,
+                    )
+                    .await
+                    .context("Failed to restart container")?;
                     info!("{} container successfully restarted", container_id);
                 }
                 ContainerStatus::Stopped => {
-                    docker
-                        .start_container(container_id, None::<StartContainerOptions<String>>)
-                        .await
-                        .context("Failed to start container")?;
+                    utils::retry(
+                        || {
+                            docker.start_container(
+                                container_id,
+                                None::<StartContainerOptions<String>>,
+                            )
+                        },
+                        DOCKER_RETRY_ATTEMPTS,
+                        DOCKER_RETRY_BACKOFF,
+                    )
+                    .await
+                    .context("Failed to start container")?;
                     info!("{} container successfully started", container_id);
                 }
                 _ => {
@@ -382,18 +1328,70 @@ async fn handle_container(
                 }
             }
         }
-        ContainerOperation::Delete => {
-            info!("Deleting container: {}", container_id);
-            if container_status == ContainerStatus::Running {
-                docker
-                    .stop_container(container_id, None::<StopContainerOptions>)
+        ContainerOperation::Pause => {
+            info!("Pausing container: {}", container_id);
+            match container_status {
+                ContainerStatus::Running => {
+                    utils::retry(
+                        || docker.pause_container(container_id),
+                        DOCKER_RETRY_ATTEMPTS,
+                        DOCKER_RETRY_BACKOFF,
+                    )
                     .await
-                    .context("Failed to stop container")?;
+                    .context("Failed to pause container")?;
+                    info!("{} container successfully paused", container_id);
+                }
+                ContainerStatus::Paused => {
+                    info!(
+                        "{} container is already paused, skipping pause operation",
+                        container_id
+                    );
+                }
+                _ => {
+                    error!("Failed to pause container: {} is not running", container_id);
+                }
             }
-            docker
-                .remove_container(container_id, None::<RemoveContainerOptions>)
+        }
+        ContainerOperation::Unpause => {
+            info!("Unpausing container: {}", container_id);
+            if container_status == ContainerStatus::Paused {
+                utils::retry(
+                    || docker.unpause_container(container_id),
+                    DOCKER_RETRY_ATTEMPTS,
+                    DOCKER_RETRY_BACKOFF,
+                )
+                .await
+                .context("Failed to unpause container")?;
+                info!("{} container successfully unpaused", container_id);
+            } else {
+                info!(
+                    "{} container isn't paused, skipping unpause operation",
+                    container_id
+                );
+            }
+        }
+        ContainerOperation::Delete => {
+            info!("Deleting container: {}", container_id);
+            // Docker won't remove a paused or restarting container without force either.
+            if matches!(
+                container_status,
+                ContainerStatus::Running | ContainerStatus::Restarting | ContainerStatus::Paused
+            ) {
+                utils::retry(
+                    || docker.stop_container(container_id, None::<StopContainerOptions>),
+                    DOCKER_RETRY_ATTEMPTS,
+                    DOCKER_RETRY_BACKOFF,
+                )
                 .await
-                .context("Failed to remove container")?;
+                .context("Failed to stop container")?;
+            }
+            utils::retry(
+                || docker.remove_container(container_id, None::<RemoveContainerOptions>),
+                DOCKER_RETRY_ATTEMPTS,
+                DOCKER_RETRY_BACKOFF,
+            )
+            .await
+            .context("Failed to remove container")?;
             info!("{} container successfully deleted", container_id);
         }
         ContainerOperation::Inspect => {
@@ -406,5 +1404,6 @@ async fn handle_container(
         container_id: container_id.to_string(),
         container_image: ContainerImage::from_str(&container_image_label),
         container_status,
+        image: container_config.image.unwrap_or_default(),
     })
 }
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use bollard::system::EventsOptions;
+use bollard::Docker;
+use futures::stream::{Stream, StreamExt};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config;
+use crate::docker::container::ContainerImage;
+
+/// Docker container actions that change an instance's status.
+const WATCHED_ACTIONS: [&str; 5] = ["start", "stop", "die", "restart", "destroy"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum InstanceEventKind {
+    Started,
+    Stopped,
+    Died,
+    Restarted,
+    /// The container was removed, e.g. because its instance was deleted.
+    Removed,
+}
+
+impl InstanceEventKind {
+    fn from_action(action: &str) -> Option<Self> {
+        match action {
+            "start" => Some(InstanceEventKind::Started),
+            "stop" => Some(InstanceEventKind::Stopped),
+            "die" => Some(InstanceEventKind::Died),
+            "restart" => Some(InstanceEventKind::Restarted),
+            "destroy" => Some(InstanceEventKind::Removed),
+            _ => None,
+        }
+    }
+}
+
+/// A state change of one of an instance's containers, as reported by Docker.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceEvent {
+    pub instance_id: String,
+    pub container_id: String,
+    pub container_image: ContainerImage,
+    pub kind: InstanceEventKind,
+}
+
+/// Subscribes to Docker's event stream and yields the container state changes of the
+/// instances in `namespace`. Containers are mapped back to their instance through the
+/// `instance` label wpdev sets on them. The stream only ends if the Docker connection does.
+pub fn watch_events(docker: &Docker, namespace: &str) -> impl Stream<Item = Result<InstanceEvent>> {
+    info!("Watching Docker events for namespace: {}", namespace);
+    let filters = HashMap::from([
+        ("type".to_string(), vec!["container".to_string()]),
+        (
+            "event".to_string(),
+            WATCHED_ACTIONS
+                .iter()
+                .map(|action| action.to_string())
+                .collect(),
+        ),
+        ("label".to_string(), vec!["instance".to_string()]),
+    ]);
+    let namespace = namespace.to_string();
+    docker
+        .events(Some(EventsOptions::<String> {
+            filters,
+            ..Default::default()
+        }))
+        .filter_map(move |event| {
+            let namespace = namespace.clone();
+            async move {
+                let event = match event.context("Failed to read Docker event") {
+                    Ok(event) => event,
+                    Err(e) => return Some(Err(e)),
+                };
+                let kind = InstanceEventKind::from_action(event.action.as_deref()?)?;
+                let actor = event.actor?;
+                let attributes = actor.attributes.unwrap_or_default();
+                let instance_id = format!("{}-{}", namespace, attributes.get("instance")?);
+                // Labels don't record the namespace, so skip instances that aren't in this one.
+                // A removed container's instance directory may already be purged, so those are
+                // let through for the caller to check.
+                let instance_dir = config::get_instance_dir().await.ok()?;
+                if !matches!(kind, InstanceEventKind::Removed)
+                    && !instance_dir.join(&instance_id).exists()
+                {
+                    return None;
+                }
+                Some(Ok(InstanceEvent {
+                    instance_id,
+                    container_id: actor.id.unwrap_or_default(),
+                    container_image: ContainerImage::from_image_ref(
+                        attributes.get("image").map_or("", String::as_str),
+                    ),
+                    kind,
+                }))
+            }
+        })
+}
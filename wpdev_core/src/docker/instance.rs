@@ -1,25 +1,53 @@
 use anyhow::{Context, Error as AnyhowError, Result};
-use bollard::container::ListContainersOptions;
+use bollard::container::{
+    DownloadFromContainerOptions, ListContainersOptions, LogsOptions, UploadToContainerOptions,
+};
+use bollard::models::ContainerSummary;
+use bollard::network::DisconnectNetworkOptions;
 use bollard::Docker;
-use dirs;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures::future::join_all;
-use log::{error, info};
+use futures::stream::{self, BoxStream, StreamExt};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
+pub use tokio_util::sync::CancellationToken;
 
 use crate::config::{self};
+use crate::docker::compose;
 use crate::docker::config::{
-    configure_adminer_container, configure_mysql_container, configure_nginx_container,
-    configure_wordpress_container,
+    adminer_container_spec, configure_external_database, generate_tls_certificate,
+    mailpit_container_spec, mysql_container_spec, nginx_container_spec, redis_container_spec,
+    wordpress_container_spec,
 };
 use crate::docker::container::{
-    ContainerEnvVars, ContainerImage, ContainerStatus, InstanceContainer,
+    CommandOutput, ContainerEnvVars, ContainerImage, ContainerSpec, ContainerStats,
+    ContainerStatus, DatabaseEngine, DebugSettings, EnvVars, ExternalDatabase, InstanceContainer,
+    LogRotation, ResourceLimits,
 };
+use crate::metrics::{Operation, OperationTimer};
 use crate::utils;
 
-#[derive(Serialize, Deserialize)]
+const BULK_CONCURRENCY: usize = 4;
+const REMOVE_DIR_ATTEMPTS: u32 = 5;
+const REMOVE_DIR_DELAY: Duration = Duration::from_millis(500);
+const READINESS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const COPY_READY_TIMEOUT: Duration = Duration::from_secs(120);
+/// Where the MySQL and MariaDB images keep their data, in an anonymous volume.
+const DATABASE_DATA_DIR: &str = "/var/lib/mysql";
+const BACKUP_SNAPSHOT_FILE: &str = "snapshot.json";
+const BACKUP_DATABASE_FILE: &str = "database.tar";
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Instance {
     pub uuid: String,
     pub status: InstanceStatus,
@@ -27,9 +55,29 @@ pub struct Instance {
     pub nginx_port: u32,
     pub adminer_port: u32,
     pub wordpress_data: Option<InstanceData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_updates: Option<Vec<ImageUpdate>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin_results: Option<Vec<PluginResult>>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Whether the registry has a newer build of an image than the one the instance runs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageUpdate {
+    pub image: String,
+    pub update_available: bool,
+}
+
+/// Outcome of installing one of `install_plugins` with `Instance::install_plugins`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PluginResult {
+    pub plugin: String,
+    pub installed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct InstanceData {
     pub admin_user: String,
     pub admin_password: String,
@@ -42,9 +90,308 @@ pub struct InstanceData {
     pub network_name: String,
     pub nginx_port: u32,
     pub adminer_port: u32,
+    #[serde(default)]
+    pub external_db_host: Option<String>,
+    #[serde(default)]
+    pub debug: DebugSettings,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    #[serde(default)]
+    pub external_network: Option<String>,
+    /// Set for instances imported with `Instance::adopt`, whose containers carry no wpdev
+    /// labels.
+    #[serde(default)]
+    pub adopted: bool,
+    /// Set when the ports were chosen with `--nginx-port`/`--adminer-port`. Pinned ports are
+    /// never reallocated, starting fails instead if one is taken.
+    #[serde(default)]
+    pub pinned_ports: bool,
+    /// Set when the containers were created with a read-only root filesystem.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Host plugin directories mounted into `wp-content/plugins`.
+    #[serde(default)]
+    pub plugins: Vec<PathBuf>,
+    /// Pinned instances are listed before the others.
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub database_engine: DatabaseEngine,
+    /// Root password of the database container. Empty for instances created before it was
+    /// recorded and for adopted ones.
+    #[serde(default)]
+    pub database_root_password: String,
+    /// Set when the instance has a Redis container.
+    #[serde(default)]
+    pub redis: bool,
+    /// Host port of the Mailpit web UI, set when the instance has a Mailpit container.
+    #[serde(default)]
+    pub mailpit_port: Option<u32>,
+    #[serde(default)]
+    pub mailpit_url: Option<String>,
+    /// Host port the database is published on, set when it was exposed at create.
+    #[serde(default)]
+    pub mysql_port: Option<u32>,
+    /// Host port nginx serves HTTPS on, set when TLS was enabled at create.
+    #[serde(default)]
+    pub tls_port: Option<u32>,
+    /// Name given at create, which commands accept in place of the instance id.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// When the instance was created, or adopted. `None` for instances created before it was
+    /// recorded.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the instance was last started, stopped or restarted.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Plugin slugs or zip URLs to install once WordPress is installed.
+    #[serde(default)]
+    pub install_plugins: Vec<String>,
+    /// Tag of the `wordpress` image, `None` for `latest`.
+    #[serde(default)]
+    pub wordpress_tag: Option<String>,
+    /// Limits of each service, keyed by service name, re-applied by `Instance::start`.
+    #[serde(default)]
+    pub limits: BTreeMap<String, ResourceLimits>,
+}
+
+/// Shown in place of a site or Adminer URL whose port couldn't be determined.
+pub const UNKNOWN_URL: &str = "unknown";
+
+/// Shown in place of passwords in output that wasn't asked to include secrets.
+pub const REDACTED: &str = "********";
+
+impl InstanceData {
+    /// The instance's `name`, or its site title when it wasn't given one.
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.site_title.clone())
+    }
+
+    /// Replaces the admin and Adminer passwords with `REDACTED`. Only for output, never write
+    /// redacted data back to `instance.toml`.
+    pub fn redact_secrets(&mut self) {
+        self.admin_password = REDACTED.to_string();
+        self.adminer_password = REDACTED.to_string();
+        if !self.database_root_password.is_empty() {
+            self.database_root_password = REDACTED.to_string();
+        }
+    }
+
+    /// Fills in ports recorded as 0 with the ports the containers actually publish. A port
+    /// that's still unknown leaves its URL as `UNKNOWN_URL` instead of a dead `:0` link. Only
+    /// for output, `instance.toml` keeps what was written at create time.
+    fn fill_missing_ports(&mut self, nginx_port: Option<u32>, adminer_port: Option<u32>) {
+        fn fill(port: &mut u32, url: &mut String, published: Option<u32>) {
+            if *port != 0 {
+                return;
+            }
+            match (published, url.strip_suffix(":0")) {
+                (Some(published), Some(base)) => {
+                    *url = format!("{}:{}", base, published);
+                    *port = published;
+                }
+                (Some(published), None) => *port = published,
+                (None, _) => *url = UNKNOWN_URL.to_string(),
+            }
+        }
+        fill(&mut self.nginx_port, &mut self.site_url, nginx_port);
+        fill(&mut self.adminer_port, &mut self.adminer_url, adminer_port);
+    }
+
+    /// The Docker network the instance's containers are attached to.
+    pub fn docker_network(&self) -> &str {
+        self.external_network
+            .as_deref()
+            .unwrap_or(&self.network_name)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminLogin {
+    pub url: String,
+    pub installed: bool,
+    pub admin_user: String,
+    pub admin_password: String,
+}
+
+/// The image and env of a stopped instance's WordPress and database containers, which
+/// `Instance::duplicate` and `Instance::restore` recreate it from.
+#[derive(Serialize, Deserialize)]
+struct InstanceSnapshot {
+    wordpress_image: String,
+    wordpress_env: Vec<String>,
+    database_env: Vec<String>,
+    /// How the backup the snapshot was written to is compressed, `None` outside backups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<BackupCompression>,
+}
+
+impl InstanceSnapshot {
+    /// Settings for a copy of the instance. The copied database only accepts the original
+    /// credentials, so those are carried over; the database host is the copy's own.
+    fn env_vars(&self, instance_data: &InstanceData) -> ContainerEnvVars {
+        let engine = instance_data.database_engine;
+        let overrides = |env: &[String], prefix: &str| -> HashMap<String, String> {
+            env.iter()
+                .filter_map(|var| var.split_once('='))
+                .filter(|(key, _)| key.starts_with(prefix) && *key != "WORDPRESS_DB_HOST")
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let (wordpress_version, php_version) = config::split_wordpress_tag(&self.wordpress_image);
+        ContainerEnvVars {
+            wordpress: Some(overrides(&self.wordpress_env, "WORDPRESS_")),
+            mysql: Some(overrides(&self.database_env, &engine.env_var("MYSQL_"))),
+            debug: instance_data.debug.clone(),
+            log_max_size: Some(instance_data.log_rotation.max_size.clone()),
+            log_max_files: Some(instance_data.log_rotation.max_files),
+            external_network: instance_data.external_network.clone(),
+            read_only: instance_data.read_only,
+            plugins: Some(instance_data.plugins.clone()),
+            wordpress_version,
+            php_version,
+            database_engine: engine,
+            admin_user: Some(instance_data.admin_user.clone()),
+            admin_password: Some(instance_data.admin_password.clone()),
+            admin_email: Some(instance_data.admin_email.clone()),
+            site_title: Some(instance_data.site_title.clone()),
+            enable_redis: instance_data.redis,
+            enable_mail: instance_data.mailpit_port.is_some(),
+            expose_mysql_port: instance_data.mysql_port.is_some(),
+            enable_tls: instance_data.tls_port.is_some(),
+            container_limits: Some(instance_data.limits.clone()),
+            ..ContainerEnvVars::default()
+        }
+    }
+}
+
+/// How `Instance::backup` compresses its archive. `Instance::restore` detects it from the
+/// archive's first bytes, so it doesn't need to be given again.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupCompression {
+    None,
+    #[default]
+    Gzip,
+    /// Smaller and faster than gzip, most of all for uploads.
+    Zstd,
+}
+
+impl BackupCompression {
+    pub fn parse(compression: &str) -> Result<Self> {
+        match compression.to_lowercase().as_str() {
+            "none" => Ok(BackupCompression::None),
+            "gzip" => Ok(BackupCompression::Gzip),
+            "zstd" => Ok(BackupCompression::Zstd),
+            _ => Err(AnyhowError::msg(format!(
+                "Unknown compression {}, expected none, gzip or zstd",
+                compression
+            ))),
+        }
+    }
+
+    /// The compression of an archive starting with `magic`, a plain tar unless it starts with
+    /// the gzip or zstd magic number.
+    fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            BackupCompression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            BackupCompression::Zstd
+        } else {
+            BackupCompression::None
+        }
+    }
+}
+
+/// The file a backup is written to, behind the encoder of its compression.
+enum BackupWriter {
+    None(std::fs::File),
+    Gzip(GzEncoder<std::fs::File>),
+    Zstd(zstd::Encoder<'static, std::fs::File>),
+}
+
+impl BackupWriter {
+    fn new(file: std::fs::File, compression: BackupCompression) -> std::io::Result<Self> {
+        Ok(match compression {
+            BackupCompression::None => BackupWriter::None(file),
+            BackupCompression::Gzip => {
+                BackupWriter::Gzip(GzEncoder::new(file, Compression::default()))
+            }
+            BackupCompression::Zstd => BackupWriter::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    /// Writes what the encoder still buffers and flushes the file.
+    fn finish(self) -> std::io::Result<()> {
+        let mut file = match self {
+            BackupWriter::None(file) => file,
+            BackupWriter::Gzip(encoder) => encoder.finish()?,
+            BackupWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for BackupWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            BackupWriter::None(file) => file.write(buf),
+            BackupWriter::Gzip(encoder) => encoder.write(buf),
+            BackupWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            BackupWriter::None(file) => file.flush(),
+            BackupWriter::Gzip(encoder) => encoder.flush(),
+            BackupWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// A chunk of a container's stdout or stderr, as streamed by `Instance::logs`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogChunk {
+    pub container_id: String,
+    pub container_image: ContainerImage,
+    pub output: String,
+}
+
+/// The logins of an instance, as shown by `wpdev credentials`.
+#[derive(Serialize, Deserialize)]
+pub struct Credentials {
+    pub site_url: String,
+    pub admin_user: String,
+    pub admin_password: String,
+    pub adminer_url: String,
+    pub adminer_user: String,
+    pub adminer_password: String,
+    pub database_root_password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContainerRuntime {
+    pub container_id: String,
+    pub name: String,
+    pub container_image: ContainerImage,
+    pub image: String,
+    pub status: ContainerStatus,
+    pub host_ports: Vec<u32>,
+}
+
+/// Declared configuration of an instance alongside what Docker reports at runtime, with any
+/// mismatches between the two listed in `drift`.
+#[derive(Serialize, Deserialize)]
+pub struct InstanceDescription {
+    pub uuid: String,
+    pub config: InstanceData,
+    pub runtime: Vec<ContainerRuntime>,
+    pub drift: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InstanceStatus {
     Running,
     Stopped,
@@ -58,26 +405,38 @@ pub enum InstanceStatus {
 }
 
 impl InstanceStatus {
+    /// Running if every container is. Otherwise a restarting or paused container decides the
+    /// status, then partially running if some containers are, then dead or unknown containers
+    /// over the instance just being stopped. Fails if any container's status can't be read.
     pub async fn default(docker: &Docker, containers: &Vec<InstanceContainer>) -> Result<Self> {
-        let mut all_running = true;
-        let mut any_running = false;
-
-        for container in containers {
-            match InstanceContainer::get_status(docker, &container.container_id).await? {
-                ContainerStatus::Running => {
-                    any_running = true;
-                }
-                ContainerStatus::Stopped | ContainerStatus::Unknown => {
-                    all_running = false;
-                }
-                _ => {}
-            }
-        }
+        let statuses = join_all(containers.iter().map(|container| async move {
+            InstanceContainer::get_status(docker, &container.container_id)
+                .await
+                .context(format!(
+                    "Failed to get status of {} container {}",
+                    container.container_image, container.container_id
+                ))
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+        let any = |status: ContainerStatus| statuses.contains(&status);
 
-        let overall_status = if all_running {
+        let overall_status = if statuses
+            .iter()
+            .all(|status| *status == ContainerStatus::Running)
+        {
             Self::Running
-        } else if any_running {
+        } else if any(ContainerStatus::Restarting) {
+            Self::Restarting
+        } else if any(ContainerStatus::Paused) {
+            Self::Paused
+        } else if any(ContainerStatus::Running) {
             Self::PartiallyRunning
+        } else if any(ContainerStatus::Dead) {
+            Self::Dead
+        } else if any(ContainerStatus::Unknown) {
+            Self::Unknown
         } else {
             Self::Stopped
         };
@@ -87,129 +446,603 @@ impl InstanceStatus {
 }
 
 pub enum InstanceSelection {
-    All,
+    All(String),
     One(String),
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct InstanceInfo {
-    uuid: String,
-    status: String,
+    pub uuid: String,
+    pub status: String,
+}
+
+/// A host port recorded for one of an instance's services.
+#[derive(Serialize, Deserialize)]
+pub struct PortMapping {
+    pub instance_id: String,
+    pub service: String,
+    pub port: u32,
+    /// Another instance has recorded the same port.
+    pub duplicate: bool,
+    /// Something is currently bound to the port on the host.
+    pub in_use: bool,
+}
+
+/// Resource usage of an instance, summed over its running containers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceStats {
+    pub instance_id: String,
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub containers: Vec<ContainerStats>,
+}
+
+/// A mismatch between the instances recorded on disk and what Docker has, found by
+/// `Instance::reconcile`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Discrepancy {
+    pub instance_id: String,
+    pub kind: DiscrepancyKind,
+    /// Set when `reconcile` was asked to fix it and did.
+    pub fixed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DiscrepancyKind {
+    /// `instance.toml` exists but none of the instance's containers do.
+    MissingContainers,
+    /// The instance network exists but has no `instance.toml`.
+    MissingMetadata,
+}
+
+/// Emitted by the `*_all_tracked` methods each time an instance finishes processing.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkProgress {
+    pub instance_id: String,
+    pub completed: usize,
+    pub total: usize,
+    pub success: bool,
+}
+
+/// Called by bulk operations each time an instance is done.
+pub type BulkProgressCallback = Box<dyn Fn(&BulkProgress) + Send + Sync>;
+
+/// Progress reporting and cancellation for bulk operations. Once `cancel` is triggered no
+/// new instances are processed, but operations already in flight are left to finish.
+#[derive(Default)]
+pub struct BulkOptions {
+    pub progress: Option<BulkProgressCallback>,
+    pub cancel: Option<CancellationToken>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkFailure {
+    pub instance_id: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct BulkReport {
+    pub completed: Vec<InstanceInfo>,
+    pub failed: Vec<BulkFailure>,
+    pub skipped: Vec<String>,
+}
+
+impl BulkReport {
+    /// Collapses the report into the result shape of the untracked bulk operations, failing
+    /// if any instance failed.
+    pub fn into_result(self) -> Result<Vec<InstanceInfo>> {
+        match self.failed.into_iter().next() {
+            Some(failure) => Err(AnyhowError::msg(failure.error)),
+            None => Ok(self.completed),
+        }
+    }
+}
+
+/// Hooks fired by `Instance::new_with_observer` as each stage of instance creation
+/// completes, letting callers report progress without duplicating the create logic.
+pub trait CreateObserver: Sync {
+    fn network_created(&self, _network_name: &str) {}
+    fn container_created(&self, _container_image: &ContainerImage, _container_id: &str) {}
+    fn config_written(&self, _instance_id: &str) {}
+    fn instance_started(&self, _instance_id: &str) {}
+}
+
+/// Result of `Instance::adopt`: the imported instance and anything about the existing stack
+/// that didn't fit the WordPress, MySQL, nginx and Adminer shape.
+#[derive(Serialize)]
+pub struct AdoptReport {
+    pub instance: Instance,
+    pub warnings: Vec<String>,
+}
+
+/// Observer used by `Instance::new`; ignores every hook.
+pub struct NoopObserver;
+
+impl CreateObserver for NoopObserver {}
+
+/// Everything `Instance::new` will create, worked out without touching Docker. Build one with
+/// `Instance::plan` and create it with `Instance::apply`.
+#[derive(Serialize)]
+pub struct InstancePlan {
+    pub instance_id: String,
+    pub namespace: String,
+    pub instance_label: String,
+    pub instance_path: PathBuf,
+    pub network: String,
+    /// `false` when attaching to an existing external network.
+    pub create_network: bool,
+    pub external_db: Option<ExternalDatabase>,
+    pub nginx_port: u32,
+    pub adminer_port: u32,
+    /// Host port of the Mailpit web UI, `None` unless mail is enabled.
+    pub mailpit_port: Option<u32>,
+    /// Host port the database is published on, `None` unless it's exposed.
+    pub mysql_port: Option<u32>,
+    /// Host port nginx serves HTTPS on, `None` unless TLS is enabled.
+    pub tls_port: Option<u32>,
+    pub images: Vec<String>,
+    pub directories: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub containers: Vec<ContainerSpec>,
+    pub nginx_config: String,
+    #[serde(skip)]
+    user_env_vars: ContainerEnvVars,
+    #[serde(skip)]
+    env_vars: EnvVars,
+    #[serde(skip)]
+    nginx_config_path: PathBuf,
 }
 
 impl Instance {
+    /// Creates an instance's network, containers and config. The containers are left stopped
+    /// unless `start` is set.
     pub async fn new(
         docker: &Docker,
         instance_label: &str,
         user_env_vars: ContainerEnvVars,
+        start: bool,
     ) -> Result<Self> {
-        let instance_dir = config::get_instance_dir().await?;
-        let home_dir =
-            dirs::home_dir().ok_or_else(|| AnyhowError::msg("Home directory not found"))?;
-
-        let env_vars = config::initialize_env_vars(instance_label, &user_env_vars).await?;
-        config::create_network_if_not_exists(docker, crate::NETWORK_NAME, instance_label).await?;
+        Self::new_with_observer(docker, instance_label, user_env_vars, start, &NoopObserver).await
+    }
 
-        let nginx_port = utils::find_free_port()
-            .await
-            .context("Failed to find free port")?;
-        let adminer_port = utils::find_free_port()
-            .await
-            .context("Failed to find free port")?;
+    pub async fn new_with_observer(
+        docker: &Docker,
+        instance_label: &str,
+        user_env_vars: ContainerEnvVars,
+        start: bool,
+        observer: &impl CreateObserver,
+    ) -> Result<Self> {
+        let timer = OperationTimer::start(Operation::Create);
+        let plan = Self::plan(instance_label, user_env_vars).await?;
+        let instance = Self::apply(docker, plan, start, observer).await?;
+        timer.succeeded();
+        Ok(instance)
+    }
 
-        let mut labels = HashMap::new();
-        let instance_label_str = instance_label.to_string();
-        let nginx_port_str = nginx_port.to_string();
-        let adminer_port_str = adminer_port.to_string();
-        labels.insert("instance".to_string(), instance_label_str);
-        labels.insert("nginx_port".to_string(), nginx_port_str);
-        labels.insert("adminer_port".to_string(), adminer_port_str);
+    /// Resolves the ports, env vars, container specs and nginx config for a new instance. No
+    /// Docker calls are made and nothing is written to the instance directory.
+    pub async fn plan(
+        instance_label: &str,
+        user_env_vars: ContainerEnvVars,
+    ) -> Result<InstancePlan> {
+        let instance_dir = config::get_instance_dir().await?;
+        let app_config = config::load_config().await?;
+        let namespace = app_config.namespace.clone();
+        let env_vars = config::initialize_env_vars(instance_label, &user_env_vars).await?;
+        if let Some(name) = &user_env_vars.name {
+            if name.trim().is_empty() {
+                return Err(AnyhowError::msg("The instance name can't be empty"));
+            }
+            if let Some(instance_id) = config::instance_id_by_name(&namespace, name).await? {
+                return Err(AnyhowError::msg(format!(
+                    "The name {} is already used by instance {}",
+                    name, instance_id
+                )));
+            }
+        }
+        let expose_mysql_port = user_env_vars.expose_mysql_port;
+        if expose_mysql_port && user_env_vars.external_db.is_some() {
+            return Err(AnyhowError::msg(
+                "expose_mysql_port can't be used with an external database",
+            ));
+        }
+        if let Some(dump) = &user_env_vars.import_dump {
+            if user_env_vars.external_db.is_some() {
+                return Err(AnyhowError::msg(
+                    "import_dump can't be used with an external database",
+                ));
+            }
+            config::check_sql_dump(
+                dump,
+                &config::extract_value(&env_vars.wordpress, "WORDPRESS_DB_NAME"),
+            )?;
+        }
 
-        let instance_path = instance_dir.join(PathBuf::from(format!(
-            "{}-{}",
-            crate::NETWORK_NAME,
-            instance_label
-        )));
+        // All ports are picked in one go so they can't collide with each other.
+        let pinned = [user_env_vars.nginx_port, user_env_vars.adminer_port];
+        let mut free_ports = utils::find_free_ports(
+            pinned.iter().filter(|port| port.is_none()).count()
+                + usize::from(user_env_vars.enable_mail)
+                + usize::from(expose_mysql_port)
+                + usize::from(user_env_vars.enable_tls),
+            app_config.port_range,
+            &config::recorded_ports(&namespace).await?,
+        )
+        .context("Failed to find free port")?
+        .into_iter();
+        let mut port = |pinned: Option<u32>| match pinned {
+            Some(port) => utils::ensure_pinned_port_free(port),
+            None => free_ports
+                .next()
+                .ok_or_else(|| AnyhowError::msg("Failed to find free port")),
+        };
+        let nginx_port = port(user_env_vars.nginx_port)?;
+        let adminer_port = port(user_env_vars.adminer_port)?;
+        if nginx_port == adminer_port {
+            return Err(AnyhowError::msg(format!(
+                "The site and Adminer can't both use port {}",
+                nginx_port
+            )));
+        }
+        let mailpit_port = if user_env_vars.enable_mail {
+            Some(port(None)?)
+        } else {
+            None
+        };
+        let mysql_port = if expose_mysql_port {
+            Some(port(None)?)
+        } else {
+            None
+        };
+        let tls_port = if user_env_vars.enable_tls {
+            Some(port(None)?)
+        } else {
+            None
+        };
 
-        let mysql_options =
-            configure_mysql_container(instance_label, &instance_path, &labels, &env_vars).await?;
+        let mut labels = user_env_vars.labels.clone().unwrap_or_default();
+        if let Some(reserved) = utils::RESERVED_LABELS
+            .iter()
+            .find(|reserved| labels.contains_key(**reserved))
+        {
+            return Err(AnyhowError::msg(format!(
+                "The {} label is set by wpdev and can't be overridden",
+                reserved
+            )));
+        }
+        labels.insert("instance".to_string(), instance_label.to_string());
+        labels.insert("nginx_port".to_string(), nginx_port.to_string());
+        labels.insert("adminer_port".to_string(), adminer_port.to_string());
 
-        let wordpress_options =
-            configure_wordpress_container(instance_label, &instance_path, &labels, &env_vars)
-                .await?;
+        let instance_id = format!("{}-{}", namespace, instance_label);
+        let instance_path = instance_dir.join(&instance_id);
 
-        let nginx_options =
-            configure_nginx_container(&instance_path, instance_label, &labels, nginx_port).await?;
+        let nginx_template = config::read_nginx_template(&app_config).await?;
+        let nginx_config = config::render_nginx_config(
+            nginx_template.as_deref(),
+            nginx_port,
+            tls_port,
+            app_config.nginx_adminer_listen_port,
+            &format!("{}-{}", instance_label, ContainerImage::Adminer.to_string()),
+            &format!(
+                "{}-{}",
+                instance_label,
+                ContainerImage::Wordpress.to_string()
+            ),
+        );
+        let nginx_config_path = config::nginx_config_path(instance_label, &instance_path);
 
-        let adminer_options = configure_adminer_container(
+        let mut containers = Vec::new();
+        if user_env_vars.external_db.is_none() {
+            containers.push(mysql_container_spec(
+                &namespace,
+                instance_label,
+                &instance_path,
+                &labels,
+                &env_vars,
+                mysql_port,
+            ));
+        }
+        containers.push(wordpress_container_spec(
+            &namespace,
+            instance_label,
+            &instance_path,
+            &labels,
+            &env_vars,
+        ));
+        containers.push(nginx_container_spec(
+            &namespace,
+            &instance_path,
+            instance_label,
+            &labels,
+            &env_vars,
+            nginx_port,
+            tls_port,
+        ));
+        containers.push(adminer_container_spec(
+            &namespace,
             instance_label,
             &instance_path,
             &labels,
             &env_vars,
             adminer_port,
-        )
-        .await?;
+        ));
+        if user_env_vars.enable_redis {
+            containers.push(redis_container_spec(
+                &namespace,
+                instance_label,
+                &instance_path,
+                &labels,
+                &env_vars,
+            ));
+        }
+        if let Some(mailpit_port) = mailpit_port {
+            containers.push(mailpit_container_spec(
+                &namespace,
+                instance_label,
+                &instance_path,
+                &labels,
+                &env_vars,
+                mailpit_port,
+            ));
+        }
 
-        let wordpress_data =
-            config::parse_instance_data(&env_vars, &nginx_port, &adminer_port, &instance_label)
-                .await?;
+        let images = containers.iter().map(|spec| spec.image_ref()).collect();
+        let directories = containers
+            .iter()
+            .map(|spec| instance_path.join(spec.image.to_string()))
+            .collect();
+        let files = vec![
+            instance_path.join("instance.toml"),
+            nginx_config_path.clone(),
+            instance_path.join("wp-cli.local.yml"),
+            instance_path.join("wp-cli.local.php"),
+        ];
 
-        let mut instance = Instance {
-            uuid: format!("{}-{}", crate::NETWORK_NAME, instance_label.to_string()),
-            status: InstanceStatus::default(&docker, &vec![])
-                .await
-                .context("Failed to get default status for instance containers")?,
-            containers: Vec::new(),
+        Ok(InstancePlan {
+            instance_id,
+            namespace,
+            instance_label: instance_label.to_string(),
+            instance_path,
+            network: env_vars.network_name.clone(),
+            create_network: user_env_vars.external_network.is_none(),
+            external_db: user_env_vars.external_db.clone(),
             nginx_port,
             adminer_port,
-            wordpress_data: Some(wordpress_data),
-        };
+            mailpit_port,
+            mysql_port,
+            tls_port,
+            images,
+            directories,
+            files,
+            containers,
+            nginx_config,
+            user_env_vars,
+            env_vars,
+            nginx_config_path,
+        })
+    }
 
-        config::generate_wpcli_config(&instance_dir, instance_label, &home_dir).await?;
+    /// Creates the network, containers and config described by `plan`, pulling missing images
+    /// first. The containers are left stopped unless `start` is set.
+    pub async fn apply(
+        docker: &Docker,
+        plan: InstancePlan,
+        start: bool,
+        observer: &impl CreateObserver,
+    ) -> Result<Self> {
+        let InstancePlan {
+            instance_id,
+            namespace,
+            instance_label,
+            nginx_port,
+            adminer_port,
+            mailpit_port,
+            mysql_port,
+            tls_port,
+            containers: container_specs,
+            nginx_config,
+            user_env_vars,
+            env_vars,
+            nginx_config_path,
+            ..
+        } = plan;
+        let app_config = config::load_config().await?;
+        app_config
+            .validate()
+            .context("Invalid custom_root in config")?;
+        config::pull_docker_images_from_config(&app_config).await?;
+        let instance_dir = config::get_instance_dir().await?;
+        let instance_path = instance_dir.join(&instance_id);
+        let instance_path_existed = instance_path.exists();
 
-        let containers = vec![
-            (mysql_options, "mysql"),
-            (wordpress_options, "wordpress"),
-            (nginx_options, "nginx"),
-            (adminer_options, "adminer"),
-        ];
+        // Whatever gets created is removed again if a later step fails, so a failed create
+        // doesn't leave containers or a network behind that the next one collides with.
+        let mut created_network = false;
+        let mut attached_external_db = None;
+        let mut created_containers = Vec::new();
+        let created = async {
+            match &user_env_vars.external_network {
+                Some(network_name) => config::ensure_network_exists(docker, network_name).await?,
+                None => {
+                    if config::create_network_if_not_exists(docker, &namespace, &instance_label)
+                        .await?
+                    {
+                        created_network = true;
+                    }
+                }
+            }
+            observer.network_created(&env_vars.network_name);
 
-        for (container, container_type_str) in containers {
-            let container_image = match container_type_str {
-                "mysql" => ContainerImage::MySQL,
-                "wordpress" => ContainerImage::Wordpress,
-                "nginx" => ContainerImage::Nginx,
-                "adminer" => ContainerImage::Adminer,
-                _ => ContainerImage::Unknown,
-            };
+            if let Some(external_db) = &user_env_vars.external_db {
+                configure_external_database(docker, &env_vars.network_name, external_db, &env_vars)
+                    .await?;
+                attached_external_db = Some(external_db);
+            }
+            config::write_nginx_config(&nginx_config, &nginx_config_path).await?;
+            if tls_port.is_some() {
+                generate_tls_certificate(docker, &instance_label, &config::tls_dir(&instance_path))
+                    .await?;
+            }
 
-            let (container_id, container_status) = container;
+            let mut containers = Vec::new();
+            for spec in container_specs {
+                let container_image = spec.image.clone();
+                let image = spec.image_ref();
+                info!("Configuring {} container", container_image.to_string());
+                let (container_id, container_status) = InstanceContainer::new(spec).await?;
+                created_containers.push(container_id.clone());
+                observer.container_created(&container_image, &container_id);
+                containers.push(InstanceContainer {
+                    container_id,
+                    container_status,
+                    container_image,
+                    image,
+                });
+            }
 
-            let instance_container = InstanceContainer {
-                container_id: container_id.clone(),
-                container_status,
-                container_image,
+            let wordpress_data = config::parse_instance_data(
+                &env_vars,
+                &user_env_vars,
+                &config::InstanceDataOptions {
+                    namespace: &namespace,
+                    instance_label: &instance_label,
+                    nginx_port,
+                    adminer_port,
+                    mailpit_port,
+                    mysql_port,
+                    tls_port,
+                },
+            )
+            .await?;
+
+            let instance = Instance {
+                uuid: instance_id.clone(),
+                status: InstanceStatus::default(docker, &containers)
+                    .await
+                    .context("Failed to get default status for instance containers")?,
+                containers,
+                nginx_port,
+                adminer_port,
+                wordpress_data: Some(wordpress_data),
+                image_updates: None,
+                plugin_results: None,
             };
 
-            instance.containers.push(instance_container);
+            config::generate_wpcli_config(
+                &instance_dir.join(&instance.uuid),
+                &instance_label,
+                &env_vars.wordpress,
+            )
+            .await?;
+            observer.config_written(&instance.uuid);
+            Ok::<_, AnyhowError>(instance)
         }
+        .await;
+        let mut instance = match created {
+            Ok(instance) => instance,
+            Err(e) => {
+                error!(
+                    "Failed to create instance {}, rolling back: {:#}",
+                    instance_id, e
+                );
+                Self::roll_back_create(
+                    docker,
+                    &created_containers,
+                    &env_vars.network_name,
+                    created_network,
+                    attached_external_db,
+                    (!instance_path_existed).then_some(instance_path.as_path()),
+                )
+                .await;
+                return Err(e);
+            }
+        };
+        config::sync_hosts_file(config::HostsAction::Add {
+            instance_id: &instance_id,
+            instance_label: &instance_label,
+        })
+        .await;
 
-        instance.status = InstanceStatus::default(&docker, &instance.containers)
-            .await
-            .context("Failed to get default status for instance containers")?;
+        if start {
+            Self::start(docker, &instance.uuid).await?;
+            for container in instance.containers.iter_mut() {
+                container.container_status =
+                    InstanceContainer::get_status(docker, &container.container_id).await?;
+            }
+            instance.status = InstanceStatus::default(docker, &instance.containers)
+                .await
+                .context("Failed to get status for started instance containers")?;
+            observer.instance_started(&instance.uuid);
+        }
 
         Ok(instance)
     }
 
-    pub async fn list(docker: &Docker, network_name: &str) -> Result<Instance> {
-        info!("Starting to list instances for network: {}", network_name);
+    /// Removes what a failed `apply` created: the containers, the external database's
+    /// attachment to `network`, `network` itself with `remove_network` and the instance
+    /// directory. Failures are only logged, the error that caused the roll back is the one
+    /// worth reporting.
+    async fn roll_back_create(
+        docker: &Docker,
+        containers: &[String],
+        network: &str,
+        remove_network: bool,
+        external_db: Option<&ExternalDatabase>,
+        instance_path: Option<&Path>,
+    ) {
+        for container_id in containers {
+            if let Err(e) = InstanceContainer::delete(docker, container_id).await {
+                error!("Failed to remove container {}: {:#}", container_id, e);
+            }
+        }
+        if let Some(external_db) = external_db {
+            let container_name = external_db.container_name();
+            if let Err(e) = docker
+                .disconnect_network(
+                    network,
+                    DisconnectNetworkOptions {
+                        container: container_name,
+                        force: true,
+                    },
+                )
+                .await
+            {
+                error!(
+                    "Failed to detach external database {}: {}",
+                    container_name, e
+                );
+            }
+        }
+        if remove_network {
+            if let Err(e) = docker.remove_network(network).await {
+                error!("Failed to remove network {}: {}", network, e);
+            }
+        }
+        if let Some(instance_path) = instance_path.filter(|path| path.exists()) {
+            if let Err(e) =
+                utils::remove_dir_verified(instance_path, REMOVE_DIR_ATTEMPTS, REMOVE_DIR_DELAY)
+                    .await
+            {
+                error!("Failed to remove {:?}: {:#}", instance_path, e);
+            }
+        }
+    }
 
-        let instance_data = crate::config::read_instance_data_from_toml(network_name)
-            .await
-            .context(format!(
-                "Failed to read instance data from TOML file for network: {}",
-                network_name
-            ))?;
+    /// Imports containers created outside wpdev on `network_name` as an instance. Containers
+    /// are classified by image, ports are taken from their published ports and credentials
+    /// from their environment. The network and containers are left as they are, wpdev only
+    /// writes an `instance.toml` for them.
+    pub async fn adopt(
+        docker: &Docker,
+        network_name: &str,
+        instance_label: &str,
+    ) -> Result<AdoptReport> {
+        info!("Adopting containers on network: {}", network_name);
+        config::ensure_network_exists(docker, network_name).await?;
+        let app_config = config::read_or_create_config().await?;
+        let instance_id = format!("{}-{}", app_config.namespace, instance_label);
 
         let mut filters = HashMap::new();
         filters.insert("network".to_string(), vec![network_name.to_string()]);
@@ -222,31 +1055,707 @@ impl Instance {
             .await
             .context("Failed to list containers")?;
 
-        let instance_containers = containers
-            .into_iter()
-            .map(|container| {
-                let container_status =
-                    ContainerStatus::from_str(&container.state.unwrap_or_default());
-                InstanceContainer {
-                    container_id: container.id.unwrap_or_default(),
-                    container_status,
-                    container_image: ContainerImage::from_str(&container.image.unwrap_or_default()),
+        let mut warnings = Vec::new();
+        let mut services: HashMap<String, ContainerSummary> = HashMap::new();
+        for container in containers {
+            let image = container.image.clone().unwrap_or_default();
+            let name = container
+                .names
+                .as_ref()
+                .and_then(|names| names.first())
+                .map(|name| name.trim_start_matches('/').to_string())
+                .unwrap_or_default();
+            match ContainerImage::from_image_ref(&image) {
+                ContainerImage::Unknown => warnings.push(format!(
+                    "Container {} ({}) isn't a WordPress, MySQL, MariaDB, nginx, Adminer, Redis or Mailpit container and will be ignored",
+                    name, image
+                )),
+                container_image => {
+                    match services.entry(container_image.to_string()) {
+                        Entry::Occupied(entry) => warnings.push(format!(
+                            "Found more than one {} container, {} will be managed alongside the first",
+                            entry.key(),
+                            name
+                        )),
+                        Entry::Vacant(entry) => {
+                            entry.insert(container);
+                        }
+                    }
                 }
-            })
-            .collect();
+            }
+        }
 
-        let instance = Instance {
-            uuid: network_name.to_string(),
-            status: InstanceStatus::default(&docker, &instance_containers)
-                .await
-                .context("Failed to get default status for instance containers")?,
-            containers: instance_containers,
-            nginx_port: instance_data.nginx_port,
-            adminer_port: instance_data.adminer_port,
-            wordpress_data: Some(instance_data),
-        };
+        let wordpress = services
+            .get(&ContainerImage::Wordpress.to_string())
+            .ok_or_else(|| {
+                AnyhowError::msg(format!(
+                    "No WordPress container found on network {}",
+                    network_name
+                ))
+            })?;
+        if !services.values().any(|container| {
+            ContainerImage::from_image_ref(container.image.as_deref().unwrap_or("")).is_database()
+        }) {
+            warnings.push("No MySQL or MariaDB container found".to_string());
+        }
+        for service in [ContainerImage::Nginx, ContainerImage::Adminer] {
+            if !services.contains_key(&service.to_string()) {
+                warnings.push(format!("No {} container found", service.to_string()));
+            }
+        }
 
-        info!("Successfully listed instance for network: {}", network_name);
+        let published_port = |service: ContainerImage| {
+            services
+                .get(&service.to_string())
+                .and_then(|container| container.ports.as_ref())
+                .and_then(|ports| ports.iter().find_map(|port| port.public_port))
+                .map(u32::from)
+        };
+        let nginx_port = match published_port(ContainerImage::Nginx)
+            .or_else(|| published_port(ContainerImage::Wordpress))
+        {
+            Some(port) => port,
+            None => {
+                warnings.push("No published port found for the site".to_string());
+                0
+            }
+        };
+        let adminer_port = match published_port(ContainerImage::Adminer) {
+            Some(port) => port,
+            None => {
+                warnings.push("No published port found for Adminer".to_string());
+                0
+            }
+        };
+
+        let wordpress_env = docker
+            .inspect_container(wordpress.id.as_deref().unwrap_or_default(), None)
+            .await
+            .context("Failed to inspect WordPress container")?
+            .config
+            .and_then(|config| config.env)
+            .unwrap_or_default();
+        let env_value = |key: &str| {
+            wordpress_env
+                .iter()
+                .find_map(|var| var.strip_prefix(&format!("{}=", key)))
+                .map(str::to_string)
+        };
+        let db_user = env_value("WORDPRESS_DB_USER").unwrap_or_else(|| {
+            warnings.push("WORDPRESS_DB_USER isn't set on the WordPress container".to_string());
+            String::new()
+        });
+        let db_password = env_value("WORDPRESS_DB_PASSWORD").unwrap_or_else(|| {
+            warnings.push("WORDPRESS_DB_PASSWORD isn't set on the WordPress container".to_string());
+            String::new()
+        });
+
+        let instance_data = InstanceData {
+            admin_user: db_user.clone(),
+            admin_password: db_password.clone(),
+            admin_email: "admin@example.com".to_string(),
+            site_title: "My Wordpress Site".to_string(),
+            site_url: format!("{}:{}", app_config.site_url, nginx_port),
+            adminer_url: format!("{}:{}", app_config.adminer_url, adminer_port),
+            adminer_user: db_user,
+            adminer_password: db_password,
+            network_name: instance_id.clone(),
+            nginx_port,
+            adminer_port,
+            external_db_host: None,
+            debug: DebugSettings::default(),
+            log_rotation: LogRotation::default(),
+            external_network: Some(network_name.to_string()),
+            adopted: true,
+            pinned_ports: false,
+            read_only: false,
+            plugins: Vec::new(),
+            pinned: false,
+            database_engine: if services.contains_key(&ContainerImage::MariaDB.to_string()) {
+                DatabaseEngine::MariaDB
+            } else {
+                DatabaseEngine::MySQL
+            },
+            database_root_password: String::new(),
+            redis: services.contains_key(&ContainerImage::Redis.to_string()),
+            mailpit_port: None,
+            mailpit_url: None,
+            mysql_port: None,
+            tls_port: None,
+            name: None,
+            created_at: Some(Utc::now()),
+            updated_at: None,
+            install_plugins: Vec::new(),
+            wordpress_tag: None,
+            limits: BTreeMap::new(),
+        };
+        config::write_adopted_instance_data(&instance_id, &instance_data).await?;
+
+        let instance = Self::list(docker, &instance_id).await?;
+        info!("Adopted network {} as {}", network_name, instance_id);
+        Ok(AdoptReport { instance, warnings })
+    }
+
+    async fn list_labelled(
+        docker: &Docker,
+        network_prefix: &str,
+        labels: &HashMap<String, String>,
+    ) -> Result<HashMap<String, Instance>> {
+        info!(
+            "Listing instances for network prefix {} with labels {:?}",
+            network_prefix, labels
+        );
+        let containers = docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: utils::label_filters(labels),
+                ..Default::default()
+            }))
+            .await
+            .context("Failed to list containers")?;
+
+        let instance_dir = config::get_instance_dir().await?;
+        let mut instances = HashMap::new();
+        for container in containers {
+            let Some(instance_label) = container
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get("instance"))
+            else {
+                continue;
+            };
+            let instance_id = format!("{}-{}", network_prefix, instance_label);
+            // Labels don't record the namespace, so skip instances that aren't in this one.
+            if instances.contains_key(&instance_id) || !instance_dir.join(&instance_id).exists() {
+                continue;
+            }
+            match Self::list(docker, &instance_id).await {
+                Ok(instance) => {
+                    instances.insert(instance_id, instance);
+                }
+                Err(e) => info!("Failed to process instance {}: {}", instance_id, e),
+            }
+        }
+        Ok(instances)
+    }
+
+    /// Creates a new instance labelled `instance_label` as a copy of a stopped instance: the
+    /// same settings and database credentials, a copy of its WordPress files and database, and
+    /// new ports and nginx config. The copy is started and its site URL rewritten to the new
+    /// port. The source has to be stopped so its database isn't copied mid-write.
+    pub async fn duplicate(docker: &Docker, source_id: &str, instance_label: &str) -> Result<Self> {
+        info!("Cloning instance {} as {}", source_id, instance_label);
+        let (source_data, snapshot, database) = Self::snapshot(docker, source_id).await?;
+        let instance = Self::new(
+            docker,
+            instance_label,
+            snapshot.env_vars(&source_data),
+            false,
+        )
+        .await?;
+
+        let instance_id = instance.uuid.clone();
+        let instance_dir = config::get_instance_dir().await?;
+        let result = match utils::copy_dir(
+            &instance_dir.join(source_id).join("wordpress"),
+            &instance_dir.join(&instance_id).join("wordpress"),
+        )
+        .await
+        .context("Failed to copy WordPress files")
+        {
+            Ok(()) => Self::finish_copy(docker, instance, database, &source_data.site_url).await,
+            Err(e) => Err(e),
+        };
+        let instance = Self::roll_back_copy(docker, &instance_id, result).await?;
+        info!("Cloned instance {} as {}", source_id, instance.uuid);
+        Ok(instance)
+    }
+
+    /// Reads what's needed to recreate a stopped instance elsewhere: its data, the env and
+    /// image of its WordPress and database containers, and an archive of its database files.
+    async fn snapshot(
+        docker: &Docker,
+        instance_id: &str,
+    ) -> Result<(InstanceData, InstanceSnapshot, Vec<u8>)> {
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        if instance_data.adopted || instance_data.external_db_host.is_some() {
+            return Err(AnyhowError::msg(format!(
+                "Instance {} is adopted or uses an external database and can't be copied",
+                instance_id
+            )));
+        }
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        // A paused or restarting database still has its data directory open.
+        if instance
+            .containers
+            .iter()
+            .any(|container| container.container_status != ContainerStatus::Stopped)
+        {
+            return Err(AnyhowError::msg(format!(
+                "Stop instance {} first, its database can only be copied while it's stopped",
+                instance_id
+            )));
+        }
+        let container = |image: ContainerImage| {
+            instance
+                .containers
+                .iter()
+                .find(|container| container.container_image.to_string() == image.to_string())
+                .ok_or_else(|| {
+                    AnyhowError::msg(format!(
+                        "{} container not found for instance {}",
+                        image, instance_id
+                    ))
+                })
+        };
+        let wordpress = container(ContainerImage::Wordpress)?;
+        let database = container(instance_data.database_engine.image())?;
+        let container_env = |container_id: String| async move {
+            docker
+                .inspect_container(&container_id, None)
+                .await
+                .context("Failed to inspect container")
+                .map(|info| {
+                    info.config
+                        .and_then(|config| config.env)
+                        .unwrap_or_default()
+                })
+        };
+        let snapshot = InstanceSnapshot {
+            wordpress_image: wordpress.image.clone(),
+            wordpress_env: container_env(wordpress.container_id.clone()).await?,
+            database_env: container_env(database.container_id.clone()).await?,
+            compression: None,
+        };
+
+        info!("Downloading database files of {}", instance_id);
+        let mut archive = Vec::new();
+        let mut stream = docker.download_from_container(
+            &database.container_id,
+            Some(DownloadFromContainerOptions {
+                path: DATABASE_DATA_DIR,
+            }),
+        );
+        while let Some(chunk) = stream.next().await {
+            archive.extend_from_slice(&chunk.context("Failed to download database files")?);
+        }
+        Ok((instance_data, snapshot, archive))
+    }
+
+    /// Deletes `instance_id`, the instance a copy was being made into, if `result` is an error,
+    /// so a failed copy doesn't leave a half-made instance behind. Failures to delete are only
+    /// logged, the error that caused the roll back is the one worth reporting.
+    async fn roll_back_copy(
+        docker: &Docker,
+        instance_id: &str,
+        result: Result<Self>,
+    ) -> Result<Self> {
+        if result.is_err() {
+            info!("Rolling back the copy into {}", instance_id);
+            if let Err(e) = Self::delete(docker, instance_id, true).await {
+                error!("Failed to remove instance {}: {:#}", instance_id, e);
+            }
+        }
+        result
+    }
+
+    /// Loads `database` into a new, never started instance made from a snapshot, starts it
+    /// and, if WordPress is installed, rewrites `previous_site_url` to the new site URL.
+    async fn finish_copy(
+        docker: &Docker,
+        instance: Self,
+        database: Vec<u8>,
+        previous_site_url: &str,
+    ) -> Result<Self> {
+        info!("Loading database files into {}", instance.uuid);
+        let database_id = instance
+            .containers
+            .iter()
+            .find(|container| container.container_image.is_database())
+            .map(|container| container.container_id.clone())
+            .ok_or_else(|| AnyhowError::msg("Database container not found"))?;
+        let data_parent = Path::new(DATABASE_DATA_DIR)
+            .parent()
+            .map_or("/".to_string(), |parent| {
+                parent.to_string_lossy().to_string()
+            });
+        docker
+            .upload_to_container(
+                &database_id,
+                Some(UploadToContainerOptions {
+                    path: data_parent,
+                    ..Default::default()
+                }),
+                database.into(),
+            )
+            .await
+            .context("Failed to upload database files")?;
+
+        Self::start(docker, &instance.uuid).await?;
+        Self::wait_until_ready(&instance.uuid, None, COPY_READY_TIMEOUT).await?;
+        let installed = Self::run_wp_cli_output(
+            docker,
+            &instance.uuid,
+            vec!["core".to_string(), "is-installed".to_string()],
+        )
+        .await?
+        .exit_code
+            == 0;
+        if installed {
+            let site_url = instance
+                .wordpress_data
+                .as_ref()
+                .map(|data| data.site_url.clone())
+                .unwrap_or_default();
+            Self::run_wp_cli(
+                docker,
+                &instance.uuid,
+                vec![
+                    "search-replace".to_string(),
+                    previous_site_url.to_string(),
+                    site_url,
+                    "--skip-columns=guid".to_string(),
+                ],
+            )
+            .await
+            .context("Failed to update the site URL")?;
+        }
+
+        Self::list(docker, &instance.uuid).await
+    }
+
+    /// Writes a stopped instance to a tar archive at `out_path`, compressed with
+    /// `compression`: its directory (WordPress files, nginx config, `instance.toml`), the env
+    /// of its containers and its database files.
+    pub async fn backup(
+        docker: &Docker,
+        instance_id: &str,
+        out_path: &Path,
+        compression: BackupCompression,
+    ) -> Result<()> {
+        info!(
+            "Backing up instance {} to {:?} ({:?})",
+            instance_id, out_path, compression
+        );
+        let (instance_data, mut snapshot, database) = Self::snapshot(docker, instance_id).await?;
+        snapshot.compression = Some(compression);
+        let instance_path = config::get_instance_dir().await?.join(instance_id);
+        let file =
+            std::fs::File::create(out_path).context(format!("Failed to create {:?}", out_path))?;
+        let mut builder = tar::Builder::new(
+            BackupWriter::new(file, compression)
+                .context(format!("Failed to create {:?}", out_path))?,
+        );
+
+        let mut entries = fs::read_dir(&instance_path)
+            .await
+            .context(format!("Failed to read {:?}", instance_path))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = Path::new(instance_id).join(entry.file_name());
+            // The database directory only holds the server's socket, its data is archived
+            // from the container below.
+            if entry.file_name().to_string_lossy()
+                == instance_data.database_engine.image().to_string()
+            {
+                continue;
+            }
+            if entry.file_type().await?.is_dir() {
+                builder.append_dir_all(&name, entry.path())
+            } else {
+                builder.append_path_with_name(entry.path(), &name)
+            }
+            .context(format!("Failed to archive {:?}", entry.path()))?;
+        }
+        for (file_name, contents) in [
+            (BACKUP_SNAPSHOT_FILE, serde_json::to_vec_pretty(&snapshot)?),
+            (BACKUP_DATABASE_FILE, database),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(
+                    &mut header,
+                    Path::new(instance_id).join(file_name),
+                    &contents[..],
+                )
+                .context(format!("Failed to archive {}", file_name))?;
+        }
+        builder
+            .into_inner()
+            .and_then(BackupWriter::finish)
+            .context(format!("Failed to write {:?}", out_path))?;
+
+        info!("Backed up instance {} to {:?}", instance_id, out_path);
+        Ok(())
+    }
+
+    /// Recreates an instance from an archive written by `backup`, under the same ID but with
+    /// new ports, nginx config and containers, and starts it. Fails without touching anything
+    /// if the instance already exists or was backed up from another namespace.
+    pub async fn restore(docker: &Docker, archive_path: &Path) -> Result<Self> {
+        info!("Restoring instance from {:?}", archive_path);
+        // Only the top-level metadata and database files are read into memory, the WordPress
+        // files are unpacked straight from a second pass over the archive below.
+        let mut files = HashMap::new();
+        let mut archive = Self::open_backup(archive_path)?;
+        for entry in archive.entries().context("Failed to read archive")? {
+            let mut entry = entry.context("Failed to read archive entry")?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = Self::backup_entry_path(&entry)?;
+            let mut components = path.iter();
+            let (Some(instance_id), Some(file_name), None) =
+                (components.next(), components.next(), components.next())
+            else {
+                continue;
+            };
+            let file_name = file_name.to_string_lossy().to_string();
+            if !["instance.toml", BACKUP_SNAPSHOT_FILE, BACKUP_DATABASE_FILE]
+                .contains(&file_name.as_str())
+            {
+                continue;
+            }
+            let mut contents = Vec::new();
+            entry
+                .read_to_end(&mut contents)
+                .context(format!("Failed to read {:?} from archive", path))?;
+            files.insert(
+                (instance_id.to_string_lossy().to_string(), file_name),
+                contents,
+            );
+        }
+
+        let instance_id = files
+            .keys()
+            .find(|(_, file_name)| file_name == BACKUP_SNAPSHOT_FILE)
+            .map(|(instance_id, _)| instance_id.clone())
+            .ok_or_else(|| AnyhowError::msg(format!("{:?} isn't a wpdev backup", archive_path)))?;
+        let namespace = config::read_or_create_config().await?.namespace;
+        let instance_label = instance_id
+            .strip_prefix(&format!("{}-", namespace))
+            .ok_or_else(|| {
+                AnyhowError::msg(format!(
+                    "Backup of {} isn't from namespace {}, switch to its profile to restore it",
+                    instance_id, namespace
+                ))
+            })?
+            .to_string();
+        let instances_dir = config::get_instance_dir().await?;
+        if instances_dir.join(&instance_id).exists() {
+            return Err(AnyhowError::msg(format!(
+                "Instance {} already exists, delete it before restoring",
+                instance_id
+            )));
+        }
+
+        let read = |file_name: &str| {
+            files
+                .get(&(instance_id.clone(), file_name.to_string()))
+                .ok_or_else(|| AnyhowError::msg(format!("Backup is missing {}", file_name)))
+        };
+        let instance_data: InstanceData = toml::from_str(
+            std::str::from_utf8(read("instance.toml")?).context("Invalid instance.toml")?,
+        )
+        .context("Invalid instance.toml")?;
+        let snapshot: InstanceSnapshot =
+            serde_json::from_slice(read(BACKUP_SNAPSHOT_FILE)?).context("Invalid snapshot")?;
+        let database = read(BACKUP_DATABASE_FILE)?.clone();
+
+        let instance = Self::new(
+            docker,
+            &instance_label,
+            snapshot.env_vars(&instance_data),
+            false,
+        )
+        .await?;
+        let wordpress_prefix = Path::new(&instance_id).join("wordpress");
+        let unpack_wordpress = || -> Result<()> {
+            let mut archive = Self::open_backup(archive_path)?;
+            for entry in archive.entries().context("Failed to read archive")? {
+                let mut entry = entry.context("Failed to read archive entry")?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let path = Self::backup_entry_path(&entry)?;
+                if !path.starts_with(&wordpress_prefix) {
+                    continue;
+                }
+                entry
+                    .unpack_in(&instances_dir)
+                    .context(format!("Failed to unpack {:?}", path))?;
+            }
+            Ok(())
+        };
+
+        let result = match unpack_wordpress() {
+            Ok(()) => Self::finish_copy(docker, instance, database, &instance_data.site_url).await,
+            Err(e) => Err(e),
+        };
+        let instance = Self::roll_back_copy(docker, &instance_id, result).await?;
+        info!(
+            "Restored instance {} from {:?}",
+            instance.uuid, archive_path
+        );
+        Ok(instance)
+    }
+
+    /// Opens an archive written by `backup` for reading, with whichever compression it was
+    /// written with.
+    fn open_backup(archive_path: &Path) -> Result<tar::Archive<Box<dyn Read>>> {
+        let file = std::fs::File::open(archive_path)
+            .context(format!("Failed to open {:?}", archive_path))?;
+        let mut reader = BufReader::new(file);
+        let magic = reader
+            .fill_buf()
+            .context(format!("Failed to read {:?}", archive_path))?;
+        let reader: Box<dyn Read> = match BackupCompression::detect(magic) {
+            BackupCompression::None => Box::new(reader),
+            BackupCompression::Gzip => Box::new(GzDecoder::new(reader)),
+            BackupCompression::Zstd => Box::new(
+                zstd::Decoder::with_buffer(reader)
+                    .context(format!("Failed to read {:?}", archive_path))?,
+            ),
+        };
+        Ok(tar::Archive::new(reader))
+    }
+
+    /// The path of a backup entry, rejected unless it's a plain relative path (no root,
+    /// `.` or `..`) so nothing can be written outside the instance directory.
+    fn backup_entry_path<R: Read>(entry: &tar::Entry<R>) -> Result<PathBuf> {
+        let path = entry
+            .path()
+            .context("Invalid path in archive")?
+            .into_owned();
+        if path
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)))
+        {
+            return Err(AnyhowError::msg(format!(
+                "Refusing to restore {:?}, it isn't a plain relative path",
+                path
+            )));
+        }
+        Ok(path)
+    }
+
+    /// Renders a compose file that runs the instance's containers with their current env
+    /// vars, bind mounts (including the generated nginx config) and published ports, so the
+    /// stack can be brought up with `docker compose up` without wpdev.
+    pub async fn to_compose(docker: &Docker, instance_id: &str) -> Result<String> {
+        info!("Exporting instance {} as a compose file", instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        if instance.containers.is_empty() {
+            return Err(AnyhowError::msg(format!(
+                "Instance {} has no containers",
+                instance_id
+            )));
+        }
+        compose::render(docker, &instance.containers).await
+    }
+
+    pub async fn list(docker: &Docker, network_name: &str) -> Result<Instance> {
+        info!("Starting to list instances for network: {}", network_name);
+
+        let instance_data = crate::config::read_instance_data_from_toml(network_name)
+            .await
+            .context(format!(
+                "Failed to read instance data from TOML file for network: {}",
+                network_name
+            ))?;
+
+        let mut filters = HashMap::new();
+        filters.insert(
+            "network".to_string(),
+            vec![instance_data.docker_network().to_string()],
+        );
+        let containers = docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+            .context("Failed to list containers")?;
+
+        // A shared MySQL container or, on an external network, other services are attached to
+        // the network too, so only keep containers labelled with this instance. Adopted
+        // instances have no labels, so every recognised service on their network is kept.
+        let containers: Vec<_> = containers
+            .into_iter()
+            .filter(|container| {
+                if instance_data.adopted {
+                    return !matches!(
+                        ContainerImage::from_image_ref(container.image.as_deref().unwrap_or("")),
+                        ContainerImage::Unknown
+                    );
+                }
+                container
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get("instance"))
+                    .is_some_and(|label| network_name.ends_with(&format!("-{}", label)))
+            })
+            .collect();
+
+        let mut instance_data = instance_data;
+        if instance_data.nginx_port == 0 || instance_data.adminer_port == 0 {
+            let published_port = |service: ContainerImage| {
+                containers
+                    .iter()
+                    .filter(|container| {
+                        ContainerImage::from_image_ref(container.image.as_deref().unwrap_or(""))
+                            .to_string()
+                            == service.to_string()
+                    })
+                    .filter_map(|container| container.ports.as_ref())
+                    .flatten()
+                    .find_map(|port| port.public_port)
+                    .map(u32::from)
+            };
+            instance_data.fill_missing_ports(
+                published_port(ContainerImage::Nginx)
+                    .or_else(|| published_port(ContainerImage::Wordpress)),
+                published_port(ContainerImage::Adminer),
+            );
+        }
+
+        let instance_containers = containers
+            .into_iter()
+            .map(|container| {
+                let container_status =
+                    ContainerStatus::from_str(&container.state.unwrap_or_default());
+                let image = container.image.unwrap_or_default();
+                InstanceContainer {
+                    container_id: container.id.unwrap_or_default(),
+                    container_status,
+                    container_image: ContainerImage::from_image_ref(&image),
+                    image,
+                }
+            })
+            .collect();
+
+        let instance = Instance {
+            uuid: network_name.to_string(),
+            status: InstanceStatus::default(&docker, &instance_containers)
+                .await
+                .context("Failed to get default status for instance containers")?,
+            containers: instance_containers,
+            nginx_port: instance_data.nginx_port,
+            adminer_port: instance_data.adminer_port,
+            wordpress_data: Some(instance_data),
+            image_updates: None,
+            plugin_results: None,
+        };
+
+        info!("Successfully listed instance for network: {}", network_name);
         Ok(instance)
     }
 
@@ -254,6 +1763,20 @@ impl Instance {
         docker: &Docker,
         network_prefix: &str,
     ) -> Result<HashMap<String, Instance>> {
+        Self::list_all_with_labels(docker, network_prefix, &HashMap::new()).await
+    }
+
+    /// Like `list_all`, but only returns instances whose containers carry every label in
+    /// `labels`. The containers are selected with a Docker label filter rather than by
+    /// listing every instance.
+    pub async fn list_all_with_labels(
+        docker: &Docker,
+        network_prefix: &str,
+        labels: &HashMap<String, String>,
+    ) -> Result<HashMap<String, Instance>> {
+        if !labels.is_empty() {
+            return Self::list_labelled(docker, network_prefix, labels).await;
+        }
         info!(
             "Starting to list all instances for network prefix: {}",
             network_prefix
@@ -264,21 +1787,27 @@ impl Instance {
             .await
             .context("Failed to list networks")?;
 
-        let mut instances = HashMap::new();
-        for network in networks.into_iter().filter(|n| {
-            n.name
-                .as_ref()
-                .map_or(false, |name| name.starts_with(network_prefix))
-        }) {
-            let full_network_name = network.name.unwrap_or_default();
+        let mut instance_ids: Vec<String> = networks
+            .into_iter()
+            .filter(|network| config::network_in_namespace(network, network_prefix))
+            .filter_map(|network| network.name)
+            .collect();
+        instance_ids.extend(config::external_network_instances(network_prefix).await?);
 
-            match Self::list(docker, &full_network_name).await {
+        let results = join_all(instance_ids.into_iter().map(|instance_id| async move {
+            let result = Self::list(docker, &instance_id).await;
+            (instance_id, result)
+        }))
+        .await;
+        let mut instances = HashMap::new();
+        for (instance_id, result) in results {
+            match result {
                 Ok(instance) => {
-                    instances.insert(full_network_name.clone(), instance);
-                    info!("Successfully processed network: {}", full_network_name);
+                    info!("Successfully processed instance: {}", instance_id);
+                    instances.insert(instance_id, instance);
                 }
                 Err(e) => {
-                    info!("Failed to process network: {}", full_network_name);
+                    info!("Failed to process instance: {}", instance_id);
                     info!("Error: {}", e);
                 }
             }
@@ -291,27 +1820,185 @@ impl Instance {
         Ok(instances)
     }
 
+    /// Compares the instances recorded on disk with the networks and containers Docker has.
+    /// With `fix`, instances whose containers are all gone have their `instance.toml` moved
+    /// aside (see `config::mark_instance_stale`). Networks without metadata are only reported,
+    /// and no instance data or Docker resources are ever removed.
+    pub async fn reconcile(
+        docker: &Docker,
+        network_prefix: &str,
+        fix: bool,
+    ) -> Result<Vec<Discrepancy>> {
+        info!(
+            "Reconciling instances for network prefix: {}",
+            network_prefix
+        );
+        let mut discrepancies = Vec::new();
+
+        let recorded = config::recorded_instances(network_prefix).await?;
+        for instance_id in &recorded {
+            let instance = Self::list(docker, instance_id)
+                .await
+                .context(format!("Failed to list instance {}", instance_id))?;
+            if !instance.containers.is_empty() {
+                continue;
+            }
+            let fixed = if fix {
+                config::mark_instance_stale(instance_id).await?;
+                true
+            } else {
+                false
+            };
+            discrepancies.push(Discrepancy {
+                instance_id: instance_id.clone(),
+                kind: DiscrepancyKind::MissingContainers,
+                fixed,
+            });
+        }
+
+        let networks = docker
+            .list_networks::<String>(None)
+            .await
+            .context("Failed to list networks")?;
+        for network_name in networks
+            .into_iter()
+            .filter(|network| config::network_in_namespace(network, network_prefix))
+            .filter_map(|network| network.name)
+        {
+            if !recorded.contains(&network_name) {
+                discrepancies.push(Discrepancy {
+                    instance_id: network_name,
+                    kind: DiscrepancyKind::MissingMetadata,
+                    fixed: false,
+                });
+            }
+        }
+
+        for discrepancy in &discrepancies {
+            warn!(
+                "Instance {}: {:?} (fixed: {})",
+                discrepancy.instance_id, discrepancy.kind, discrepancy.fixed
+            );
+        }
+        Ok(discrepancies)
+    }
+
+    /// Fails if a pinned port of a container that isn't running has been taken by something
+    /// else, since Docker would otherwise fail to start it.
+    fn ensure_pinned_ports_free(instance: &Instance) -> Result<()> {
+        if !instance
+            .wordpress_data
+            .as_ref()
+            .is_some_and(|data| data.pinned_ports)
+        {
+            return Ok(());
+        }
+        for (image, port) in [
+            (ContainerImage::Nginx, instance.nginx_port),
+            (ContainerImage::Adminer, instance.adminer_port),
+        ] {
+            let stopped = instance.containers.iter().any(|container| {
+                container.container_image.to_string() == image.to_string()
+                    && !matches!(container.container_status, ContainerStatus::Running)
+            });
+            if stopped {
+                utils::ensure_pinned_port_free(port).context(format!(
+                    "Can't start {} for instance {}",
+                    image, instance.uuid
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn start(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
         info!("Starting to start instance: {}", instance_id);
+        let timer = OperationTimer::start(Operation::Start);
         let mut instance = Self::list(docker, &instance_id)
             .await
             .context("Failed to list instance")?;
-        let start_container_futures = instance.containers.iter().map(|container| async move {
-            InstanceContainer::start(docker, &container.container_id)
-                .await
-                .with_context(|| format!("Failed to start container {}", &container.container_id))
-        });
-        let _ = join_all(start_container_futures).await;
+        Self::ensure_pinned_ports_free(&instance)?;
+        let limits = instance
+            .wordpress_data
+            .as_ref()
+            .map(|instance_data| instance_data.limits.clone())
+            .unwrap_or_default();
+        // The database is started first and waited for, otherwise WordPress boots into
+        // connection errors while it initialises.
+        let (databases, others): (Vec<_>, Vec<_>) = instance
+            .containers
+            .iter()
+            .partition(|container| container.container_image.is_database());
+        let databases_started = join_all(
+            databases
+                .iter()
+                .map(|container| Self::start_container(docker, container, &limits)),
+        )
+        .await
+        .into_iter()
+        .all(|result| result.is_ok());
+        if !databases.is_empty() && databases_started && instance.wordpress_data.is_some() {
+            let timeout =
+                Duration::from_secs(config::read_or_create_config().await?.startup_timeout_secs);
+            Self::wait_for_database(docker, instance_id, timeout).await?;
+        }
+        let _ = join_all(
+            others
+                .iter()
+                .map(|container| Self::start_container(docker, container, &limits)),
+        )
+        .await;
         instance.status = InstanceStatus::default(docker, &instance.containers)
             .await
             .context("Failed to get default status for instance containers")?;
+        Self::record_update(instance_id).await;
+        timer.succeeded();
         Ok(InstanceInfo {
             uuid: instance.uuid.clone(),
             status: format!("{:?}", instance.status),
         })
     }
 
+    /// Sets `updated_at` of the instance after its containers changed state. Failing to is only
+    /// logged, the containers themselves did change.
+    async fn record_update(instance_id: &str) {
+        let recorded = async {
+            let mut instance_data = config::read_instance_data_from_toml(instance_id).await?;
+            instance_data.updated_at = Some(Utc::now());
+            config::write_instance_data_to_toml(instance_id, &instance_data).await
+        }
+        .await;
+        if let Err(e) = recorded {
+            error!("Failed to record update time of {}: {:#}", instance_id, e);
+        }
+    }
+
+    /// Starts one of the instance's containers with its recorded `limits` applied, so they win
+    /// over any changed with `docker update` since.
+    async fn start_container(
+        docker: &Docker,
+        container: &InstanceContainer,
+        limits: &BTreeMap<String, ResourceLimits>,
+    ) -> Result<InstanceContainer> {
+        if let Some(limits) = limits.get(&container.container_image.to_string()) {
+            InstanceContainer::update_limits(docker, &container.container_id, limits).await?;
+        }
+        InstanceContainer::start(docker, &container.container_id)
+            .await
+            .with_context(|| format!("Failed to start container {}", &container.container_id))
+    }
+
     pub async fn start_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+        Self::start_all_tracked(docker, network_prefix, &BulkOptions::default())
+            .await?
+            .into_result()
+    }
+
+    pub async fn start_all_tracked(
+        docker: &Docker,
+        network_prefix: &str,
+        options: &BulkOptions,
+    ) -> Result<BulkReport> {
         info!(
             "Starting to start all instances for network prefix: {}",
             network_prefix
@@ -320,19 +2007,21 @@ impl Instance {
             .await
             .context("Failed to list instances")?;
 
-        let start_instance_futures = instances.values().map(|instance| async move {
-            Self::start(docker, &instance.uuid)
-                .await
-                .with_context(|| format!("Failed to start instance {}", &instance.uuid))
-        });
-
-        let results: Result<Vec<_>> = join_all(start_instance_futures).await.into_iter().collect();
-
-        results
+        Ok(run_bulk(
+            instances.into_keys().collect(),
+            options,
+            |instance_id| async move {
+                Self::start(docker, &instance_id)
+                    .await
+                    .with_context(|| format!("Failed to start instance {}", &instance_id))
+            },
+        )
+        .await)
     }
 
     pub async fn stop(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
         info!("Starting to stop instance: {}", instance_id);
+        let timer = OperationTimer::start(Operation::Stop);
         let mut instance = Self::list(docker, &instance_id)
             .await
             .context("Failed to list instance")?;
@@ -345,6 +2034,8 @@ impl Instance {
         instance.status = InstanceStatus::default(docker, &instance.containers)
             .await
             .context("Failed to get default status for instance containers")?;
+        Self::record_update(instance_id).await;
+        timer.succeeded();
         Ok(InstanceInfo {
             uuid: instance.uuid.clone(),
             status: format!("{:?}", instance.status),
@@ -352,6 +2043,16 @@ impl Instance {
     }
 
     pub async fn stop_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+        Self::stop_all_tracked(docker, network_prefix, &BulkOptions::default())
+            .await?
+            .into_result()
+    }
+
+    pub async fn stop_all_tracked(
+        docker: &Docker,
+        network_prefix: &str,
+        options: &BulkOptions,
+    ) -> Result<BulkReport> {
         info!(
             "Starting to stop all instances for network prefix: {}",
             network_prefix
@@ -360,126 +2061,1433 @@ impl Instance {
             .await
             .context("Failed to list instances")?;
 
-        let stop_instance_futures = instances.values().map(|instance| async move {
-            Self::stop(docker, &instance.uuid)
+        Ok(run_bulk(
+            instances.into_keys().collect(),
+            options,
+            |instance_id| async move {
+                Self::stop(docker, &instance_id)
+                    .await
+                    .with_context(|| format!("Failed to stop instance {}", &instance_id))
+            },
+        )
+        .await)
+    }
+
+    pub async fn restart(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
+        info!("Starting to restart instance: {}", instance_id);
+        let timer = OperationTimer::start(Operation::Restart);
+        let mut instance = Self::list(docker, &instance_id)
+            .await
+            .context("Failed to list instance")?;
+        Self::ensure_pinned_ports_free(&instance)?;
+        let restart_container_futures = instance.containers.iter().map(|container| async move {
+            InstanceContainer::restart(docker, &container.container_id)
+                .await
+                .with_context(|| format!("Failed to restart container {}", &container.container_id))
+        });
+        let _ = join_all(restart_container_futures).await;
+        instance.status = InstanceStatus::default(docker, &instance.containers)
+            .await
+            .context("Failed to get default status for instance containers")?;
+        Self::record_update(instance_id).await;
+        timer.succeeded();
+        Ok(InstanceInfo {
+            uuid: instance.uuid.clone(),
+            status: format!("{:?}", instance.status),
+        })
+    }
+
+    /// Freezes the processes in an instance's running containers. Memory stays allocated but
+    /// the containers stop using CPU until they're unpaused.
+    pub async fn pause(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
+        info!("Starting to pause instance: {}", instance_id);
+        let mut instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let pause_container_futures = instance.containers.iter().map(|container| async move {
+            InstanceContainer::pause(docker, &container.container_id)
+                .await
+                .with_context(|| format!("Failed to pause container {}", &container.container_id))
+        });
+        let _ = join_all(pause_container_futures).await;
+        instance.status = InstanceStatus::default(docker, &instance.containers)
+            .await
+            .context("Failed to get default status for instance containers")?;
+        Ok(InstanceInfo {
+            uuid: instance.uuid.clone(),
+            status: format!("{:?}", instance.status),
+        })
+    }
+
+    pub async fn unpause(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
+        info!("Starting to unpause instance: {}", instance_id);
+        let mut instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let unpause_container_futures = instance.containers.iter().map(|container| async move {
+            InstanceContainer::unpause(docker, &container.container_id)
+                .await
+                .with_context(|| format!("Failed to unpause container {}", &container.container_id))
+        });
+        let _ = join_all(unpause_container_futures).await;
+        instance.status = InstanceStatus::default(docker, &instance.containers)
+            .await
+            .context("Failed to get default status for instance containers")?;
+        Ok(InstanceInfo {
+            uuid: instance.uuid.clone(),
+            status: format!("{:?}", instance.status),
+        })
+    }
+
+    pub async fn restart_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+        Self::restart_all_tracked(docker, network_prefix, &BulkOptions::default())
+            .await?
+            .into_result()
+    }
+
+    pub async fn restart_all_tracked(
+        docker: &Docker,
+        network_prefix: &str,
+        options: &BulkOptions,
+    ) -> Result<BulkReport> {
+        info!(
+            "Starting to restart all instances for network prefix: {}",
+            network_prefix
+        );
+        let instances = Self::list_all(docker, network_prefix)
+            .await
+            .context("Failed to list instances")?;
+
+        Ok(run_bulk(
+            instances.into_keys().collect(),
+            options,
+            |instance_id| async move {
+                Self::restart(docker, &instance_id)
+                    .await
+                    .with_context(|| format!("Failed to restart instance {}", &instance_id))
+            },
+        )
+        .await)
+    }
+
+    /// Removes an instance's containers. With `purge` its network and directory are removed
+    /// too, otherwise they're left for the caller to clean up.
+    pub async fn delete(docker: &Docker, instance_id: &str, purge: bool) -> Result<InstanceInfo> {
+        info!("Starting to delete instance: {}", instance_id);
+        let timer = OperationTimer::start(Operation::Delete);
+        let instance = Self::list(docker, &instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let delete_container_futures = instance.containers.iter().map(|container| async move {
+            InstanceContainer::delete(docker, &container.container_id)
+                .await
+                .with_context(|| format!("Failed to delete container {}", &container.container_id))
+        });
+        let _ = join_all(delete_container_futures).await;
+        if let Some((external_db_host, network_name)) =
+            instance.wordpress_data.as_ref().and_then(|data| {
+                data.external_db_host
+                    .as_ref()
+                    .map(|host| (host, data.docker_network()))
+            })
+        {
+            let container_name = external_db_host
+                .split(':')
+                .next()
+                .unwrap_or(external_db_host);
+            info!("Detaching external database: {}", container_name);
+            if let Err(e) = docker
+                .disconnect_network(
+                    network_name,
+                    DisconnectNetworkOptions {
+                        container: container_name,
+                        force: true,
+                    },
+                )
+                .await
+            {
+                error!(
+                    "Failed to detach external database {}: {}",
+                    container_name, e
+                );
+            }
+        }
+        config::sync_hosts_file(config::HostsAction::Remove { instance_id }).await;
+        if purge {
+            purge_instances(InstanceSelection::One(instance_id.to_string())).await?;
+        }
+        timer.succeeded();
+        Ok(InstanceInfo {
+            uuid: instance.uuid.clone(),
+            status: format!("{:?}", InstanceStatus::Deleted),
+        })
+    }
+
+    pub async fn delete_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+        Self::delete_all_tracked(docker, network_prefix, &BulkOptions::default())
+            .await?
+            .into_result()
+    }
+
+    pub async fn delete_all_tracked(
+        docker: &Docker,
+        network_prefix: &str,
+        options: &BulkOptions,
+    ) -> Result<BulkReport> {
+        info!(
+            "Starting to delete all instances for network prefix: {}",
+            network_prefix
+        );
+        let instances = Self::list_all(docker, network_prefix)
+            .await
+            .context("Failed to list instances")?;
+
+        let report = run_bulk(
+            instances.into_keys().collect(),
+            options,
+            // Networks and directories are purged below, once the whole batch is done.
+            |instance_id| async move {
+                Self::delete(docker, &instance_id, false)
+                    .await
+                    .with_context(|| format!("Failed to delete instance {}", &instance_id))
+            },
+        )
+        .await;
+
+        // Only sweep the whole prefix when every instance was deleted, otherwise the networks
+        // and directories of instances that failed or were skipped, and so still have
+        // containers, would be removed with it.
+        if report.failed.is_empty() && report.skipped.is_empty() {
+            purge_instances(InstanceSelection::All(network_prefix.to_string())).await?;
+        } else {
+            for instance in &report.completed {
+                purge_instances(InstanceSelection::One(instance.uuid.clone())).await?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub async fn port_map(docker: &Docker, network_prefix: &str) -> Result<Vec<PortMapping>> {
+        info!(
+            "Starting to map ports for network prefix: {}",
+            network_prefix
+        );
+        let instances = Self::list_all(docker, network_prefix)
+            .await
+            .context("Failed to list instances")?;
+
+        let mut ports: Vec<PortMapping> = instances
+            .into_iter()
+            .flat_map(|(instance_id, instance)| {
+                let data = instance.wordpress_data.as_ref();
+                let mailpit_port = data.and_then(|data| data.mailpit_port);
+                let mysql_port = data.and_then(|data| data.mysql_port);
+                let tls_port = data.and_then(|data| data.tls_port);
+                [
+                    ("nginx", instance.nginx_port),
+                    ("adminer", instance.adminer_port),
+                ]
+                .into_iter()
+                .chain(mailpit_port.map(|port| ("mailpit", port)))
+                .chain(mysql_port.map(|port| ("mysql", port)))
+                .chain(tls_port.map(|port| ("https", port)))
+                .map(move |(service, port)| PortMapping {
+                    instance_id: instance_id.clone(),
+                    service: service.to_string(),
+                    port,
+                    duplicate: false,
+                    in_use: utils::port_in_use(port),
+                })
+            })
+            .collect();
+
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for mapping in &ports {
+            *counts.entry(mapping.port).or_default() += 1;
+        }
+        for mapping in &mut ports {
+            mapping.duplicate = counts[&mapping.port] > 1;
+        }
+        ports.sort_by_key(|mapping| mapping.port);
+
+        Ok(ports)
+    }
+
+    pub async fn inspect(docker: &Docker, instance_id: &str) -> Result<Instance> {
+        info!("Starting to inspect instance: {}", instance_id);
+        let instance_name = format!("{}", instance_id);
+        let instance = Self::list(docker, &instance_name)
+            .await
+            .context("Failed to list instance")?;
+        Ok(instance)
+    }
+
+    /// Fills in `image_updates` by checking each of the instance's images against the
+    /// registry. This makes a network request per image, so it's opt-in.
+    pub async fn check_image_updates(&mut self) -> Result<()> {
+        info!("Checking image updates for instance: {}", self.uuid);
+        let images: Vec<String> = self
+            .containers
+            .iter()
+            .filter(|container| !matches!(container.container_image, ContainerImage::Unknown))
+            .map(|container| {
+                if container.image.is_empty() {
+                    container.container_image.image_ref()
+                } else {
+                    container.image.clone()
+                }
+            })
+            .collect();
+        let mut updates = Vec::new();
+        for image in images {
+            let update_available = config::image_update_available(&image).await?;
+            updates.push(ImageUpdate {
+                image,
+                update_available,
+            });
+        }
+        self.image_updates = Some(updates);
+        Ok(())
+    }
+
+    pub async fn inspect_all(docker: &Docker, network_prefix: &str) -> Result<Vec<Instance>> {
+        info!(
+            "Starting to inspect all instances for network prefix: {}",
+            network_prefix
+        );
+        let instances = Self::list_all(docker, network_prefix)
+            .await
+            .context("Failed to list instances")?;
+        let mut instances: Vec<Instance> = instances.into_values().collect();
+        // Stable, so instances keep their relative order within each group.
+        instances.sort_by_key(|instance| !instance.is_pinned());
+        Ok(instances)
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.wordpress_data.as_ref().is_some_and(|data| data.pinned)
+    }
+
+    /// Resolves what a user passed to refer to an instance of `namespace`: an instance id is
+    /// returned as is, otherwise it's looked up as an instance's `name`. Anything else is
+    /// returned unchanged, so the operation fails with its usual not found error.
+    pub async fn resolve_id(namespace: &str, id_or_name: &str) -> Result<String> {
+        let instance_dir = config::get_instance_dir().await?;
+        if instance_dir.join(id_or_name).join("instance.toml").exists() {
+            return Ok(id_or_name.to_string());
+        }
+        Ok(config::instance_id_by_name(namespace, id_or_name)
+            .await?
+            .unwrap_or_else(|| id_or_name.to_string()))
+    }
+
+    /// Pins or unpins an instance, recording it in `instance.toml`.
+    pub async fn set_pinned(instance_id: &str, pinned: bool) -> Result<()> {
+        info!("Setting pinned to {} for instance: {}", pinned, instance_id);
+        let mut instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        instance_data.pinned = pinned;
+        config::write_instance_data_to_toml(instance_id, &instance_data).await
+    }
+
+    /// Inspects the instances of every namespace, grouped by namespace. Networks are matched by
+    /// their namespace label, or for instances created before networks were labelled, by
+    /// having an instance directory.
+    pub async fn inspect_all_namespaces(
+        docker: &Docker,
+    ) -> Result<BTreeMap<String, Vec<Instance>>> {
+        info!("Starting to inspect instances across all namespaces");
+        let instance_dir = config::get_instance_dir().await?;
+        let networks = docker
+            .list_networks::<String>(None)
+            .await
+            .context("Failed to list networks")?;
+
+        let mut namespaces: BTreeMap<String, Vec<Instance>> = BTreeMap::new();
+        for network in networks {
+            let network_name = network.name.unwrap_or_default();
+            let namespace = match network
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(config::NAMESPACE_LABEL))
+            {
+                Some(namespace) => namespace.clone(),
+                None if instance_dir
+                    .join(&network_name)
+                    .join("instance.toml")
+                    .exists() =>
+                {
+                    // Instance labels are UUIDs, so the namespace is everything before them.
+                    match network_name.len().checked_sub(config::UUID_LEN + 1) {
+                        Some(end) => network_name[..end].to_string(),
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+
+            match Self::list(docker, &network_name).await {
+                Ok(instance) => namespaces.entry(namespace).or_default().push(instance),
+                Err(e) => info!("Failed to process network {}: {}", network_name, e),
+            }
+        }
+
+        Ok(namespaces)
+    }
+
+    pub async fn describe(docker: &Docker, instance_id: &str) -> Result<InstanceDescription> {
+        info!("Starting to describe instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let instance_data = instance
+            .wordpress_data
+            .ok_or_else(|| AnyhowError::msg("Instance data not found"))?;
+
+        let mut runtime = Vec::new();
+        let mut drift = Vec::new();
+        for container in &instance.containers {
+            let container_info = docker
+                .inspect_container(&container.container_id, None)
                 .await
-                .with_context(|| format!("Failed to stop instance {}", &instance.uuid))
+                .context(format!(
+                    "Failed to inspect container {}",
+                    container.container_id
+                ))?;
+            let container_config = container_info.config.unwrap_or_default();
+            let labels = container_config.labels.unwrap_or_default();
+            let container_image = ContainerImage::from_str(
+                labels.get("image").map(String::as_str).unwrap_or_default(),
+            );
+            let image = container_config.image.unwrap_or_default();
+            let mut host_ports: Vec<u32> = container_info
+                .host_config
+                .and_then(|host_config| host_config.port_bindings)
+                .unwrap_or_default()
+                .into_values()
+                .flatten()
+                .flatten()
+                .filter_map(|binding| binding.host_port.and_then(|port| port.parse().ok()))
+                .collect();
+            host_ports.sort();
+
+            let expected_image = container_image.to_string();
+            let recorded_image = match container_image {
+                ContainerImage::Wordpress => instance_data
+                    .wordpress_tag
+                    .as_ref()
+                    .map(|tag| format!("{}:{}", container_image.repository(), tag))
+                    .unwrap_or_else(|| container_image.image_ref()),
+                _ => container_image.image_ref(),
+            };
+            // Adopted containers run whatever images they were created with.
+            if !instance_data.adopted && image != recorded_image {
+                drift.push(format!(
+                    "{} container is running image {} but {} was recorded",
+                    expected_image, image, recorded_image
+                ));
+            }
+
+            let expected_ports = match container_image {
+                ContainerImage::Nginx => {
+                    let mut ports: Vec<u32> = std::iter::once(instance_data.nginx_port)
+                        .chain(instance_data.tls_port)
+                        .collect();
+                    ports.sort();
+                    ports
+                }
+                ContainerImage::Adminer => vec![instance_data.adminer_port],
+                ContainerImage::Mailpit => instance_data.mailpit_port.into_iter().collect(),
+                ContainerImage::MySQL | ContainerImage::MariaDB => {
+                    instance_data.mysql_port.into_iter().collect()
+                }
+                _ => vec![],
+            };
+            if host_ports != expected_ports {
+                drift.push(format!(
+                    "{} container publishes ports {:?} but {:?} was recorded",
+                    expected_image, host_ports, expected_ports
+                ));
+            }
+
+            for (label, recorded) in [
+                ("nginx_port", instance_data.nginx_port),
+                ("adminer_port", instance_data.adminer_port),
+            ] {
+                if let Some(value) = labels.get(label) {
+                    if *value != recorded.to_string() {
+                        drift.push(format!(
+                            "{} container is labelled with {}={} but {} was recorded",
+                            expected_image, label, value, recorded
+                        ));
+                    }
+                }
+            }
+
+            runtime.push(ContainerRuntime {
+                container_id: container.container_id.clone(),
+                name: container_info
+                    .name
+                    .unwrap_or_default()
+                    .trim_start_matches('/')
+                    .to_string(),
+                container_image,
+                image,
+                status: container.container_status.clone(),
+                host_ports,
+            });
+        }
+
+        let database = instance_data.database_engine.image();
+        let mut expected_images = vec![
+            database,
+            ContainerImage::Wordpress,
+            ContainerImage::Nginx,
+            ContainerImage::Adminer,
+        ];
+        if instance_data.redis {
+            expected_images.push(ContainerImage::Redis);
+        }
+        if instance_data.mailpit_port.is_some() {
+            expected_images.push(ContainerImage::Mailpit);
+        }
+        for expected in expected_images {
+            if !runtime
+                .iter()
+                .any(|container| container.container_image.to_string() == expected.to_string())
+            {
+                drift.push(format!("{} container is missing", expected.to_string()));
+            }
+        }
+
+        Ok(InstanceDescription {
+            uuid: instance.uuid,
+            config: instance_data,
+            runtime,
+            drift,
+        })
+    }
+
+    /// Runs a WP-CLI command against an instance using a one-off `wordpress:cli` container
+    /// that shares the WordPress volume, environment and network.
+    pub async fn run_wp_cli(
+        docker: &Docker,
+        instance_id: &str,
+        args: Vec<String>,
+    ) -> Result<String> {
+        Self::run_wp_cli_output(docker, instance_id, args)
+            .await?
+            .into_result()
+            .context("Failed to run wp-cli command")
+    }
+
+    /// Runs a WP-CLI command like `run_wp_cli`, returning its stdout, stderr and exit code
+    /// untouched so they can be passed straight through.
+    pub async fn run_wp_cli_output(
+        docker: &Docker,
+        instance_id: &str,
+        args: Vec<String>,
+    ) -> Result<CommandOutput> {
+        info!("Running wp-cli {:?} for instance: {}", args, instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let wordpress_container = instance
+            .containers
+            .iter()
+            .find(|container| matches!(container.container_image, ContainerImage::Wordpress))
+            .ok_or_else(|| AnyhowError::msg("WordPress container not found"))?;
+        let container_info = docker
+            .inspect_container(&wordpress_container.container_id, None)
+            .await
+            .context("Failed to inspect WordPress container")?;
+        let container_config = container_info.config.unwrap_or_default();
+        let binds = container_info
+            .host_config
+            .and_then(|host_config| host_config.binds)
+            .unwrap_or_default();
+
+        // The WordPress image doesn't ship WP-CLI, so it runs in a one-off `wordpress:cli`
+        // container sharing the WordPress container's env, volumes and network.
+        ensure_wp_cli_image(docker).await?;
+        let mut cmd = vec!["wp".to_string()];
+        cmd.extend(args);
+        InstanceContainer::run_once_output(
+            docker,
+            crate::WORDPRESS_CLI_IMAGE,
+            cmd,
+            container_config.env.unwrap_or_default(),
+            binds,
+            instance
+                .wordpress_data
+                .as_ref()
+                .map_or(&instance.uuid, |data| data.docker_network()),
+            container_config.user,
+        )
+        .await
+        .context("Failed to run wp-cli command")
+    }
+
+    /// Rewrites the instance's `wp-cli.local.yml` and `wp-cli.local.php` from the current
+    /// template and the database settings of its WordPress container. Safe to run repeatedly.
+    pub async fn regenerate_wpcli_config(
+        docker: &Docker,
+        instance_id: &str,
+    ) -> Result<InstanceInfo> {
+        info!("Regenerating wp-cli config for instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let wordpress_container = instance
+            .containers
+            .iter()
+            .find(|container| matches!(container.container_image, ContainerImage::Wordpress))
+            .ok_or_else(|| AnyhowError::msg("WordPress container not found"))?;
+        let container_config = docker
+            .inspect_container(&wordpress_container.container_id, None)
+            .await
+            .context("Failed to inspect WordPress container")?
+            .config
+            .unwrap_or_default();
+        // Adopted containers have no `instance` label, their DB host always comes from the env.
+        let instance_label = container_config
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get("instance"))
+            .map_or(instance_id, String::as_str);
+
+        let instance_dir = config::get_instance_dir().await?.join(instance_id);
+        config::generate_wpcli_config(
+            &instance_dir,
+            instance_label,
+            &container_config.env.unwrap_or_default(),
+        )
+        .await?;
+        Ok(InstanceInfo {
+            uuid: instance.uuid.clone(),
+            status: format!("{:?}", instance.status),
+        })
+    }
+
+    pub async fn regenerate_all_wpcli_configs(
+        docker: &Docker,
+        network_prefix: &str,
+    ) -> Result<Vec<InstanceInfo>> {
+        let instances = Self::list_all(docker, network_prefix)
+            .await
+            .context("Failed to list instances")?;
+        run_bulk(
+            instances.into_keys().collect(),
+            &BulkOptions::default(),
+            |instance_id| async move {
+                Self::regenerate_wpcli_config(docker, &instance_id)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to regenerate wp-cli config for {}", &instance_id)
+                    })
+            },
+        )
+        .await
+        .into_result()
+    }
+
+    /// Sets the permalink structure of an installed instance and flushes its rewrite rules.
+    pub async fn set_permalinks(
+        docker: &Docker,
+        instance_id: &str,
+        structure: &str,
+    ) -> Result<String> {
+        info!(
+            "Setting permalink structure {} for instance: {}",
+            structure, instance_id
+        );
+        Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "rewrite".to_string(),
+                "structure".to_string(),
+                structure.to_string(),
+                "--hard".to_string(),
+            ],
+        )
+        .await
+    }
+
+    /// Polls the instance's nginx port until `path` (defaulting to `readiness_path` from the
+    /// config) answers with one of the configured `readiness_status_codes`.
+    pub async fn wait_until_ready(
+        instance_id: &str,
+        path: Option<&str>,
+        timeout: Duration,
+    ) -> Result<()> {
+        info!("Waiting for instance to be ready: {}", instance_id);
+        let config = config::read_or_create_config().await?;
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        let path = path.unwrap_or(&config.readiness_path);
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let last_response = match utils::http_status(instance_data.nginx_port, path).await {
+                Ok(status) if config.readiness_status_codes.contains(&status) => {
+                    info!(
+                        "Instance {} is ready, {} returned {}",
+                        instance_id, path, status
+                    );
+                    return Ok(());
+                }
+                Ok(status) => format!("status {}", status),
+                Err(e) => e.to_string(),
+            };
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AnyhowError::msg(format!(
+                    "Instance {} not ready after {:?}, last response from {}: {}",
+                    instance_id, timeout, path, last_response
+                )));
+            }
+            tokio::time::sleep(READINESS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Polls the instance's database container until it accepts TCP connections from the
+    /// WordPress user. The images' first-run setup only listens on a socket, so this also
+    /// waits out the initialisation. Instances using an external database return right away.
+    pub async fn wait_for_database(
+        docker: &Docker,
+        instance_id: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        info!("Waiting for database of instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let instance_data = instance
+            .wordpress_data
+            .ok_or_else(|| AnyhowError::msg("Instance data not found"))?;
+        let database = match instance
+            .containers
+            .iter()
+            .find(|container| container.container_image.is_database())
+        {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+        // Recent MariaDB images only ship the `mariadb-admin` client.
+        let admin = match database.container_image {
+            ContainerImage::MariaDB => "mariadb-admin",
+            _ => "mysqladmin",
+        };
+        let password_arg = format!("-p{}", instance_data.adminer_password);
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let last_error = match InstanceContainer::exec(
+                docker,
+                &database.container_id,
+                vec![
+                    admin,
+                    "ping",
+                    "--silent",
+                    "-h",
+                    "127.0.0.1",
+                    "-u",
+                    &instance_data.adminer_user,
+                    &password_arg,
+                ],
+            )
+            .await
+            {
+                Ok(_) => {
+                    info!(
+                        "Database of instance {} is accepting connections",
+                        instance_id
+                    );
+                    return Ok(());
+                }
+                Err(e) => e.to_string(),
+            };
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AnyhowError::msg(format!(
+                    "Database of instance {} not ready after {:?}: {}",
+                    instance_id, timeout, last_error
+                )));
+            }
+            tokio::time::sleep(READINESS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Streams a SQL dump, optionally gzipped, into the instance's database with the database
+    /// container's own credentials, then replaces `previous_site_url` (or the dump's
+    /// `siteurl`) with the instance's URL. Waits for a freshly started database first.
+    pub async fn import_sql_dump(
+        docker: &Docker,
+        instance_id: &str,
+        dump: &Path,
+        previous_site_url: Option<&str>,
+    ) -> Result<()> {
+        info!("Importing {:?} into instance: {}", dump, instance_id);
+        Self::wait_for_database(docker, instance_id, COPY_READY_TIMEOUT).await?;
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let instance_data = instance
+            .wordpress_data
+            .ok_or_else(|| AnyhowError::msg("Instance data not found"))?;
+        let database = instance
+            .containers
+            .iter()
+            .find(|container| container.container_image.is_database())
+            .ok_or_else(|| AnyhowError::msg("Database container not found"))?;
+        let engine = instance_data.database_engine;
+        // Recent MariaDB images only ship the `mariadb` client.
+        let client = match engine {
+            DatabaseEngine::MariaDB => "mariadb",
+            DatabaseEngine::MySQL => "mysql",
+        };
+        let script = format!(
+            "exec {} -u\"${}\" -p\"${}\" \"${}\"",
+            client,
+            engine.env_var("MYSQL_USER"),
+            engine.env_var("MYSQL_PASSWORD"),
+            engine.env_var("MYSQL_DATABASE"),
+        );
+        InstanceContainer::exec_with_input(
+            docker,
+            &database.container_id,
+            vec!["sh", "-c", &script],
+            config::open_sql_dump(dump)?,
+        )
+        .await
+        .context(format!("Failed to import SQL dump {:?}", dump))?;
+
+        let previous_site_url = match previous_site_url {
+            Some(url) => url.to_string(),
+            None => Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "option".to_string(),
+                    "get".to_string(),
+                    "siteurl".to_string(),
+                ],
+            )
+            .await
+            .context("Failed to read the imported site URL")?
+            .trim()
+            .to_string(),
+        };
+        if previous_site_url != instance_data.site_url {
+            info!(
+                "Replacing {} with {}",
+                previous_site_url, instance_data.site_url
+            );
+            Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "search-replace".to_string(),
+                    previous_site_url,
+                    instance_data.site_url,
+                    "--skip-columns=guid".to_string(),
+                ],
+            )
+            .await
+            .context("Failed to update the site URL")?;
+        }
+        Ok(())
+    }
+
+    /// Runs `wp core install` using the admin details recorded in the instance's data.
+    pub async fn install(docker: &Docker, instance_id: &str) -> Result<String> {
+        info!(
+            "Starting to install WordPress for instance: {}",
+            instance_id
+        );
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "core".to_string(),
+                "install".to_string(),
+                format!("--url={}", instance_data.site_url),
+                format!("--title={}", instance_data.site_title),
+                format!("--admin_user={}", instance_data.admin_user),
+                format!("--admin_password={}", instance_data.admin_password),
+                format!("--admin_email={}", instance_data.admin_email),
+                "--skip-email".to_string(),
+            ],
+        )
+        .await
+    }
+
+    /// Installs and activates each of the instance's `install_plugins` with WP-CLI. A plugin
+    /// that fails to install is reported in its result rather than failing the rest, including
+    /// when WP-CLI itself can't run. WordPress has to be installed.
+    pub async fn install_plugins(docker: &Docker, instance_id: &str) -> Result<Vec<PluginResult>> {
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        let mut results = Vec::new();
+        for plugin in instance_data.install_plugins {
+            info!("Installing plugin {} for instance: {}", plugin, instance_id);
+            let result = Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "plugin".to_string(),
+                    "install".to_string(),
+                    plugin.clone(),
+                    "--activate".to_string(),
+                ],
+            )
+            .await;
+            if let Err(e) = &result {
+                error!("Failed to install plugin {}: {:#}", plugin, e);
+            }
+            results.push(PluginResult {
+                plugin,
+                installed: result.is_ok(),
+                error: result.err().map(|e| format!("{:#}", e)),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Activates the plugins mounted with `--plugin`. WordPress has to be installed.
+    pub async fn activate_plugins(docker: &Docker, instance_id: &str) -> Result<String> {
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        if instance_data.plugins.is_empty() {
+            return Ok(String::new());
+        }
+        info!("Activating mounted plugins for instance: {}", instance_id);
+        let mut args = vec!["plugin".to_string(), "activate".to_string()];
+        args.extend(
+            instance_data
+                .plugins
+                .iter()
+                .map(|plugin| config::plugin_name(plugin)),
+        );
+        Self::run_wp_cli(docker, instance_id, args).await
+    }
+
+    /// Streams the combined stdout and stderr of an instance's containers, interleaved as the
+    /// output arrives. `container_id` limits it to the container whose ID starts with it,
+    /// `tail` to that many lines of existing output per container. With `follow` the stream
+    /// stays open for new output until the containers stop.
+    pub async fn logs(
+        docker: &Docker,
+        instance_id: &str,
+        container_id: Option<&str>,
+        follow: bool,
+        tail: Option<usize>,
+    ) -> Result<BoxStream<'static, Result<LogChunk>>> {
+        info!("Streaming logs for instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
+            .await
+            .context("Failed to list instance")?;
+        let containers: Vec<InstanceContainer> = instance
+            .containers
+            .into_iter()
+            .filter(|container| match container_id {
+                Some(id) => container.container_id.starts_with(id),
+                None => true,
+            })
+            .collect();
+        if containers.is_empty() {
+            return Err(AnyhowError::msg(match container_id {
+                Some(id) => format!("No container {} in instance {}", id, instance_id),
+                None => format!("Instance {} has no containers", instance_id),
+            }));
+        }
+
+        let streams = containers.into_iter().map(|container| {
+            let options = LogsOptions::<String> {
+                follow,
+                stdout: true,
+                stderr: true,
+                tail: tail.map_or_else(|| "all".to_string(), |tail| tail.to_string()),
+                ..Default::default()
+            };
+            docker
+                .logs(&container.container_id, Some(options))
+                .map(move |chunk| {
+                    Ok(LogChunk {
+                        container_id: container.container_id.clone(),
+                        container_image: container.container_image.clone(),
+                        output: chunk.context("Failed to read container logs")?.to_string(),
+                    })
+                })
+                .boxed()
         });
+        Ok(stream::select_all(streams).boxed())
+    }
+
+    /// Reads an instance's WordPress admin and Adminer logins from `instance.toml`. This is
+    /// the one place secrets are returned without asking, everything else redacts them.
+    pub async fn credentials(instance_id: &str) -> Result<Credentials> {
+        let instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+        Ok(Credentials {
+            site_url: instance_data.site_url,
+            admin_user: instance_data.admin_user,
+            admin_password: instance_data.admin_password,
+            adminer_url: instance_data.adminer_url,
+            adminer_user: instance_data.adminer_user,
+            adminer_password: instance_data.adminer_password,
+            database_root_password: instance_data.database_root_password,
+        })
+    }
+
+    /// Redacts the passwords in `wordpress_data`, see `InstanceData::redact_secrets`.
+    pub fn redact_secrets(&mut self) {
+        if let Some(instance_data) = self.wordpress_data.as_mut() {
+            instance_data.redact_secrets();
+        }
+    }
+
+    /// Sets the WordPress admin's password, generating one with `wp_generate_password` when
+    /// `password` is `None`, and records it in `instance.toml`. If the recorded admin user
+    /// doesn't exist in WordPress the first administrator is used and recorded instead.
+    pub async fn set_admin_password(
+        docker: &Docker,
+        instance_id: &str,
+        password: Option<String>,
+    ) -> Result<AdminLogin> {
+        info!("Setting admin password for instance: {}", instance_id);
+        let mut instance_data = config::read_instance_data_from_toml(instance_id)
+            .await
+            .context("Failed to read instance data")?;
+
+        let administrators = Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "user".to_string(),
+                "list".to_string(),
+                "--role=administrator".to_string(),
+                "--field=user_login".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to list administrators")?;
+        let administrators: Vec<&str> = administrators
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let admin_user = if administrators.contains(&instance_data.admin_user.as_str()) {
+            instance_data.admin_user.clone()
+        } else {
+            let admin_user = administrators.first().ok_or_else(|| {
+                AnyhowError::msg(format!(
+                    "No administrator found for instance {}, is WordPress installed?",
+                    instance_id
+                ))
+            })?;
+            info!(
+                "Admin user {} not found, using {}",
+                instance_data.admin_user, admin_user
+            );
+            admin_user.to_string()
+        };
+
+        let password = match password {
+            Some(password) => password,
+            None => Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "eval".to_string(),
+                    "echo wp_generate_password(24, true, false);".to_string(),
+                ],
+            )
+            .await
+            .context("Failed to generate password")?
+            .trim()
+            .to_string(),
+        };
+        Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "user".to_string(),
+                "update".to_string(),
+                admin_user.clone(),
+                format!("--user_pass={}", password),
+                "--skip-email".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to update admin password")?;
 
-        let results: Result<Vec<_>> = join_all(stop_instance_futures).await.into_iter().collect();
+        instance_data.admin_user = admin_user.clone();
+        instance_data.admin_password = password.clone();
+        config::write_instance_data_to_toml(instance_id, &instance_data).await?;
 
-        results
+        Ok(AdminLogin {
+            url: format!("{}/wp-admin", instance_data.site_url),
+            installed: true,
+            admin_user,
+            admin_password: password,
+        })
     }
 
-    pub async fn restart(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
-        info!("Starting to restart instance: {}", instance_id);
-        let mut instance = Self::list(docker, &instance_id)
-            .await
-            .context("Failed to list instance")?;
-        let restart_container_futures = instance.containers.iter().map(|container| async move {
-            InstanceContainer::restart(docker, &container.container_id)
+    /// Wipes a running instance back to a fresh WordPress install, keeping its containers,
+    /// ports and admin credentials. Plugins other than the ones bundled with core are deleted
+    /// and, unless `keep_uploads` is set, so is `wp-content/uploads`.
+    pub async fn reset(docker: &Docker, instance_id: &str, keep_uploads: bool) -> Result<String> {
+        info!("Starting to reset instance: {}", instance_id);
+        let mut output = Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec!["db".to_string(), "reset".to_string(), "--yes".to_string()],
+        )
+        .await
+        .context("Failed to reset database")?;
+        output.push_str(
+            &Self::install(docker, instance_id)
                 .await
-                .with_context(|| format!("Failed to restart container {}", &container.container_id))
-        });
-        let _ = join_all(restart_container_futures).await;
-        instance.status = InstanceStatus::default(docker, &instance.containers)
+                .context("Failed to reinstall WordPress")?,
+        );
+        output.push_str(
+            &Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "plugin".to_string(),
+                    "delete".to_string(),
+                    "--all".to_string(),
+                    "--exclude=akismet,hello".to_string(),
+                ],
+            )
             .await
-            .context("Failed to get default status for instance containers")?;
-        Ok(InstanceInfo {
-            uuid: instance.uuid.clone(),
-            status: format!("{:?}", instance.status),
-        })
+            .context("Failed to delete plugins")?,
+        );
+
+        if !keep_uploads {
+            let container_id =
+                Self::find_container(docker, instance_id, &ContainerImage::Wordpress).await?;
+            InstanceContainer::exec(
+                docker,
+                &container_id,
+                vec!["sh", "-c", "rm -rf /var/www/html/wp-content/uploads/*"],
+            )
+            .await
+            .context("Failed to clear uploads")?;
+        }
+
+        Ok(output)
     }
 
-    pub async fn restart_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+    /// Imports demo content into an installed instance. The source is taken from
+    /// `demo_content_source` in the config and may be either a local WXR file or a URL.
+    pub async fn import_demo_content(docker: &Docker, instance_id: &str) -> Result<String> {
         info!(
-            "Starting to restart all instances for network prefix: {}",
-            network_prefix
+            "Starting to import demo content for instance: {}",
+            instance_id
         );
-        let instances = Self::list_all(docker, network_prefix)
-            .await
-            .context("Failed to list instances")?;
+        let config = config::read_or_create_config().await?;
+        let instance_dir = config::get_instance_dir().await?;
+        let source = config.demo_content_source;
+        let wxr_name = "wpdev-demo-content.xml";
+        let wxr_host_path = instance_dir
+            .join(instance_id)
+            .join("wordpress")
+            .join(wxr_name);
+        let wxr_container_path = format!("/var/www/html/{}", wxr_name);
 
-        let restart_instance_futures = instances.values().map(|instance| async move {
-            Self::restart(docker, &instance.uuid)
+        Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "plugin".to_string(),
+                "install".to_string(),
+                "wordpress-importer".to_string(),
+                "--activate".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to install wordpress-importer")?;
+
+        if PathBuf::from(&source).is_file() {
+            info!("Copying demo content from {}", source);
+            fs::copy(&source, &wxr_host_path)
                 .await
-                .with_context(|| format!("Failed to restart instance {}", &instance.uuid))
-        });
+                .context(format!("Failed to copy demo content from {}", source))?;
+        } else {
+            info!("Downloading demo content from {}", source);
+            Self::run_wp_cli(
+                docker,
+                instance_id,
+                vec![
+                    "eval".to_string(),
+                    format!(
+                        "file_put_contents('{}', file_get_contents('{}')) or exit(1);",
+                        wxr_container_path, source
+                    ),
+                ],
+            )
+            .await
+            .context(format!("Failed to download demo content from {}", source))?;
+        }
 
-        let results: Result<Vec<_>> = join_all(restart_instance_futures)
+        let result = Self::run_wp_cli(
+            docker,
+            instance_id,
+            vec![
+                "import".to_string(),
+                wxr_container_path,
+                "--authors=create".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to import demo content");
+
+        if let Err(e) = fs::remove_file(&wxr_host_path).await {
+            error!("Failed to remove {:?}: {}", wxr_host_path, e);
+        }
+
+        result
+    }
+
+    /// Resolves the wp-admin URL for an instance along with its stored credentials. If
+    /// WordPress hasn't been installed yet the URL points at the installer instead.
+    pub async fn admin_url(docker: &Docker, instance_id: &str) -> Result<AdminLogin> {
+        info!("Starting to get admin url for instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
             .await
-            .into_iter()
-            .collect();
+            .context("Failed to list instance")?;
+        let instance_data = instance
+            .wordpress_data
+            .ok_or_else(|| AnyhowError::msg("Instance data not found"))?;
+        let mysql_container = instance
+            .containers
+            .iter()
+            .find(|container| container.container_image.is_database())
+            .ok_or_else(|| AnyhowError::msg("Database container not found"))?;
+        // Recent MariaDB images only ship the `mariadb` client.
+        let client = match mysql_container.container_image {
+            ContainerImage::MariaDB => "mariadb",
+            _ => "mysql",
+        };
 
-        results
+        let password_arg = format!("-p{}", instance_data.adminer_password);
+        let output = InstanceContainer::exec(
+            docker,
+            &mysql_container.container_id,
+            vec![
+                client,
+                "-N",
+                "-u",
+                &instance_data.adminer_user,
+                &password_arg,
+                "wordpress",
+                "-e",
+                "SHOW TABLES LIKE 'wp_options'",
+            ],
+        )
+        .await
+        .context("Failed to check if WordPress is installed")?;
+        let installed = output.contains("wp_options");
+
+        let url = if installed {
+            format!("{}/wp-admin", instance_data.site_url)
+        } else {
+            format!("{}/wp-admin/install.php", instance_data.site_url)
+        };
+
+        Ok(AdminLogin {
+            url,
+            installed,
+            admin_user: instance_data.admin_user,
+            admin_password: instance_data.admin_password,
+        })
     }
 
-    pub async fn delete(docker: &Docker, instance_id: &str, purge: bool) -> Result<InstanceInfo> {
-        info!("Starting to delete instance: {}", instance_id);
-        let instance = Self::list(docker, &instance_id)
+    pub async fn stats(docker: &Docker, instance_id: &str) -> Result<InstanceStats> {
+        info!("Getting stats for instance: {}", instance_id);
+        let instance = Self::list(docker, instance_id)
             .await
             .context("Failed to list instance")?;
-        let delete_container_futures = instance.containers.iter().map(|container| async move {
-            InstanceContainer::delete(docker, &container.container_id)
-                .await
-                .with_context(|| format!("Failed to delete container {}", &container.container_id))
-        });
-        let _ = join_all(delete_container_futures).await;
-        if !purge {
-            purge_instances(InstanceSelection::One(instance_id.to_string())).await?;
-        }
-        Ok(InstanceInfo {
-            uuid: instance.uuid.clone(),
-            status: format!("{:?}", InstanceStatus::Deleted),
+        let containers = join_all(
+            instance
+                .containers
+                .iter()
+                .filter(|container| matches!(container.container_status, ContainerStatus::Running))
+                .map(|container| {
+                    InstanceContainer::stats(
+                        docker,
+                        &container.container_id,
+                        container.container_image.clone(),
+                    )
+                }),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+        Ok(InstanceStats {
+            instance_id: instance_id.to_string(),
+            cpu_percent: containers.iter().map(|c| c.cpu_percent).sum(),
+            memory_usage: containers.iter().map(|c| c.memory_usage).sum(),
+            containers,
         })
     }
 
-    pub async fn delete_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceInfo>> {
+    pub async fn stats_all(docker: &Docker, network_prefix: &str) -> Result<Vec<InstanceStats>> {
         info!(
-            "Starting to delete all instances for network prefix: {}",
+            "Getting stats for all instances with prefix: {}",
             network_prefix
         );
-        let instances = Self::list_all(docker, network_prefix)
-            .await
-            .context("Failed to list instances")?;
-
-        let delete_instance_futures = instances.values().map(|instance| async move {
-            Self::delete(docker, &instance.uuid, true)
-                .await
-                .with_context(|| format!("Failed to delete instance {}", &instance.uuid))
-        });
+        let instances = Self::list_all(docker, network_prefix).await?;
+        let mut stats = join_all(
+            instances
+                .keys()
+                .map(|instance_id| Self::stats(docker, instance_id)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+        stats.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+        Ok(stats)
+    }
 
-        let results: Result<Vec<_>> = join_all(delete_instance_futures)
-            .await
-            .into_iter()
-            .collect();
+    /// Copies `container_path` out of one of the instance's containers to `local_path`. Like
+    /// `docker cp`, an existing local directory receives the file or directory inside it,
+    /// otherwise the copy is written to `local_path` itself.
+    pub async fn copy_from(
+        docker: &Docker,
+        instance_id: &str,
+        container_image: ContainerImage,
+        container_path: &str,
+        local_path: &PathBuf,
+    ) -> Result<()> {
+        info!(
+            "Copying {}:{} from instance {} to {:?}",
+            container_image, container_path, instance_id, local_path
+        );
+        let container_id = Self::find_container(docker, instance_id, &container_image).await?;
+        let mut archive = Vec::new();
+        let mut stream = docker.download_from_container(
+            &container_id,
+            Some(DownloadFromContainerOptions {
+                path: container_path,
+            }),
+        );
+        while let Some(chunk) = stream.next().await {
+            archive.extend_from_slice(&chunk.context(format!(
+                "Failed to download {} from {} container",
+                container_path, container_image
+            ))?);
+        }
 
-        purge_instances(InstanceSelection::All).await?;
+        // The archive is rooted at the basename of `container_path`, strip that component
+        // unless copying into an existing directory.
+        let skip = if local_path.is_dir() { 0 } else { 1 };
+        let mut archive = tar::Archive::new(&archive[..]);
+        for entry in archive.entries().context("Failed to read archive")? {
+            let mut entry = entry.context("Failed to read archive entry")?;
+            let entry_path = entry
+                .path()
+                .context("Invalid path in archive")?
+                .into_owned();
+            let destination =
+                local_path.join(entry_path.components().skip(skip).collect::<PathBuf>());
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)
+                    .context(format!("Failed to create directory {:?}", parent))?;
+            }
+            entry
+                .unpack(&destination)
+                .context(format!("Failed to write {:?}", destination))?;
+        }
 
-        results
+        Ok(())
     }
 
-    pub async fn inspect(docker: &Docker, instance_id: &str) -> Result<Instance> {
-        info!("Starting to inspect instance: {}", instance_id);
-        let instance_name = format!("{}", instance_id);
-        let instance = Self::list(docker, &instance_name)
+    /// Copies a local file or directory into one of the instance's containers at
+    /// `container_path`.
+    pub async fn copy_to(
+        docker: &Docker,
+        instance_id: &str,
+        container_image: ContainerImage,
+        local_path: &PathBuf,
+        container_path: &str,
+    ) -> Result<()> {
+        info!(
+            "Copying {:?} to {}:{} in instance {}",
+            local_path, container_image, container_path, instance_id
+        );
+        let container_id = Self::find_container(docker, instance_id, &container_image).await?;
+        let target = PathBuf::from(container_path);
+        let (parent, name) = match (target.parent(), target.file_name()) {
+            (Some(parent), Some(name)) => (parent.to_path_buf(), PathBuf::from(name)),
+            _ => {
+                return Err(AnyhowError::msg(format!(
+                    "Invalid container path: {}",
+                    container_path
+                )))
+            }
+        };
+
+        let mut builder = tar::Builder::new(Vec::new());
+        if local_path.is_dir() {
+            builder.append_dir_all(&name, local_path)
+        } else {
+            builder.append_path_with_name(local_path, &name)
+        }
+        .context(format!("Failed to archive {:?}", local_path))?;
+        let archive = builder
+            .into_inner()
+            .context(format!("Failed to archive {:?}", local_path))?;
+
+        docker
+            .upload_to_container(
+                &container_id,
+                Some(UploadToContainerOptions {
+                    path: parent.to_string_lossy().to_string(),
+                    ..Default::default()
+                }),
+                archive.into(),
+            )
             .await
-            .context("Failed to list instance")?;
-        Ok(instance)
+            .context(format!(
+                "Failed to upload {:?} to {} container",
+                local_path, container_image
+            ))?;
+
+        Ok(())
     }
 
-    pub async fn inspect_all(docker: &Docker, network_prefix: &str) -> Result<Vec<Instance>> {
-        info!(
-            "Starting to inspect all instances for network prefix: {}",
-            network_prefix
-        );
-        let instances = Self::list_all(docker, network_prefix)
+    async fn find_container(
+        docker: &Docker,
+        instance_id: &str,
+        container_image: &ContainerImage,
+    ) -> Result<String> {
+        let instance = Self::list(docker, instance_id)
             .await
-            .context("Failed to list instances")?;
-        Ok(instances
+            .context("Failed to list instance")?;
+        instance
+            .containers
             .into_iter()
-            .map(|(_, instance)| instance)
-            .collect())
+            .find(|container| container.container_image.to_string() == container_image.to_string())
+            .map(|container| container.container_id)
+            .ok_or_else(|| {
+                AnyhowError::msg(format!(
+                    "{} container not found for instance {}",
+                    container_image, instance_id
+                ))
+            })
     }
 
     pub async fn get_status(docker: &Docker, instance_id: &str) -> Result<InstanceInfo> {
@@ -520,10 +3528,90 @@ impl Instance {
     }
 }
 
+/// Fails with a hint to pull the image when `WORDPRESS_CLI_IMAGE` isn't available locally,
+/// rather than the bare "no such image" Docker returns when creating the container.
+async fn ensure_wp_cli_image(docker: &Docker) -> Result<()> {
+    match docker.inspect_image(crate::WORDPRESS_CLI_IMAGE).await {
+        Ok(_) => Ok(()),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Err(AnyhowError::msg(format!(
+            "WP-CLI image {} isn't pulled, run `docker pull {}` or add it to docker_images in the config",
+            crate::WORDPRESS_CLI_IMAGE,
+            crate::WORDPRESS_CLI_IMAGE
+        ))),
+        Err(e) => Err(AnyhowError::from(e).context(format!(
+            "Failed to inspect WP-CLI image {}",
+            crate::WORDPRESS_CLI_IMAGE
+        ))),
+    }
+}
+
+async fn run_bulk<F, Fut>(
+    instance_ids: Vec<String>,
+    options: &BulkOptions,
+    operation: F,
+) -> BulkReport
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<InstanceInfo>>,
+{
+    let total = instance_ids.len();
+    let operation = &operation;
+    let mut results = stream::iter(instance_ids)
+        .map(|instance_id| async move {
+            if options
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.is_cancelled())
+            {
+                info!("Skipping {}, operation was cancelled", instance_id);
+                return (instance_id, None);
+            }
+            let result = operation(instance_id.clone()).await;
+            (instance_id, Some(result))
+        })
+        .buffer_unordered(BULK_CONCURRENCY);
+
+    let mut report = BulkReport::default();
+    let mut completed = 0;
+    while let Some((instance_id, result)) = results.next().await {
+        let Some(result) = result else {
+            report.skipped.push(instance_id);
+            continue;
+        };
+        completed += 1;
+        if let Some(progress) = &options.progress {
+            progress(&BulkProgress {
+                instance_id: instance_id.clone(),
+                completed,
+                total,
+                success: result.is_ok(),
+            });
+        }
+        match result {
+            Ok(info) => report.completed.push(info),
+            Err(e) => {
+                error!("{:#}", e);
+                report.failed.push(BulkFailure {
+                    instance_id,
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    report
+}
+
 async fn purge_instances(instance: InstanceSelection) -> Result<()> {
     info!("Starting to purge instances");
+    config::load_config()
+        .await?
+        .validate()
+        .context("Invalid custom_root in config")?;
     let instance_dir = config::get_instance_dir().await?;
-    let docker = Docker::connect_with_defaults().context("Failed to connect to Docker")?;
+    let docker = crate::docker::connect_docker().await?;
 
     if !instance_dir.exists() {
         info!("Instance directory not found");
@@ -531,8 +3619,9 @@ async fn purge_instances(instance: InstanceSelection) -> Result<()> {
     }
 
     match instance {
-        InstanceSelection::All => {
+        InstanceSelection::All(network_prefix) => {
             info!("Pruning all instances");
+            config::sync_hosts_file(config::HostsAction::Purge).await;
             let p = &instance_dir;
             let path = p.to_str().context("Instance directory not found")?;
             let networks = docker
@@ -546,23 +3635,37 @@ async fn purge_instances(instance: InstanceSelection) -> Result<()> {
             }
 
             info!("Pruning networks");
-            for network in networks.into_iter().filter(|n| {
-                n.name
-                    .as_ref()
-                    .map_or(false, |name| name.starts_with(crate::NETWORK_NAME))
-            }) {
+            for network in networks
+                .into_iter()
+                .filter(|network| config::network_in_namespace(network, &network_prefix))
+            {
                 let full_network_name = network.name.unwrap_or_default();
                 docker
                     .remove_network(&full_network_name)
                     .await
                     .context(format!("Failed to remove network {}", full_network_name))?;
+                let instance_path = format!("{}/{}", path, full_network_name);
+                if PathBuf::from(&instance_path).exists() {
+                    info!("Removing directory: {}", instance_path);
+                    utils::remove_dir_verified(
+                        Path::new(&instance_path),
+                        REMOVE_DIR_ATTEMPTS,
+                        REMOVE_DIR_DELAY,
+                    )
+                    .await?;
+                }
+            }
+            for instance_id in config::external_network_instances(&network_prefix).await? {
+                let instance_path = format!("{}/{}", path, instance_id);
+                info!("Removing directory: {}", instance_path);
+                utils::remove_dir_verified(
+                    Path::new(&instance_path),
+                    REMOVE_DIR_ATTEMPTS,
+                    REMOVE_DIR_DELAY,
+                )
+                .await?;
             }
             info!("Networks pruned");
-            info!("Removing instances directory: {}", path);
-            fs::remove_dir_all(&path)
-                .await
-                .context(format!("Error removing directory: {}", path))?;
-            info!("Directory removed: {}", path);
             Ok(())
         }
         InstanceSelection::One(instance_uuid) => {
@@ -574,18 +3677,535 @@ async fn purge_instances(instance: InstanceSelection) -> Result<()> {
                 error!("Instance directory not found");
                 return Ok(());
             }
-            info!("Removing network: {}", instance_uuid);
-            docker
-                .remove_network(&instance_uuid)
+            let external_network = config::read_instance_data_from_toml(&instance_uuid)
                 .await
-                .context(format!("Failed to remove network {}", instance_uuid))?;
-            info!("Network removed: {}", instance_uuid);
+                .ok()
+                .and_then(|data| data.external_network);
+            match external_network {
+                // The external network is managed outside wpdev and may be shared, so leave it.
+                Some(network_name) => info!("Keeping external network: {}", network_name),
+                None => {
+                    info!("Removing network: {}", instance_uuid);
+                    docker
+                        .remove_network(&instance_uuid)
+                        .await
+                        .context(format!("Failed to remove network {}", instance_uuid))?;
+                    info!("Network removed: {}", instance_uuid);
+                }
+            }
             info!("Removing directory: {}", instance_path);
-            fs::remove_dir_all(&instance_path)
-                .await
-                .context(format!("Error removing directory: {}", instance_path))?;
-            info!("Directory removed: {}", instance_path);
+            utils::remove_dir_verified(
+                Path::new(&instance_path),
+                REMOVE_DIR_ATTEMPTS,
+                REMOVE_DIR_DELAY,
+            )
+            .await?;
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockDocker, TestEnv, NAMESPACE};
+    use serde_json::json;
+
+    /// A one-entry archive, with the name written into the header as is, since
+    /// `Header::set_path` refuses `..`.
+    fn archive_with_entry(name: &str) -> Vec<u8> {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(1);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, &b"x"[..]).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn first_entry_path(archive: &[u8]) -> Result<PathBuf> {
+        let mut archive = tar::Archive::new(archive);
+        let entry = archive.entries()?.next().unwrap()?;
+        Instance::backup_entry_path(&entry)
+    }
+
+    #[test]
+    fn backup_entry_path_accepts_relative_paths() {
+        let archive = archive_with_entry("wpdev-a/wordpress/index.php");
+        assert_eq!(
+            first_entry_path(&archive).unwrap(),
+            PathBuf::from("wpdev-a/wordpress/index.php")
+        );
+    }
+
+    /// Records the hooks `Instance::apply` fires, in order.
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl CreateObserver for RecordingObserver {
+        fn network_created(&self, network_name: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("network {}", network_name));
+        }
+
+        fn container_created(&self, container_image: &ContainerImage, _container_id: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("container {}", container_image.to_string()));
+        }
+
+        fn config_written(&self, instance_id: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("config {}", instance_id));
+        }
+
+        fn instance_started(&self, instance_id: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("started {}", instance_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn create_fires_observer_hooks_in_order() {
+        let docker = MockDocker::start(test_support::daemon).await;
+        let _env = TestEnv::new(&docker.config()).await;
+        let observer = RecordingObserver::default();
+
+        Instance::new_with_observer(
+            &docker.client(),
+            "observed",
+            ContainerEnvVars::default(),
+            true,
+            &observer,
+        )
+        .await
+        .unwrap();
+
+        let instance_id = format!("{}-observed", NAMESPACE);
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            vec![
+                format!("network {}", instance_id),
+                "container mysql".to_string(),
+                "container wordpress".to_string(),
+                "container nginx".to_string(),
+                "container adminer".to_string(),
+                format!("config {}", instance_id),
+                format!("started {}", instance_id),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_create_removes_what_it_created() {
+        // The third container, nginx, fails after MySQL and WordPress were created.
+        let docker = MockDocker::start(|request| {
+            if request.is("POST", "/containers/create?name=rollback-nginx") {
+                return (500, r#"{"message": "injected failure"}"#.to_string());
+            }
+            test_support::daemon(request)
+        })
+        .await;
+        let env = TestEnv::new(&docker.config()).await;
+
+        let result = Instance::new(
+            &docker.client(),
+            "rollback",
+            ContainerEnvVars::default(),
+            false,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let requests = docker.requests();
+        for container in ["rollback-mysql", "rollback-wordpress"] {
+            assert!(
+                requests
+                    .iter()
+                    .any(|request| request.is("DELETE", &format!("/containers/{}", container))),
+                "{} wasn't removed",
+                container
+            );
+        }
+        assert!(!requests
+            .iter()
+            .any(|request| request.is("DELETE", "/containers/rollback-nginx")));
+        assert!(requests
+            .iter()
+            .any(|request| request.is("DELETE", &format!("/networks/{}-rollback", NAMESPACE))));
+        assert!(!env
+            .instances_dir()
+            .join(format!("{}-rollback", NAMESPACE))
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn plan_makes_no_docker_calls() {
+        let docker = MockDocker::start(test_support::daemon).await;
+        let env = TestEnv::new(&docker.config()).await;
+        env.write_config(
+            "config.toml",
+            &format!(
+                "namespace = {:?}\ncustom_root = {:?}\nauto_pull_images = true\n{}",
+                NAMESPACE,
+                env.instances_dir(),
+                docker.config()
+            ),
+        );
+
+        let plan = Instance::plan("planned", ContainerEnvVars::default())
+            .await
+            .unwrap();
+
+        assert_eq!(plan.instance_id, format!("{}-planned", NAMESPACE));
+        assert!(docker.requests().is_empty(), "{:?}", docker.requests());
+        // Not even `custom_root` is created, that's left to `apply`.
+        assert!(!env.instances_dir().exists());
+    }
+
+    /// Creates `label` against `docker` and deletes it again, returning the instance directory.
+    async fn create_and_delete(
+        docker: &MockDocker,
+        env: &TestEnv,
+        label: &str,
+        purge: bool,
+    ) -> PathBuf {
+        let client = docker.client();
+        Instance::new(&client, label, ContainerEnvVars::default(), false)
+            .await
+            .unwrap();
+        let instance_id = format!("{}-{}", NAMESPACE, label);
+        let instance_dir = env.instances_dir().join(&instance_id);
+        assert!(instance_dir.exists());
+
+        Instance::delete(&client, &instance_id, purge)
+            .await
+            .unwrap();
+        instance_dir
+    }
+
+    #[tokio::test]
+    async fn delete_keeps_the_instance_without_purge() {
+        let docker = MockDocker::start(test_support::daemon).await;
+        let env = TestEnv::new(&docker.config()).await;
+
+        let instance_dir = create_and_delete(&docker, &env, "kept", false).await;
+
+        assert!(instance_dir.join("instance.toml").exists());
+        assert!(!docker
+            .requests()
+            .iter()
+            .any(|request| request.is("DELETE", "/networks/")));
+    }
+
+    #[tokio::test]
+    async fn delete_with_purge_removes_only_that_instance() {
+        let docker = MockDocker::start(test_support::daemon).await;
+        let env = TestEnv::new(&docker.config()).await;
+        let other_dir = env.instances_dir().join(format!("{}-other", NAMESPACE));
+        std::fs::create_dir_all(&other_dir).unwrap();
+
+        let instance_dir = create_and_delete(&docker, &env, "purged", true).await;
+
+        assert!(!instance_dir.exists());
+        assert!(other_dir.exists());
+        let network_removals: Vec<_> = docker
+            .requests()
+            .into_iter()
+            .filter(|request| request.is("DELETE", "/networks/"))
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(
+            network_removals,
+            [format!("/networks/{}-purged", NAMESPACE)]
+        );
+    }
+
+    /// `instance.toml` of an adopted instance whose ports weren't known when it was adopted.
+    const ADOPTED_INSTANCE: &str = r#"
+admin_user = "admin"
+admin_password = "password"
+admin_email = "admin@example.com"
+site_title = "Legacy"
+site_url = "http://localhost:0"
+adminer_url = "http://localhost:0"
+adminer_user = "admin"
+adminer_password = "password"
+network_name = "test-legacy"
+nginx_port = 0
+adminer_port = 0
+external_network = "legacy"
+adopted = true
+"#;
+
+    #[test]
+    fn fill_missing_ports_marks_unknown_ports() {
+        let mut instance_data: InstanceData = toml::from_str(ADOPTED_INSTANCE).unwrap();
+        instance_data.fill_missing_ports(Some(8123), None);
+
+        assert_eq!(instance_data.nginx_port, 8123);
+        assert_eq!(instance_data.site_url, "http://localhost:8123");
+        assert_eq!(instance_data.adminer_port, 0);
+        assert_eq!(instance_data.adminer_url, UNKNOWN_URL);
+    }
+
+    #[tokio::test]
+    async fn list_fills_in_ports_of_unlabelled_containers() {
+        let docker = MockDocker::start(|request| {
+            if request.is("GET", "/containers/json") {
+                let container = |id: &str, image: &str, public_port: u16| {
+                    json!({
+                        "Id": id,
+                        "Image": image,
+                        "State": "running",
+                        "Labels": {},
+                        "Ports": [{"PrivatePort": 80, "PublicPort": public_port, "Type": "tcp"}],
+                    })
+                };
+                let containers = json!([
+                    container("legacy-nginx", "nginx:1.25", 8123),
+                    container("legacy-adminer", "adminer:latest", 8124),
+                ]);
+                return (200, containers.to_string());
+            }
+            test_support::daemon(request)
+        })
+        .await;
+        let env = TestEnv::new(&docker.config()).await;
+        let instance_id = format!("{}-legacy", NAMESPACE);
+        std::fs::create_dir_all(env.instances_dir().join(&instance_id)).unwrap();
+        std::fs::write(
+            env.instances_dir().join(&instance_id).join("instance.toml"),
+            ADOPTED_INSTANCE,
+        )
+        .unwrap();
+
+        let instance = Instance::list(&docker.client(), &instance_id)
+            .await
+            .unwrap();
+
+        assert_eq!(instance.containers.len(), 2);
+        assert_eq!(instance.nginx_port, 8123);
+        assert_eq!(instance.adminer_port, 8124);
+        let instance_data = instance.wordpress_data.unwrap();
+        assert_eq!(instance_data.site_url, "http://localhost:8123");
+        assert_eq!(instance_data.adminer_url, "http://localhost:8124");
+    }
+
+    /// Records `instance_id` as an instance on its own network, without creating anything.
+    fn write_instance(env: &TestEnv, instance_id: &str) {
+        let mut instance_data: InstanceData = toml::from_str(ADOPTED_INSTANCE).unwrap();
+        instance_data.network_name = instance_id.to_string();
+        instance_data.external_network = None;
+        instance_data.adopted = false;
+        let dir = env.instances_dir().join(instance_id);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("instance.toml"),
+            toml::to_string(&instance_data).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_stops_paused_containers_first() {
+        let instance_id = format!("{}-00000000-0000-0000-0000-00000000000a", NAMESPACE);
+        let label = instance_id[NAMESPACE.len() + 1..].to_string();
+        let docker = MockDocker::start(move |request| {
+            if request.is("GET", "/containers/json") {
+                let containers = json!([{
+                    "Id": "paused-wordpress",
+                    "Image": "wordpress:latest",
+                    "State": "paused",
+                    "Labels": {"instance": label},
+                }]);
+                return (200, containers.to_string());
+            }
+            if request.is("GET", "/containers/paused-wordpress/json") {
+                let container = json!({
+                    "Id": "paused-wordpress",
+                    "State": {"Status": "paused"},
+                    "Config": {"Labels": {}, "Image": "wordpress:latest"},
+                });
+                return (200, container.to_string());
+            }
+            if request.is("POST", "/containers/paused-wordpress/stop") {
+                return (204, String::new());
+            }
+            test_support::daemon(request)
+        })
+        .await;
+        let env = TestEnv::new(&docker.config()).await;
+        write_instance(&env, &instance_id);
+
+        Instance::delete(&docker.client(), &instance_id, false)
+            .await
+            .unwrap();
+
+        let container_calls: Vec<_> = docker
+            .requests()
+            .into_iter()
+            .filter(|request| {
+                request.is("POST", "/containers/paused-wordpress/")
+                    || request.is("DELETE", "/containers/paused-wordpress")
+            })
+            .map(|request| format!("{} {}", request.method, request.path))
+            .collect();
+        assert_eq!(
+            container_calls,
+            [
+                "POST /containers/paused-wordpress/stop",
+                "DELETE /containers/paused-wordpress",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_all_keeps_instances_that_failed_to_delete() {
+        let deleted = format!("{}-00000000-0000-0000-0000-00000000000a", NAMESPACE);
+        let failed = format!("{}-00000000-0000-0000-0000-00000000000b", NAMESPACE);
+        let networks = json!([
+            {"Name": deleted, "Labels": {config::NAMESPACE_LABEL: NAMESPACE}},
+            {"Name": failed, "Labels": {config::NAMESPACE_LABEL: NAMESPACE}},
+        ]);
+        // The instance is listed fine by `list_all`, but not again when it's deleted.
+        let listings = std::sync::atomic::AtomicUsize::new(0);
+        let failing = failed.clone();
+        let docker = MockDocker::start(move |request| {
+            if request.is("GET", "/networks") {
+                return (200, networks.to_string());
+            }
+            if request.is("GET", "/containers/json")
+                && request.path.contains(&failing)
+                && listings.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0
+            {
+                return (500, json!({"message": "daemon went away"}).to_string());
+            }
+            test_support::daemon(request)
+        })
+        .await;
+        let env = TestEnv::new(&docker.config()).await;
+        write_instance(&env, &deleted);
+        write_instance(&env, &failed);
+
+        let report =
+            Instance::delete_all_tracked(&docker.client(), NAMESPACE, &BulkOptions::default())
+                .await
+                .unwrap();
+
+        assert_eq!(report.completed.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].instance_id, failed);
+        assert!(!env.instances_dir().join(&deleted).exists());
+        assert!(env
+            .instances_dir()
+            .join(&failed)
+            .join("instance.toml")
+            .exists());
+        let network_removals: Vec<_> = docker
+            .requests()
+            .into_iter()
+            .filter(|request| request.is("DELETE", "/networks/"))
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(network_removals, [format!("/networks/{}", deleted)]);
+    }
+
+    #[tokio::test]
+    async fn list_all_keeps_namespaces_apart() {
+        const WP: &str = "wp-00000000-0000-0000-0000-00000000000a";
+        const WP_UNLABELLED: &str = "wp-00000000-0000-0000-0000-00000000000b";
+        const WP_NETWORK: &str = "wp-network-00000000-0000-0000-0000-00000000000c";
+        const WP_NETWORK_UNLABELLED: &str = "wp-network-00000000-0000-0000-0000-00000000000d";
+        let docker = MockDocker::start(|request| {
+            if request.is("GET", "/networks") {
+                let networks = json!([
+                    {"Name": WP, "Labels": {config::NAMESPACE_LABEL: "wp"}},
+                    {"Name": WP_UNLABELLED, "Labels": {}},
+                    {"Name": WP_NETWORK, "Labels": {config::NAMESPACE_LABEL: "wp-network"}},
+                    {"Name": WP_NETWORK_UNLABELLED, "Labels": {}},
+                ]);
+                return (200, networks.to_string());
+            }
+            test_support::daemon(request)
+        })
+        .await;
+        let env = TestEnv::new(&docker.config()).await;
+        for instance_id in [WP, WP_UNLABELLED, WP_NETWORK, WP_NETWORK_UNLABELLED] {
+            write_instance(&env, instance_id);
+        }
+
+        for (namespace, expected) in [
+            ("wp", [WP, WP_UNLABELLED]),
+            ("wp-network", [WP_NETWORK, WP_NETWORK_UNLABELLED]),
+        ] {
+            let instances = Instance::list_all(&docker.client(), namespace)
+                .await
+                .unwrap();
+            let mut instance_ids: Vec<_> = instances.keys().map(String::as_str).collect();
+            instance_ids.sort();
+            assert_eq!(instance_ids, expected, "{}", namespace);
+        }
+    }
+
+    #[test]
+    fn open_backup_detects_the_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [
+            BackupCompression::None,
+            BackupCompression::Gzip,
+            BackupCompression::Zstd,
+        ] {
+            let path = dir.path().join(format!("{:?}.tar", compression));
+            let writer =
+                BackupWriter::new(std::fs::File::create(&path).unwrap(), compression).unwrap();
+            let mut builder = tar::Builder::new(writer);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(2);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, "wpdev-a/snapshot.json", &b"{}"[..])
+                .unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+
+            let mut archive = Instance::open_backup(&path).unwrap();
+            let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            assert_eq!(
+                Instance::backup_entry_path(&entry).unwrap(),
+                PathBuf::from("wpdev-a/snapshot.json"),
+                "{:?}",
+                compression
+            );
+            assert_eq!(contents, "{}");
+        }
+    }
+
+    #[test]
+    fn backup_entry_path_rejects_traversal() {
+        for name in [
+            "wpdev-a/wordpress/../../../etc/passwd",
+            "../wpdev-a/snapshot.json",
+            "/etc/passwd",
+            "./wpdev-a/instance.toml",
+        ] {
+            assert!(
+                first_entry_path(&archive_with_entry(name)).is_err(),
+                "{} was accepted",
+                name
+            );
+        }
+    }
+}
@@ -1,3 +1,79 @@
+pub mod compose;
 pub mod config;
 pub mod container;
+pub mod events;
 pub mod instance;
+
+use anyhow::{Context, Error as AnyhowError, Result};
+use bollard::{Docker, API_DEFAULT_VERSION};
+
+/// Seconds a Docker request may take, the same as bollard's default.
+const DOCKER_TIMEOUT: u64 = 120;
+
+/// Connects to the Docker daemon in `docker_host` from the config, see `connect_docker_host`.
+pub async fn connect_docker() -> Result<Docker> {
+    let config = crate::config::load_config().await?;
+    connect_docker_host(config.docker_host.as_deref())
+}
+
+/// Connects to the Docker daemon at `host`, falling back to `DOCKER_HOST` and then the local
+/// socket when it's `None`. Supports `unix://` sockets and `tcp://`/`http://` hosts. Built with
+/// the `tls` feature, `https://` hosts and `tcp://` hosts with `DOCKER_TLS_VERIFY` set connect
+/// over TLS with `key.pem`, `cert.pem` and `ca.pem` from `DOCKER_CERT_PATH` (or `~/.docker`).
+pub fn connect_docker_host(host: Option<&str>) -> Result<Docker> {
+    let host = match host.filter(|host| !host.is_empty()) {
+        Some(host) => host,
+        None => return Docker::connect_with_defaults().context("Failed to connect to Docker"),
+    };
+    let docker = match host {
+        #[cfg(unix)]
+        host if host.starts_with("unix://") => {
+            Docker::connect_with_unix(host, DOCKER_TIMEOUT, API_DEFAULT_VERSION)
+        }
+        host if host.starts_with("tcp://") || host.starts_with("http://") => {
+            #[cfg(feature = "tls")]
+            if std::env::var("DOCKER_TLS_VERIFY").is_ok_and(|verify| !verify.is_empty()) {
+                return connect_docker_tls(host);
+            }
+            Docker::connect_with_http(host, DOCKER_TIMEOUT, API_DEFAULT_VERSION)
+        }
+        #[cfg(feature = "tls")]
+        host if host.starts_with("https://") => return connect_docker_tls(host),
+        _ => {
+            return Err(AnyhowError::msg(format!(
+                "Unsupported docker_host {}, expected a unix://, tcp:// or http:// URL{}",
+                host,
+                if cfg!(feature = "tls") {
+                    " or https://"
+                } else {
+                    ", https:// needs wpdev built with the tls feature"
+                }
+            )))
+        }
+    };
+    docker.with_context(|| format!("Failed to connect to Docker at {}", host))
+}
+
+#[cfg(feature = "tls")]
+fn connect_docker_tls(host: &str) -> Result<Docker> {
+    let cert_path = match std::env::var("DOCKER_CERT_PATH") {
+        Ok(path) if !path.is_empty() => std::path::PathBuf::from(path),
+        _ => dirs::home_dir()
+            .context("Failed to find home directory")?
+            .join(".docker"),
+    };
+    Docker::connect_with_ssl(
+        host,
+        &cert_path.join("key.pem"),
+        &cert_path.join("cert.pem"),
+        &cert_path.join("ca.pem"),
+        DOCKER_TIMEOUT,
+        API_DEFAULT_VERSION,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to connect to Docker at {} with the certificates in {:?}",
+            host, cert_path
+        )
+    })
+}
@@ -0,0 +1,66 @@
+use bollard::errors::Error as DockerError;
+use std::fmt;
+
+/// Errors callers handle differently from a generic failure. They're raised inside
+/// `anyhow::Error`s like everything else, so look for them in the error's chain, e.g. with
+/// `WpdevError::is_not_found`.
+#[derive(Debug)]
+pub enum WpdevError {
+    /// The named instance or container doesn't exist.
+    NotFound(String),
+    Docker(DockerError),
+    Io(std::io::Error),
+}
+
+impl WpdevError {
+    /// Whether `err` was caused by a missing instance or container, including a 404 from
+    /// the Docker API.
+    pub fn is_not_found(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| {
+            let docker = match cause.downcast_ref::<WpdevError>() {
+                Some(WpdevError::NotFound(_)) => return true,
+                Some(WpdevError::Docker(docker)) => Some(docker),
+                _ => cause.downcast_ref::<DockerError>(),
+            };
+            matches!(
+                docker,
+                Some(DockerError::DockerResponseServerError {
+                    status_code: 404,
+                    ..
+                })
+            )
+        })
+    }
+}
+
+impl fmt::Display for WpdevError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WpdevError::NotFound(name) => write!(f, "{} not found", name),
+            WpdevError::Docker(err) => write!(f, "Docker error: {}", err),
+            WpdevError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for WpdevError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WpdevError::NotFound(_) => None,
+            WpdevError::Docker(err) => Some(err),
+            WpdevError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<DockerError> for WpdevError {
+    fn from(err: DockerError) -> Self {
+        WpdevError::Docker(err)
+    }
+}
+
+impl From<std::io::Error> for WpdevError {
+    fn from(err: std::io::Error) -> Self {
+        WpdevError::Io(err)
+    }
+}
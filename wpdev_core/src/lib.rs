@@ -1,22 +1,69 @@
+use anyhow::{Context, Error as AnyhowError, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::docker::container::ResourceLimits;
 
 pub mod config;
 pub mod docker;
+pub mod error;
+pub mod metrics;
 pub mod utils;
 
+#[cfg(test)]
+mod test_support;
+
 pub const NETWORK_NAME: &str = "wp-network";
 pub const WORDPRESS_IMAGE: &str = "wordpress:latest";
 pub const NGINX_IMAGE: &str = "nginx:latest";
 pub const MYSQL_IMAGE: &str = "mysql:latest";
 pub const ADMINER_IMAGE: &str = "adminer:latest";
 pub const WORDPRESS_CLI_IMAGE: &str = "wordpress:cli";
+/// System directories `custom_root` may never point at, since purging instances deletes
+/// directories under it.
+const PROTECTED_DIRS: [&str; 14] = [
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/opt", "/proc", "/root", "/sbin",
+    "/sys", "/usr", "/var",
+];
+
+pub const DEMO_CONTENT_URL: &str =
+    "https://raw.githubusercontent.com/WordPress/theme-test-data/master/themeunittestdata.wordpress.xml";
+
+/// How the generated `wp-cli.local.php` reaches the instance database.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WpCliDbConnection {
+    /// Over TCP to the same host WordPress uses, which resolves inside the instance network
+    /// where WP-CLI runs.
+    #[default]
+    Tcp,
+    /// Through `mysql/mysqld.sock` in the instance directory. Only works when MySQL is
+    /// configured to put its socket there.
+    Socket,
+}
+
+/// Whether Docker restarts instance containers, see Docker's `--restart`. Containers stopped
+/// with `wpdev stop` stay stopped except with `Always`, which also starts them again when the
+/// Docker daemon restarts.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerRestartPolicy {
+    No,
+    /// Restarts a container that exits non-zero, up to 3 times.
+    OnFailure,
+    #[default]
+    UnlessStopped,
+    Always,
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct AppConfig {
     pub custom_root: Option<PathBuf>,
+    /// Docker daemon to use, e.g. `tcp://build-box:2375`. Falls back to `DOCKER_HOST`.
+    pub docker_host: Option<String>,
     pub docker_images: Vec<String>,
     pub log_level: String,
     pub enable_frontend: bool,
@@ -28,12 +75,38 @@ pub struct AppConfig {
     pub web_app_port: u16,
     pub api_ip: IpAddr,
     pub api_port: u16,
+    pub permalink_structure: String,
+    pub demo_content_source: String,
+    pub namespace: String,
+    pub tail_on_error: bool,
+    pub log_max_size: String,
+    pub log_max_files: u32,
+    pub readiness_path: String,
+    pub readiness_status_codes: Vec<u16>,
+    pub api_cache_ttl_ms: u64,
+    pub wpcli_db_connection: WpCliDbConnection,
+    pub enable_metrics: bool,
+    pub reconcile_interval_secs: u64,
+    pub reconcile_fix: bool,
+    pub use_random_passwords: bool,
+    pub port_range: Option<(u16, u16)>,
+    pub auto_pull_images: bool,
+    pub nginx_template_path: Option<PathBuf>,
+    pub nginx_adminer_listen_port: u16,
+    pub restart_policy: ContainerRestartPolicy,
+    pub startup_timeout_secs: u64,
+    pub container_uid: u32,
+    pub container_gid: u32,
+    pub manage_hosts_file: bool,
+    pub container_limits: BTreeMap<String, ResourceLimits>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
+        let (container_uid, container_gid) = utils::default_container_ids();
         AppConfig {
             custom_root: None,
+            docker_host: None,
             docker_images: vec![
                 WORDPRESS_IMAGE.to_string(),
                 NGINX_IMAGE.to_string(),
@@ -51,6 +124,95 @@ impl Default for AppConfig {
             api_ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             api_port: 8001,
             cli_theme: None,
+            permalink_structure: String::from("/%postname%/"),
+            demo_content_source: String::from(DEMO_CONTENT_URL),
+            namespace: String::from(NETWORK_NAME),
+            tail_on_error: false,
+            log_max_size: String::from("10m"),
+            log_max_files: 3,
+            readiness_path: String::from("/wp-login.php"),
+            readiness_status_codes: vec![200, 301, 302],
+            api_cache_ttl_ms: 2000,
+            wpcli_db_connection: WpCliDbConnection::default(),
+            enable_metrics: false,
+            reconcile_interval_secs: 0,
+            reconcile_fix: false,
+            use_random_passwords: true,
+            port_range: None,
+            auto_pull_images: true,
+            nginx_template_path: None,
+            nginx_adminer_listen_port: 8080,
+            restart_policy: ContainerRestartPolicy::default(),
+            startup_timeout_secs: 120,
+            container_uid,
+            container_gid,
+            manage_hosts_file: false,
+            container_limits: BTreeMap::from([
+                ("mysql".to_string(), ResourceLimits::new(1024, 1.0)),
+                ("mariadb".to_string(), ResourceLimits::new(1024, 1.0)),
+                ("wordpress".to_string(), ResourceLimits::new(512, 1.0)),
+                ("nginx".to_string(), ResourceLimits::new(128, 0.5)),
+                ("adminer".to_string(), ResourceLimits::new(128, 0.5)),
+                ("redis".to_string(), ResourceLimits::new(128, 0.5)),
+                ("mailpit".to_string(), ResourceLimits::new(64, 0.25)),
+            ]),
         }
     }
 }
+
+impl AppConfig {
+    /// Checks that `custom_root` is an absolute, non-system directory that can be created and
+    /// written to, so a bad value fails here instead of deep inside instance creation or purge.
+    pub fn validate(&self) -> Result<()> {
+        let custom_root = self
+            .custom_root
+            .as_ref()
+            .ok_or_else(|| AnyhowError::msg("custom_root is not set"))?;
+        if !custom_root.is_absolute() {
+            return Err(AnyhowError::msg(format!(
+                "custom_root {:?} must be an absolute path",
+                custom_root
+            )));
+        }
+
+        let home_dir = dirs::home_dir();
+        let protected = PROTECTED_DIRS
+            .iter()
+            .map(Path::new)
+            .chain(home_dir.as_deref())
+            .any(|dir| dir == custom_root.as_path());
+        if protected || custom_root.parent().is_none() {
+            return Err(AnyhowError::msg(format!(
+                "custom_root {:?} is a system directory, set it to a dedicated directory such as ~/wpdev/instances",
+                custom_root
+            )));
+        }
+
+        std::fs::create_dir_all(custom_root).context(format!(
+            "custom_root {:?} could not be created, check that its parent exists and is writable",
+            custom_root
+        ))?;
+        let probe = custom_root.join(".wpdev-write-check");
+        std::fs::write(&probe, b"")
+            .and_then(|_| std::fs::remove_file(&probe))
+            .context(format!("custom_root {:?} is not writable", custom_root))?;
+
+        Ok(())
+    }
+
+    /// The settings whose value differs from `AppConfig::default()`, keyed by setting name.
+    pub fn overrides(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let serde_json::Value::Object(config) = serde_json::to_value(self)? else {
+            return Err(AnyhowError::msg("Config didn't serialize to an object"));
+        };
+        let defaults = serde_json::to_value(AppConfig::default())?;
+        Ok(config
+            .into_iter()
+            .filter(|(key, value)| defaults.get(key) != Some(value))
+            .collect())
+    }
+
+    pub fn to_toml(value: &impl Serialize) -> Result<String> {
+        toml::to_string(value).context("Failed to serialize config as TOML")
+    }
+}
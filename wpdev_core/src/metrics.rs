@@ -0,0 +1,173 @@
+use log::info;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::docker::instance::Instance;
+
+/// Upper bounds, in seconds, of the operation duration histogram buckets.
+const DURATION_BUCKETS: [f64; 9] = [0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Instance operations that are counted and timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    Create,
+    Start,
+    Stop,
+    Restart,
+    Delete,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Start => "start",
+            Operation::Stop => "stop",
+            Operation::Restart => "restart",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+/// The registry wpdev's metrics are registered in, along with the metrics themselves.
+struct Metrics {
+    registry: Registry,
+    operations: IntCounterVec,
+    durations: HistogramVec,
+    instances: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let operations = IntCounterVec::new(
+            Opts::new("wpdev_operations_total", "Instance operations by result."),
+            &["operation", "result"],
+        )
+        .expect("valid operations counter");
+        let durations = HistogramVec::new(
+            HistogramOpts::new(
+                "wpdev_operation_duration_seconds",
+                "Duration of instance operations.",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["operation"],
+        )
+        .expect("valid duration histogram");
+        let instances = IntGaugeVec::new(
+            Opts::new("wpdev_instances", "Current instances by status."),
+            &["status"],
+        )
+        .expect("valid instances gauge");
+
+        let registry = Registry::new();
+        for collector in [
+            Box::new(operations.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(durations.clone()),
+            Box::new(instances.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metrics are only registered once");
+        }
+        Metrics {
+            registry,
+            operations,
+            durations,
+            instances,
+        }
+    }
+
+    fn get() -> &'static Metrics {
+        METRICS.get_or_init(Metrics::new)
+    }
+}
+
+/// The registry holding the operation and instance metrics, for the API to serve.
+pub fn registry() -> &'static Registry {
+    &Metrics::get().registry
+}
+
+/// Sets the instance gauge to the number of `instances` in each status. Statuses no instance
+/// has anymore are dropped.
+pub fn record_instances(instances: &[Instance]) {
+    let gauge = &Metrics::get().instances;
+    gauge.reset();
+    for instance in instances {
+        gauge
+            .with_label_values(&[&format!("{:?}", instance.status)])
+            .inc();
+    }
+}
+
+/// Times an operation until it's dropped. A timer dropped without calling `succeeded`, e.g.
+/// by an early return with `?`, is counted as a failure.
+pub struct OperationTimer {
+    operation: Operation,
+    started: Instant,
+    succeeded: bool,
+}
+
+impl OperationTimer {
+    pub fn start(operation: Operation) -> Self {
+        OperationTimer {
+            operation,
+            started: Instant::now(),
+            succeeded: false,
+        }
+    }
+
+    pub fn succeeded(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for OperationTimer {
+    fn drop(&mut self) {
+        let seconds = self.started.elapsed().as_secs_f64();
+        info!(
+            "{} took {:.3}s (succeeded: {})",
+            self.operation.as_str(),
+            seconds,
+            self.succeeded
+        );
+        let metrics = Metrics::get();
+        let result = if self.succeeded { "success" } else { "failure" };
+        metrics
+            .operations
+            .with_label_values(&[self.operation.as_str(), result])
+            .inc();
+        metrics
+            .durations
+            .with_label_values(&[self.operation.as_str()])
+            .observe(seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_timers_count_as_failures() {
+        let operations = &Metrics::get().operations;
+        let count = |result: &str| {
+            operations
+                .with_label_values(&[Operation::Restart.as_str(), result])
+                .get()
+        };
+        let (succeeded, failed) = (count("success"), count("failure"));
+
+        OperationTimer::start(Operation::Restart).succeeded();
+        drop(OperationTimer::start(Operation::Restart));
+
+        assert_eq!(count("success"), succeeded + 1);
+        assert_eq!(count("failure"), failed + 1);
+        let families = registry().gather();
+        assert!(families
+            .iter()
+            .any(|family| family.get_name() == "wpdev_operation_duration_seconds"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Helpers for the unit tests: a throwaway config directory and a fake Docker daemon.
+
+use bollard::{Docker, API_DEFAULT_VERSION};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::MutexGuard;
+
+/// The namespace `TestEnv` configures.
+pub(crate) const NAMESPACE: &str = "test";
+
+/// Tests that point the config directory somewhere else hold this, since the environment is
+/// shared by every test in the process.
+static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// A config directory with a `config.toml` and an empty `custom_root` in a temp dir, used by
+/// everything reading the config until it's dropped.
+pub(crate) struct TestEnv {
+    pub dir: TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestEnv {
+    /// Writes `config.toml` with the test namespace, `custom_root` and `extra` settings.
+    pub async fn new(extra: &str) -> Self {
+        let lock = ENV_LOCK.lock().await;
+        let dir = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", dir.path());
+        std::env::set_var("XDG_CONFIG_HOME", dir.path().join("config"));
+        std::env::remove_var(crate::config::PROFILE_ENV);
+        let env = TestEnv { dir, _lock: lock };
+        std::fs::create_dir_all(env.config_dir()).unwrap();
+        env.write_config(
+            "config.toml",
+            &format!(
+                "namespace = {:?}\ncustom_root = {:?}\nauto_pull_images = false\n{}",
+                NAMESPACE,
+                env.instances_dir(),
+                extra
+            ),
+        );
+        env
+    }
+
+    /// `~/.config/wpdev`.
+    pub fn config_dir(&self) -> PathBuf {
+        self.dir.path().join("config").join("wpdev")
+    }
+
+    /// The `custom_root` instances are created in.
+    pub fn instances_dir(&self) -> PathBuf {
+        self.dir.path().join("instances")
+    }
+
+    pub fn write_config(&self, file_name: &str, contents: &str) {
+        std::fs::write(self.config_dir().join(file_name), contents).unwrap();
+    }
+}
+
+/// A request received by `MockDocker`, with the API version prefix stripped from the path.
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+impl Request {
+    pub fn is(&self, method: &str, path_prefix: &str) -> bool {
+        self.method == method && self.path.starts_with(path_prefix)
+    }
+}
+
+type Handler = dyn Fn(&Request) -> (u16, String) + Send + Sync;
+
+/// An HTTP server standing in for the Docker daemon. Every request is recorded and answered
+/// by the handler it was started with.
+pub(crate) struct MockDocker {
+    port: u16,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockDocker {
+    pub async fn start(
+        handler: impl Fn(&Request) -> (u16, String) + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(request) = read_request(&mut reader).await {
+                        recorded.lock().unwrap().push(request.clone());
+                        let (status, body) = handler(&request);
+                        let response = format!(
+                            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        if writer.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        MockDocker { port, requests }
+    }
+
+    /// The `docker_host` setting pointing at the daemon.
+    pub fn config(&self) -> String {
+        format!("docker_host = \"tcp://127.0.0.1:{}\"\n", self.port)
+    }
+
+    pub fn client(&self) -> Docker {
+        Docker::connect_with_http(
+            &format!("tcp://127.0.0.1:{}", self.port),
+            10,
+            API_DEFAULT_VERSION,
+        )
+        .unwrap()
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Option<Request> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await.ok()? == 0 {
+        return None;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?;
+    // Strip the `/v1.44` prefix bollard adds.
+    let path = match path.strip_prefix("/v") {
+        Some(versioned) => versioned
+            .find('/')
+            .map_or(path, |slash| &versioned[slash..]),
+        None => path,
+    }
+    .to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(Request { method, path })
+}
+
+/// Answers the requests creating and removing an instance the way an empty daemon with every
+/// image pulled would. Containers get their name as ID.
+pub(crate) fn daemon(request: &Request) -> (u16, String) {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["images", "json"]) => (
+            200,
+            json!([{
+                "Id": "sha256:mock",
+                "ParentId": "",
+                "RepoTags": [
+                    "wordpress:latest",
+                    "nginx:latest",
+                    "mysql:latest",
+                    "mariadb:latest",
+                    "adminer:latest",
+                    "redis:latest",
+                    "axllent/mailpit:latest",
+                ],
+                "RepoDigests": [],
+                "Created": 0,
+                "Size": 0,
+                "SharedSize": 0,
+                "Labels": {},
+                "Containers": 0,
+            }])
+            .to_string(),
+        ),
+        ("GET", ["networks"]) => (200, "[]".to_string()),
+        ("POST", ["networks", "create"]) => (201, json!({"Id": "network"}).to_string()),
+        ("DELETE", ["networks", _]) => (204, String::new()),
+        ("POST", ["containers", "create"]) => {
+            let name = request
+                .path
+                .split_once("name=")
+                .map(|(_, name)| name.split('&').next().unwrap_or_default())
+                .unwrap_or_default();
+            (201, json!({"Id": name, "Warnings": []}).to_string())
+        }
+        ("GET", ["containers", id, "json"]) => (
+            200,
+            json!({
+                "Id": id,
+                "Name": format!("/{}", id),
+                "State": {"Status": "created"},
+                "Config": {"Labels": {}, "Image": ""},
+            })
+            .to_string(),
+        ),
+        ("GET", ["containers", "json"]) => (200, "[]".to_string()),
+        ("DELETE", ["containers", _]) => (204, String::new()),
+        _ => (
+            404,
+            json!({"message": format!("{} {} isn't mocked", request.method, request.path)})
+                .to_string(),
+        ),
+    }
+}
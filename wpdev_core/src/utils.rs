@@ -1,21 +1,337 @@
-use anyhow::{Context, Result};
-use log::info;
+use anyhow::{Context, Error as AnyhowError, Result};
+use bollard::errors::Error as BollardError;
+use bollard::models::CreateImageInfo;
+use chrono::{TimeDelta, Utc};
+use log::{info, warn};
+use serde_json::Value as Json;
 use spinners::{Spinner, Spinners};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
-use std::io::{self, Write};
-use std::net::{SocketAddr, TcpListener};
+use std::io::{self, IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
 use std::{thread, time::Duration};
 
+use crate::config;
 use crate::docker::container::ContainerImage;
-use std::path::PathBuf;
+use crate::docker::instance::{Instance, InstanceData};
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
+const FREE_PORT_ATTEMPTS: usize = 100;
+const TABLE_UUID_WIDTH: usize = 24;
+
+/// Labels wpdev sets on every container itself, which user labels may not override.
+pub(crate) const RESERVED_LABELS: [&str; 4] = ["instance", "nginx_port", "adminer_port", "image"];
+
+/// UID and GID WordPress and the database run as by default: those of the current process, so
+/// the bind-mounted directories stay owned by the user running wpdev. Falls back to
+/// `1000:1000` for root, where the database would refuse to start, and off Unix.
+pub(crate) fn default_container_ids() -> (u32, u32) {
+    #[cfg(unix)]
+    {
+        // SAFETY: getuid and getgid can't fail and have no preconditions.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        if uid != 0 {
+            return (uid, gid);
+        }
+    }
+    (1000, 1000)
+}
+
+/// Parses a `key=value` label.
+pub fn parse_label(label: &str) -> Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(AnyhowError::msg(format!(
+            "Invalid label {}, expected key=value",
+            label
+        ))),
+    }
+}
+
+/// Builds a Docker `label` filter matching containers that have every label in `labels`.
+pub(crate) fn label_filters(labels: &HashMap<String, String>) -> HashMap<String, Vec<String>> {
+    let mut selectors: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    selectors.sort();
+    HashMap::from([("label".to_string(), selectors)])
+}
+
+/// Total size in bytes of the files under `path`. Anything that can't be read is skipped.
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn port_or_unknown(port: u32) -> String {
+    match port {
+        0 => "unknown".to_string(),
+        port => port.to_string(),
+    }
+}
+
+/// Writes `instances` as CSV with a header row, one row per instance as it's processed.
+/// `created_at` is the Unix time the instance was created, or for instances that didn't record
+/// it the time their directory was created, and `disk_bytes` the size
+/// of the instance directory. `mysql_port` is empty unless the database port is exposed.
+pub async fn to_csv<W: Write>(instances: &[Instance], writer: W) -> Result<()> {
+    let instance_dir = config::get_instance_dir().await?;
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    csv_writer
+        .write_record([
+            "uuid",
+            "name",
+            "status",
+            "nginx_port",
+            "adminer_port",
+            "created_at",
+            "disk_bytes",
+            "mysql_port",
+        ])
+        .context("Failed to write CSV header")?;
+    for instance in instances {
+        let instance_path = instance_dir.join(&instance.uuid);
+        let created_at = match instance
+            .wordpress_data
+            .as_ref()
+            .and_then(|data| data.created_at)
+        {
+            Some(created_at) => created_at.timestamp().to_string(),
+            None => std::fs::metadata(&instance_path)
+                .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()))
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs().to_string())
+                .unwrap_or_default(),
+        };
+        let name = instance
+            .wordpress_data
+            .as_ref()
+            .map(InstanceData::display_name)
+            .unwrap_or_default();
+        let mysql_port = instance
+            .wordpress_data
+            .as_ref()
+            .and_then(|data| data.mysql_port)
+            .map(|port| port.to_string())
+            .unwrap_or_default();
+        csv_writer
+            .write_record([
+                instance.uuid.clone(),
+                name,
+                format!("{:?}", instance.status),
+                port_or_unknown(instance.nginx_port),
+                port_or_unknown(instance.adminer_port),
+                created_at,
+                dir_size(&instance_path).to_string(),
+                mysql_port,
+            ])
+            .context(format!("Failed to write CSV row for {}", instance.uuid))?;
+    }
+    csv_writer.flush().context("Failed to write CSV")?;
+    Ok(())
+}
+
+/// Writes `instances` as a table padded to the widest value of each column. UUIDs longer than
+/// `TABLE_UUID_WIDTH` are truncated, and each container is listed on its own line as
+/// `image: status` in the last column.
+pub fn print_instances_table<W: Write>(instances: &[Instance], mut writer: W) -> Result<()> {
+    let header = ["UUID", "NAME", "STATUS", "AGE", "SITE", "ADMINER"].map(str::to_string);
+    let now = Utc::now();
+    let rows: Vec<([String; 6], Vec<String>)> = instances
+        .iter()
+        .map(|instance| {
+            let data = instance.wordpress_data.as_ref();
+            let uuid = if instance.uuid.chars().count() > TABLE_UUID_WIDTH {
+                let truncated: String = instance.uuid.chars().take(TABLE_UUID_WIDTH - 1).collect();
+                format!("{}…", truncated)
+            } else {
+                instance.uuid.clone()
+            };
+            let columns = [
+                uuid,
+                data.map(InstanceData::display_name).unwrap_or_default(),
+                format!("{:?}", instance.status),
+                data.and_then(|data| data.created_at)
+                    .map(|created_at| format_age(now - created_at))
+                    .unwrap_or_else(|| "-".to_string()),
+                data.map(|data| data.site_url.clone())
+                    .unwrap_or_else(|| port_or_unknown(instance.nginx_port)),
+                data.map(|data| data.adminer_url.clone())
+                    .unwrap_or_else(|| port_or_unknown(instance.adminer_port)),
+            ];
+            let containers = instance
+                .containers
+                .iter()
+                .map(|container| {
+                    format!(
+                        "{}: {}",
+                        container.container_image.to_string(),
+                        container.container_status.to_string()
+                    )
+                })
+                .collect();
+            (columns, containers)
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for (columns, _) in &rows {
+        for (width, value) in widths.iter_mut().zip(columns) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let pad = |columns: &[String; 6]| {
+        columns
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    let blank = pad(&Default::default());
+    writeln!(writer, "{}  CONTAINERS", pad(&header)).context("Failed to write table")?;
+    for (columns, containers) in &rows {
+        let mut containers = containers.iter();
+        let first = containers.next().map(String::as_str).unwrap_or("none");
+        writeln!(writer, "{}  {}", pad(columns), first).context("Failed to write table")?;
+        for container in containers {
+            writeln!(writer, "{}  {}", blank, container).context("Failed to write table")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `value` as a table padded to the widest value of each column: an array of objects
+/// as a row per element with a column per field, and anything else as a row per field or
+/// element. Nested arrays and objects are written as compact JSON.
+pub fn print_table<W: Write>(value: &Json, mut writer: W) -> Result<()> {
+    let (header, rows): (Vec<String>, Vec<Vec<String>>) = match value {
+        Json::Array(items) if items.iter().all(Json::is_object) => {
+            let mut header: Vec<String> = Vec::new();
+            for key in items
+                .iter()
+                .filter_map(Json::as_object)
+                .flat_map(|item| item.keys())
+            {
+                if !header.contains(key) {
+                    header.push(key.clone());
+                }
+            }
+            let rows = items
+                .iter()
+                .map(|item| header.iter().map(|key| table_cell(item.get(key))).collect())
+                .collect();
+            (header, rows)
+        }
+        Json::Array(items) => (
+            vec!["value".to_string()],
+            items
+                .iter()
+                .map(|item| vec![table_cell(Some(item))])
+                .collect(),
+        ),
+        Json::Object(fields) => (
+            vec!["field".to_string(), "value".to_string()],
+            fields
+                .iter()
+                .map(|(key, value)| vec![key.clone(), table_cell(Some(value))])
+                .collect(),
+        ),
+        value => (
+            vec!["value".to_string()],
+            vec![vec![table_cell(Some(value))]],
+        ),
+    };
+
+    let header: Vec<String> = header.iter().map(|column| column.to_uppercase()).collect();
+    let mut widths: Vec<usize> = header.iter().map(|column| column.chars().count()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let pad = |row: &[String]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    writeln!(writer, "{}", pad(&header)).context("Failed to write table")?;
+    for row in &rows {
+        writeln!(writer, "{}", pad(row)).context("Failed to write table")?;
+    }
+    Ok(())
+}
+
+/// A value in a `print_table` cell: strings without quotes and `-` for missing values.
+fn table_cell(value: Option<&Json>) -> String {
+    match value {
+        None | Some(Json::Null) => "-".to_string(),
+        Some(Json::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// An age like `45s`, `12m`, `5h` or `3d`, in its largest whole unit.
+fn format_age(age: TimeDelta) -> String {
+    if age.num_days() > 0 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h", age.num_hours())
+    } else if age.num_minutes() > 0 {
+        format!("{}m", age.num_minutes())
+    } else {
+        format!("{}s", age.num_seconds().max(0))
+    }
+}
+
+/// Whether stdout is a terminal. Spinners and colours are only drawn when it is, in CI or when
+/// output is piped to a file their control characters would end up in the output.
+pub fn is_interactive() -> bool {
+    io::stdout().is_terminal()
+}
+
+/// Runs `future` behind a spinner, or prints a plain `message...` line when stdout isn't a
+/// terminal.
 pub async fn with_spinner<F, T, E>(future: F, message: &str) -> Result<T, E>
 where
     F: Future<Output = Result<T, E>>,
 {
-    let _ = io::stdout().flush();
+    spin_to(&mut io::stdout(), is_interactive(), future, message).await
+}
+
+/// `with_spinner` writing the plain line to `out`. The spinner itself always draws on stdout.
+async fn spin_to<W, F, T, E>(
+    out: &mut W,
+    interactive: bool,
+    future: F,
+    message: &str,
+) -> Result<T, E>
+where
+    W: Write,
+    F: Future<Output = Result<T, E>>,
+{
+    if !interactive {
+        let _ = writeln!(out, "{}...", message);
+        return future.await;
+    }
+    let _ = out.flush();
     let mut sp = Spinner::new(Spinners::Dots9, message.into());
     let result = future.await;
     sp.stop();
@@ -25,6 +341,211 @@ where
     result
 }
 
+const PULL_BAR_WIDTH: usize = 30;
+
+/// Download progress of one image layer.
+struct LayerProgress {
+    id: String,
+    status: String,
+    current: u64,
+    total: u64,
+    done: bool,
+}
+
+/// Draws the progress of an image pull from the `create_image` stream: a bar per layer and
+/// one for the whole image. It's written to stderr, keeping stdout to the command's output, and
+/// when stderr isn't a terminal only the start and end are printed.
+pub(crate) struct PullProgress {
+    image: String,
+    layers: Vec<LayerProgress>,
+    drawn_lines: usize,
+    interactive: bool,
+}
+
+impl PullProgress {
+    pub(crate) fn new(image: &str) -> Self {
+        let interactive = io::stderr().is_terminal();
+        if !interactive {
+            eprintln!("Pulling {}...", image);
+        }
+        PullProgress {
+            image: image.to_string(),
+            layers: Vec::new(),
+            drawn_lines: 0,
+            interactive,
+        }
+    }
+
+    /// Records a progress message of the pull and redraws the bars.
+    pub(crate) fn update(&mut self, info: &CreateImageInfo) {
+        let (Some(id), Some(status)) = (&info.id, &info.status) else {
+            return;
+        };
+        // "Pulling from library/wordpress" carries the tag as its id, it isn't a layer.
+        if status.starts_with("Pulling from") {
+            return;
+        }
+        let index = match self.layers.iter().position(|layer| &layer.id == id) {
+            Some(index) => index,
+            None => {
+                self.layers.push(LayerProgress {
+                    id: id.clone(),
+                    status: String::new(),
+                    current: 0,
+                    total: 0,
+                    done: false,
+                });
+                self.layers.len() - 1
+            }
+        };
+        let layer = &mut self.layers[index];
+        layer.status = status.clone();
+        match status.as_str() {
+            "Downloading" => {
+                if let Some(detail) = &info.progress_detail {
+                    layer.current = detail.current.unwrap_or(0).max(0) as u64;
+                    layer.total = detail.total.unwrap_or(0).max(0) as u64;
+                }
+            }
+            "Download complete" | "Pull complete" | "Already exists" => {
+                layer.current = layer.total;
+                layer.done = true;
+            }
+            _ => {}
+        }
+        self.draw();
+    }
+
+    /// Draws the bars a final time and prints whether the pull succeeded.
+    pub(crate) fn finish(mut self, success: bool) {
+        if success {
+            for layer in &mut self.layers {
+                layer.current = layer.total;
+                layer.done = true;
+            }
+        }
+        self.draw();
+        let outcome = if success { "Pulled" } else { "Failed to pull" };
+        eprintln!("{} {}", outcome, self.image);
+    }
+
+    fn draw(&mut self) {
+        if !self.interactive {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        if self.drawn_lines > 0 {
+            let _ = write!(stderr, "\x1b[{}A", self.drawn_lines);
+        }
+        for layer in &self.layers {
+            let _ = writeln!(
+                stderr,
+                "\x1b[2K  {}: {:<18} {}",
+                layer.id,
+                layer.status,
+                progress_bar(layer.current, layer.total, layer.done)
+            );
+        }
+        let done = self.layers.iter().filter(|layer| layer.done).count();
+        let current = self.layers.iter().map(|layer| layer.current).sum();
+        let total = self.layers.iter().map(|layer| layer.total).sum();
+        let _ = writeln!(
+            stderr,
+            "\x1b[2KPulling {} {} {}/{} layers",
+            self.image,
+            progress_bar(
+                current,
+                total,
+                !self.layers.is_empty() && done == self.layers.len()
+            ),
+            done,
+            self.layers.len()
+        );
+        let _ = stderr.flush();
+        self.drawn_lines = self.layers.len() + 1;
+    }
+}
+
+/// A `[=====>    ]  52% 12.3MiB/23.6MiB` bar. Layers that are done before their size is known,
+/// e.g. ones that already exist locally, are drawn full.
+fn progress_bar(current: u64, total: u64, done: bool) -> String {
+    let ratio = match (done, total) {
+        (true, _) => 1.0,
+        (false, 0) => 0.0,
+        (false, total) => (current as f64 / total as f64).min(1.0),
+    };
+    let filled = (ratio * PULL_BAR_WIDTH as f64) as usize;
+    let bar = if filled >= PULL_BAR_WIDTH {
+        "=".repeat(PULL_BAR_WIDTH)
+    } else {
+        format!(
+            "{}>{}",
+            "=".repeat(filled),
+            " ".repeat(PULL_BAR_WIDTH - filled - 1)
+        )
+    };
+    let size = if total > 0 {
+        format!(" {}/{}", format_size(current), format_size(total))
+    } else {
+        String::new()
+    };
+    format!("[{}] {:>3.0}%{}", bar, ratio * 100.0, size)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+/// Retries a Docker operation when it fails with a transient error.
+///
+/// The operation is attempted up to `attempts` times, doubling `backoff` between each
+/// attempt. Client errors (4xx) are returned immediately since retrying won't help.
+pub async fn retry<F, Fut, T>(
+    mut op: F,
+    attempts: u32,
+    backoff: Duration,
+) -> Result<T, BollardError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, BollardError>>,
+{
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < attempts && is_transient(&err) => {
+                warn!(
+                    "Transient Docker error on attempt {}/{}: {}",
+                    attempt, attempts, err
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn is_transient(err: &BollardError) -> bool {
+    match err {
+        BollardError::DockerResponseServerError { status_code, .. } => *status_code >= 500,
+        BollardError::RequestTimeoutError
+        | BollardError::IOError { .. }
+        | BollardError::HyperResponseError { .. }
+        | BollardError::HyperLegacyError { .. } => true,
+        _ => false,
+    }
+}
+
 pub(crate) async fn create_path(path: &PathBuf) -> Result<&PathBuf> {
     info!("Creating directory at path: {}", path.to_string_lossy());
     fs::create_dir_all(&path).await.context(format!(
@@ -34,15 +555,172 @@ pub(crate) async fn create_path(path: &PathBuf) -> Result<&PathBuf> {
     Ok(path)
 }
 
-pub(crate) async fn find_free_port() -> Result<u32> {
-    info!("Finding a free port");
-    let listener = TcpListener::bind("127.0.0.1:0").context("Failed to bind to port")?;
-    let socket_addr: SocketAddr = listener
-        .local_addr()
-        .context("Failed to get local address")?;
-    let port = socket_addr.port();
+/// Recursively copies the contents of `source` into `destination`, creating directories as
+/// needed and overwriting files that already exist.
+pub(crate) async fn copy_dir(source: &Path, destination: &Path) -> Result<()> {
+    info!("Copying {:?} to {:?}", source, destination);
+    let mut pending = vec![(source.to_path_buf(), destination.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        fs::create_dir_all(&to)
+            .await
+            .context(format!("Failed to create directory {:?}", to))?;
+        let mut entries = fs::read_dir(&from)
+            .await
+            .context(format!("Failed to read directory {:?}", from))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Failed to read directory {:?}", from))?
+        {
+            let target = to.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                pending.push((entry.path(), target));
+            } else {
+                fs::copy(entry.path(), &target)
+                    .await
+                    .context(format!("Failed to copy {:?}", entry.path()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Picks `count` distinct free ports, from `range` if set or else from the OS, skipping
+/// `reserved` ports (e.g. those recorded by stopped instances). Every listener is held open
+/// until all ports are chosen, so the same port can't be picked twice. Ports are bound on all
+/// interfaces, like Docker publishes them.
+pub(crate) fn find_free_ports(
+    count: usize,
+    range: Option<(u16, u16)>,
+    reserved: &HashSet<u32>,
+) -> Result<Vec<u32>> {
+    info!("Finding {} free ports", count);
+    let mut listeners = Vec::with_capacity(count);
+    let mut ports = Vec::with_capacity(count);
+    let mut candidates = range.map(|(start, end)| start..=end);
+    // Binding port 0 never runs out of candidates, so cap the attempts instead.
+    let mut attempts = 0;
+    while ports.len() < count {
+        let listener = match candidates.as_mut() {
+            Some(candidates) => match candidates.next() {
+                Some(port) => TcpListener::bind(("0.0.0.0", port)).ok(),
+                None => {
+                    let (start, end) = range.unwrap_or_default();
+                    return Err(AnyhowError::msg(format!(
+                        "Not enough free ports in port_range {}-{}",
+                        start, end
+                    )));
+                }
+            },
+            None => {
+                attempts += 1;
+                if attempts > FREE_PORT_ATTEMPTS {
+                    return Err(AnyhowError::msg("Failed to find a free port"));
+                }
+                Some(TcpListener::bind("0.0.0.0:0").context("Failed to bind to port")?)
+            }
+        };
+        let Some(listener) = listener else {
+            continue;
+        };
+        let socket_addr: SocketAddr = listener
+            .local_addr()
+            .context("Failed to get local address")?;
+        let port = u32::from(socket_addr.port());
+        if reserved.contains(&port) {
+            continue;
+        }
+        ports.push(port);
+        listeners.push(listener);
+    }
+
+    Ok(ports)
+}
+
+/// Fails with an actionable message if `ip:port` can't be bound, naming the config key that
+/// sets the port so a server can report it before trying to start.
+pub fn ensure_port_available(ip: IpAddr, port: u16, config_key: &str) -> Result<()> {
+    info!("Checking that {}:{} is available", ip, port);
+    match TcpListener::bind((ip, port)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(AnyhowError::msg(format!(
+            "Cannot bind {}:{} ({}). Stop whatever is using the port or change `{}` in config.toml",
+            ip, port, e, config_key
+        ))),
+    }
+}
+
+/// Checks whether a host port is currently bound by attempting to bind it ourselves.
+pub(crate) fn port_in_use(port: u32) -> bool {
+    let Ok(port) = u16::try_from(port) else {
+        return false;
+    };
+    TcpListener::bind(("0.0.0.0", port)).is_err()
+}
+
+/// Returns `port` if it can be bound, for ports pinned by the user rather than picked by
+/// `find_free_ports`.
+pub(crate) fn ensure_pinned_port_free(port: u32) -> Result<u32> {
+    if port_in_use(port) {
+        return Err(AnyhowError::msg(format!(
+            "Pinned port {} is already in use",
+            port
+        )));
+    }
+    Ok(port)
+}
+
+/// Sends a plain `GET` for `path` to a local port and returns the response status code.
+pub(crate) async fn http_status(port: u32, path: &str) -> Result<u16> {
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port as u16))
+        .await
+        .context(format!("Failed to connect to port {}", port))?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost:{}\r\nConnection: close\r\n\r\n",
+        path, port
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("Failed to send request")?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .await
+        .context("Failed to read response")?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| AnyhowError::msg(format!("Invalid HTTP response: {}", status_line.trim())))
+}
+
+/// Removes a directory and confirms it's gone, retrying while files are still held open, e.g.
+/// by a container that's still shutting down or a Windows file lock.
+pub(crate) async fn remove_dir_verified(path: &Path, attempts: u32, delay: Duration) -> Result<()> {
+    let mut last_error = None;
+    for attempt in 1..=attempts {
+        if let Err(e) = fs::remove_dir_all(path).await {
+            warn!(
+                "Attempt {}/{} to remove {:?} failed: {}",
+                attempt, attempts, path, e
+            );
+            last_error = Some(e);
+        }
+        if !path.exists() {
+            info!("Directory removed: {:?}", path);
+            return Ok(());
+        }
+        tokio::time::sleep(delay).await;
+    }
 
-    Ok(u32::from(port))
+    Err(AnyhowError::msg(format!(
+        "Directory {:?} still exists after {} removal attempts{}, remove it manually",
+        path,
+        attempts,
+        last_error.map_or(String::new(), |e| format!(" ({})", e))
+    )))
 }
 
 pub(crate) fn create_labels(
@@ -54,3 +732,208 @@ pub(crate) fn create_labels(
     new_labels.insert("image".to_string(), image.to_string());
     new_labels
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn spinner_prints_a_plain_line_off_a_terminal() {
+        let mut out = Vec::new();
+        let result: Result<u32, ()> =
+            spin_to(&mut out, false, async { Ok(3) }, "Starting instance").await;
+        assert_eq!(result, Ok(3));
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "Starting instance...\n");
+        assert!(!out.contains('\x1b'));
+    }
+
+    fn server_error(status_code: u16) -> BollardError {
+        BollardError::DockerResponseServerError {
+            status_code,
+            message: "failed".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_retries_transient_errors() {
+        let calls = AtomicU32::new(0);
+        let result = retry(
+            || {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    match call {
+                        1 | 2 => Err(server_error(503)),
+                        _ => Ok(call),
+                    }
+                }
+            },
+            5,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_on_other_errors() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = retry(
+            || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(server_error(404)) }
+            },
+            5,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_stops_after_the_last_attempt() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = retry(
+            || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(server_error(500)) }
+            },
+            3,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn is_transient_only_matches_server_and_connection_errors() {
+        assert!(is_transient(&server_error(500)));
+        assert!(is_transient(&BollardError::RequestTimeoutError));
+        assert!(!is_transient(&server_error(404)));
+        assert!(!is_transient(&server_error(409)));
+    }
+
+    #[test]
+    fn print_table_writes_a_row_per_element() {
+        let statuses = serde_json::json!([
+            {"uuid": "wp-a", "status": "Running"},
+            {"uuid": "wp-long-b", "status": "Stopped", "error": null},
+        ]);
+        let mut table = Vec::new();
+        print_table(&statuses, &mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "STATUS   UUID       ERROR\n\
+             Running  wp-a       -\n\
+             Stopped  wp-long-b  -\n"
+        );
+
+        let mut table = Vec::new();
+        print_table(&serde_json::json!({"ports": [8000, 8001]}), &mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "FIELD  VALUE\nports  [8000,8001]\n"
+        );
+    }
+
+    #[test]
+    fn parse_label_splits_on_the_first_equals_sign() {
+        assert_eq!(
+            parse_label("client=acme").unwrap(),
+            ("client".to_string(), "acme".to_string())
+        );
+        assert_eq!(
+            parse_label("query=a=b").unwrap(),
+            ("query".to_string(), "a=b".to_string())
+        );
+        assert_eq!(
+            parse_label("empty=").unwrap(),
+            ("empty".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn parse_label_rejects_malformed_labels() {
+        for label in ["client", "=acme", ""] {
+            assert!(parse_label(label).is_err(), "{:?} was accepted", label);
+        }
+    }
+
+    #[test]
+    fn label_filters_match_every_label() {
+        let labels = HashMap::from([
+            ("team".to_string(), "web".to_string()),
+            ("client".to_string(), "acme".to_string()),
+        ]);
+        assert_eq!(
+            label_filters(&labels),
+            HashMap::from([(
+                "label".to_string(),
+                vec!["client=acme".to_string(), "team=web".to_string()]
+            )])
+        );
+        assert_eq!(
+            label_filters(&HashMap::new()),
+            HashMap::from([("label".to_string(), Vec::new())])
+        );
+    }
+
+    /// A directory with a file in it that can't be removed while the directory is read-only.
+    #[cfg(unix)]
+    fn locked_dir(root: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = root.join("instance");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("wp-config.php"), "<?php").unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    fn unlock_dir(dir: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn remove_dir_verified_retries_until_the_dir_is_unlocked() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = locked_dir(root.path());
+        let unlock = {
+            let dir = dir.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                // Root removes it on the first attempt, locked or not.
+                let _ = unlock_dir(&dir);
+            })
+        };
+
+        remove_dir_verified(&dir, 20, Duration::from_millis(20))
+            .await
+            .unwrap();
+        unlock.await.unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn remove_dir_verified_gives_up_while_locked() {
+        // Permissions don't stop root from removing the directory.
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let root = tempfile::tempdir().unwrap();
+        let dir = locked_dir(root.path());
+
+        let result = remove_dir_verified(&dir, 3, Duration::from_millis(1)).await;
+        unlock_dir(&dir).unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("after 3 removal attempts"), "{}", error);
+        assert!(dir.exists());
+    }
+}
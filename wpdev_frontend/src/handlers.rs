@@ -1,308 +1,284 @@
-use actix_web::{delete, get, post, web, HttpResponse, Result};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, ResponseError};
 use bollard::Docker;
 use rust_embed::RustEmbed;
 use serde_json::json;
+use std::fmt;
 use tera::{Context, Tera};
 use uuid::Uuid;
 
+use wpdev_core::config;
 use wpdev_core::docker::container::ContainerEnvVars;
 use wpdev_core::docker::instance::Instance;
+use wpdev_core::error::WpdevError;
 
 #[derive(RustEmbed)]
 #[folder = "templates/"]
 struct TemplateAssets;
 
+/// A failed request. It's answered with `{"status": "error", "message": ...}` instead of an
+/// HTML fragment, and an `HX-Trigger` header raising a `showToast` event the page shows the
+/// message with, since htmx doesn't swap in error responses.
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let status = if WpdevError::is_not_found(&err) {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        ApiError {
+            status,
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+impl From<tera::Error> for ApiError {
+    fn from(err: tera::Error) -> Self {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("Failed to render template: {}", err),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let trigger = json!({
+            "showToast": {
+                "level": "error",
+                "message": self.message,
+            }
+        });
+        HttpResponse::build(self.status)
+            .insert_header(("HX-Trigger", ascii_json(&trigger)))
+            .json(json!({
+                "status": "error",
+                "message": self.message,
+            }))
+    }
+}
+
+/// `value` as JSON with everything outside ASCII escaped, since header values have to be
+/// ASCII.
+fn ascii_json(value: &serde_json::Value) -> String {
+    value
+        .to_string()
+        .chars()
+        .flat_map(|c| match c.is_ascii() {
+            true => vec![c.to_string()],
+            false => c
+                .encode_utf16(&mut [0; 2])
+                .iter()
+                .map(|unit| format!("\\u{:04x}", unit))
+                .collect(),
+        })
+        .collect()
+}
+
+type Result<T> = std::result::Result<T, ApiError>;
+
 async fn render_template(
     tera: web::Data<Tera>,
     template_name: &str,
     context: &Context,
 ) -> Result<HttpResponse> {
-    let rendered = tera
-        .render(&format!("{}.html", template_name), context)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    let rendered = tera.render(&format!("{}.html", template_name), context)?;
 
     Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
 }
 
+async fn render_instance(
+    docker: &Docker,
+    tera: web::Data<Tera>,
+    instance_uuid: &str,
+) -> Result<HttpResponse> {
+    let instance = Instance::inspect(docker, instance_uuid).await?;
+    let mut context = Context::new();
+    context.insert("instance", &instance);
+    render_template(tera, "instance", &context).await
+}
+
+async fn render_instances(
+    docker: &Docker,
+    tera: web::Data<Tera>,
+    namespace: &str,
+) -> Result<HttpResponse> {
+    let instances = Instance::inspect_all(docker, namespace).await?;
+    let mut context = Context::new();
+    context.insert("instances", &instances);
+    render_template(tera, "instances", &context).await
+}
+
+async fn namespace() -> Result<String> {
+    Ok(config::read_or_create_config().await?.namespace)
+}
+
 #[get("/list_all_instances")]
-pub(crate) async fn inspect_all(tera: web::Data<Tera>) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
+pub(crate) async fn inspect_all(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse> {
+    let namespace = namespace().await?;
+    render_instances(&docker, tera, &namespace).await
+}
 
-    match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(instances) => {
-            let mut context = Context::new();
-            context.insert("instances", &instances);
-            render_template(tera, "instances", &context).await
-        }
-        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-    }
+#[get("/instance/{id}")]
+pub(crate) async fn inspect_instance(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let instance_uuid = path.into_inner();
+    render_instance(&docker, tera, &instance_uuid).await
 }
 
 #[post("/create_instance")]
 pub(crate) async fn create_instance(
+    docker: web::Data<Docker>,
     tera: web::Data<Tera>,
     body: Option<web::Bytes>,
 ) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
     let uuid = Uuid::new_v4().to_string();
-    let env_vars = body
-        .and_then(|b| serde_json::from_slice::<ContainerEnvVars>(&b).ok())
-        .unwrap_or_default();
+    let env_vars = match body.filter(|b| !b.is_empty()) {
+        Some(b) => serde_json::from_slice::<ContainerEnvVars>(&b)
+            .map_err(|e| ApiError::bad_request(format!("Invalid create payload: {}", e)))?,
+        None => ContainerEnvVars::default(),
+    };
 
-    match Instance::new(&docker, &uuid, env_vars).await {
-        Ok(instance) => {
-            let mut context = Context::new();
-            context.insert("instance", &instance);
-            render_template(tera, "instance", &context).await
-        }
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+    let instance = Instance::new(&docker, &uuid, env_vars, false).await?;
+    let mut context = Context::new();
+    context.insert("instance", &instance);
+    render_template(tera, "instance", &context).await
 }
 
 #[delete("/delete_instances")]
-pub(crate) async fn delete_all_instances(tera: web::Data<Tera>) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::delete_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(_) => match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-            Ok(instances) => {
-                let mut context = Context::new();
-                context.insert("instances", &instances);
-                render_template(tera, "instances", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+pub(crate) async fn delete_all_instances(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse> {
+    let namespace = namespace().await?;
+    Instance::delete_all(&docker, &namespace).await?;
+    render_instances(&docker, tera, &namespace).await
 }
 
 #[delete("/delete_instance/{id}")]
-pub(crate) async fn delete_instance(path: web::Path<String>) -> Result<HttpResponse> {
+pub(crate) async fn delete_instance(
+    docker: web::Data<Docker>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
     let instance_uuid = path.into_inner();
-
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::delete(&docker, &instance_uuid, false).await {
-        Ok(_) => Ok(HttpResponse::Ok().finish()),
-        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
-    }
+    Instance::delete(&docker, &instance_uuid, true).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[post("/restart_all_instances")]
-pub(crate) async fn restart_all_instances(tera: web::Data<Tera>) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::restart_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(_) => match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-            Ok(instances) => {
-                let mut context = Context::new();
-                context.insert("instances", &instances);
-                render_template(tera, "instances", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+pub(crate) async fn restart_all_instances(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse> {
+    let namespace = namespace().await?;
+    Instance::restart_all(&docker, &namespace).await?;
+    render_instances(&docker, tera, &namespace).await
 }
 
 #[post("/restart_instance/{id}")]
 pub(crate) async fn restart_instance(
+    docker: web::Data<Docker>,
     tera: web::Data<Tera>,
     path: web::Path<String>,
 ) -> Result<HttpResponse> {
     let instance_uuid = path.into_inner();
-
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::restart(&docker, &instance_uuid).await {
-        Ok(_) => match Instance::inspect(&docker, &instance_uuid).await {
-            Ok(instance) => {
-                let mut context = Context::new();
-                context.insert("instance", &instance);
-                render_template(tera, "instance", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+    Instance::restart(&docker, &instance_uuid).await?;
+    render_instance(&docker, tera, &instance_uuid).await
 }
 
 #[post("/stop_all_instances")]
-pub(crate) async fn stop_all_instances(tera: web::Data<Tera>) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::stop_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(_) => match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-            Ok(instances) => {
-                let mut context = Context::new();
-                context.insert("instances", &instances);
-                render_template(tera, "instances", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+pub(crate) async fn stop_all_instances(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse> {
+    let namespace = namespace().await?;
+    Instance::stop_all(&docker, &namespace).await?;
+    render_instances(&docker, tera, &namespace).await
 }
 
 #[post("/stop_instance/{id}")]
 pub(crate) async fn stop_instance(
+    docker: web::Data<Docker>,
     tera: web::Data<Tera>,
     path: web::Path<String>,
 ) -> Result<HttpResponse> {
     let instance_uuid = path.into_inner();
-
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::stop(&docker, &instance_uuid).await {
-        Ok(_) => match Instance::inspect(&docker, &instance_uuid).await {
-            Ok(instance) => {
-                let mut context = Context::new();
-                context.insert("instance", &instance);
-                render_template(tera, "instance", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+    Instance::stop(&docker, &instance_uuid).await?;
+    render_instance(&docker, tera, &instance_uuid).await
 }
 
 #[post("/start_instance/{id}")]
 pub(crate) async fn start_instance(
+    docker: web::Data<Docker>,
     tera: web::Data<Tera>,
     path: web::Path<String>,
 ) -> Result<HttpResponse> {
     let instance_uuid = path.into_inner();
-
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
-
-    match Instance::start(&docker, &instance_uuid).await {
-        Ok(_) => match Instance::inspect(&docker, &instance_uuid).await {
-            Ok(instance) => {
-                let mut context = Context::new();
-                context.insert("instance", &instance);
-                render_template(tera, "instance", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+    Instance::start(&docker, &instance_uuid).await?;
+    render_instance(&docker, tera, &instance_uuid).await
 }
 
 #[post("/start_all_instances")]
-pub(crate) async fn start_all_instances(tera: web::Data<Tera>) -> Result<HttpResponse> {
-    let docker = Docker::connect_with_defaults().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Failed to connect to Docker: {}", e))
-    })?;
+pub(crate) async fn start_all_instances(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse> {
+    let namespace = namespace().await?;
+    Instance::start_all(&docker, &namespace).await?;
+    render_instances(&docker, tera, &namespace).await
+}
 
-    match Instance::start_all(&docker, wpdev_core::NETWORK_NAME).await {
-        Ok(_) => match Instance::inspect_all(&docker, wpdev_core::NETWORK_NAME).await {
-            Ok(instances) => {
-                let mut context = Context::new();
-                context.insert("instances", &instances);
-                render_template(tera, "instances", &context).await
-            }
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": e.to_string()
-                })));
-            }
-        },
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": e.to_string()
-            })));
-        }
-    }
+/// Toggles whether an instance is pinned and re-renders the list, since pinned instances are
+/// listed first.
+#[post("/pin_instance/{id}")]
+pub(crate) async fn pin_instance(
+    docker: web::Data<Docker>,
+    tera: web::Data<Tera>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let instance_uuid = path.into_inner();
+    let namespace = namespace().await?;
+
+    let pinned = Instance::list(&docker, &instance_uuid).await?.is_pinned();
+    Instance::set_pinned(&instance_uuid, !pinned).await?;
+    render_instances(&docker, tera, &namespace).await
 }
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(inspect_all)
+        .service(inspect_instance)
         .service(create_instance)
         .service(delete_all_instances)
         .service(delete_instance)
@@ -311,5 +287,6 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(stop_all_instances)
         .service(stop_instance)
         .service(start_instance)
-        .service(start_all_instances);
+        .service(start_all_instances)
+        .service(pin_instance);
 }
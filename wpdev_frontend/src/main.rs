@@ -5,7 +5,7 @@ use anyhow::Result;
 use rust_embed::RustEmbed;
 use serde::Serialize;
 use tera::{Context, Tera};
-use wpdev_core::config;
+use wpdev_core::{config, utils};
 
 mod handlers;
 use env_logger;
@@ -78,11 +78,16 @@ fn create_tera_instance() -> Result<Tera, actix_web::Error> {
 #[actix_web::main]
 async fn main() -> Result<()> {
     let config = config::read_or_create_config().await?;
+    utils::ensure_port_available(config.web_app_ip, config.web_app_port, "web_app_port")?;
     let host_bind = format!("{}:{}", config.web_app_ip, config.web_app_port);
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(config.log_level))
         .init();
     let cors_allowed_origin = format!("http://{}", host_bind);
     let tera = create_tera_instance().expect("Failed to create Tera instance");
+    // One client is shared by every worker, bollard pools the connections behind it.
+    let docker = web::Data::new(wpdev_core::docker::connect_docker_host(
+        config.docker_host.as_deref(),
+    )?);
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin(&cors_allowed_origin)
@@ -95,6 +100,7 @@ async fn main() -> Result<()> {
 
         App::new()
             .app_data(web::Data::new(tera.clone()))
+            .app_data(docker.clone())
             .wrap(cors)
             .wrap(Logger::default())
             .service(web::resource("/").route(web::get().to(index)))